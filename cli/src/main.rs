@@ -74,15 +74,22 @@ fn main() {
         thread::sleep(Duration::from_millis(1000));
 
         let (price_pda, bump) = Pubkey::find_program_address(&[b"price"], &program_id);
+        let (config_pda, _) = Pubkey::find_program_address(&[b"config"], &program_id);
         let rs = program_client
             .request()
-            .accounts(nexfin_program::accounts::LoadPrice { price: price })
+            .accounts(nexfin_program::accounts::LoadPrice {
+                authority: payer.pubkey(),
+                price: price,
+                secondary_price: price_pda,
+                config: config_pda,
+                system_program: system_program::ID,
+            })
             .args(nexfin_program::instruction::LoadPrice { bump })
             .send();
         match rs {
             Ok(s) => {
                 println!("TX: {}", s);
-                let acc = program_client.account::<nexfin_program::state::Price>(price);
+                let acc = program_client.account::<nexfin_program::state::Price>(price_pda);
                 println!("{:?}", acc);
                 println!("===================");
             }
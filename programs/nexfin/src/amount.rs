@@ -0,0 +1,65 @@
+use crate::error::NexfinError;
+use anchor_lang::prelude::*;
+use anchor_spl::token::Mint;
+
+/// Decimals of the per deposit stable coin rewards, which are kept in hundredths of a coin
+pub const REWARD_DECIMALS: u8 = 2;
+
+/// Stable coin amount in the base units of the mint
+///
+/// The troves and deposits record whole coins, the token program works in base units,
+/// so the conversion always goes through the decimals of the mint account.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StableAmount {
+    base_units: u64,
+    decimals: u8,
+}
+
+impl StableAmount {
+    /// Amount of whole `coins` for a mint with `decimals`
+    pub fn from_coins(coins: u64, decimals: u8) -> Result<Self, ProgramError> {
+        Self::from_scaled(coins, 0, decimals)
+    }
+
+    /// Amount of whole `coins` of the `mint`
+    pub fn from_mint(coins: u64, mint: &Mint) -> Result<Self, ProgramError> {
+        Self::from_coins(coins, mint.decimals)
+    }
+
+    /// Amount given with `amount_decimals`, rescaled to a mint with `decimals`
+    ///
+    /// Scaling down truncates the remainder, the protocol never pays out more than recorded.
+    pub fn from_scaled(amount: u64, amount_decimals: u8, decimals: u8) -> Result<Self, ProgramError> {
+        let base_units = if decimals >= amount_decimals {
+            amount.checked_mul(pow10(decimals - amount_decimals)?).ok_or(NexfinError::MathOverflow)?
+        } else {
+            amount.checked_div(pow10(amount_decimals - decimals)?).ok_or(NexfinError::MathOverflow)?
+        };
+
+        Ok(StableAmount { base_units, decimals })
+    }
+
+    /// Amount already in base units of a mint with `decimals`
+    pub fn from_base_units(base_units: u64, decimals: u8) -> Self {
+        StableAmount { base_units, decimals }
+    }
+
+    /// Amount to pass to the token program
+    pub fn base_units(&self) -> u64 {
+        self.base_units
+    }
+
+    /// Whole coins, truncating the fraction
+    pub fn coins(&self) -> Result<u64, ProgramError> {
+        Ok(self.base_units.checked_div(pow10(self.decimals)?).ok_or(NexfinError::MathOverflow)?)
+    }
+
+    pub fn decimals(&self) -> u8 {
+        self.decimals
+    }
+}
+
+/// 10^exp, erroring instead of wrapping for mints with too many decimals
+fn pow10(exp: u8) -> Result<u64, ProgramError> {
+    Ok(10u64.checked_pow(exp as u32).ok_or(NexfinError::MathOverflow)?)
+}
@@ -196,6 +196,40 @@ pub enum NexfinError {
     /// Not enough liquidity after flash loan
     #[error("Not enough liquidity after flash loan")]
     NotEnoughLiquidityAfterFlashLoan,
+
+    // 45
+    /// The sorted trove list is full
+    #[error("Sorted trove list is full")]
+    TroveListFull,
+    /// Troves must be passed in the sorted order
+    #[error("Troves are not passed in the sorted order")]
+    InvalidTroveOrder,
+    /// The primary and secondary oracle prices deviate
+    #[error("Oracle prices deviate beyond the configured bound")]
+    OracleDeviation,
+    /// The trove is still in the liquidation grace period
+    #[error("Liquidation grace period has not elapsed")]
+    LiquidationGracePeriod,
+    /// The operation lowers the total collateral ratio in recovery mode
+    #[error("Operation lowers the total collateral ratio in recovery mode")]
+    RecoveryMode,
+
+    // 50
+    /// The instruction is paused by the admin or the guardian
+    #[error("Protocol is paused")]
+    ProtocolPaused,
+    /// Only the guardian or the admin can pause the protocol
+    #[error("Only For Guardian")]
+    OnlyForGuardian,
+    /// Only the proposed admin can accept the admin role
+    #[error("Only For Pending Admin")]
+    OnlyForPendingAdmin,
+    /// The trove debt would fall under the minimum borrow amount
+    #[error("Trove debt is under the minimum")]
+    DebtBelowMinimum,
+    /// The stability pool tracks no more (epoch, scale) reward sums
+    #[error("Stability pool scale list is full")]
+    PoolScaleListFull,
 }
 
 impl From<NexfinError> for ProgramError {
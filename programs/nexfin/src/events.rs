@@ -0,0 +1,205 @@
+use crate::params::{ProtocolParams, MAX_COLLATERAL_TYPES};
+use crate::{CollateralType, Trove};
+use anchor_lang::prelude::*;
+
+/// A trove was opened with `borrow_amount` stable coins against `lamports_amount`
+#[event]
+pub struct TroveOpened {
+    pub authority: Pubkey,
+    pub lamports_amount: u64,
+    pub borrow_amount: u64,
+    pub amount_to_close: u64,
+    pub depositor_fee: u64,
+    pub team_fee: u64,
+}
+
+/// The collateral or the debt of a trove changed, the amounts are the trove state after the change
+#[event]
+pub struct TroveAdjusted {
+    pub authority: Pubkey,
+    pub lamports_amount: u64,
+    pub collaterals: [u64; MAX_COLLATERAL_TYPES],
+    pub borrow_amount: u64,
+    pub amount_to_close: u64,
+}
+
+impl TroveAdjusted {
+    pub fn new(trove: &Trove) -> Self {
+        TroveAdjusted {
+            authority: trove.authority,
+            lamports_amount: trove.lamports_amount,
+            collaterals: trove.collaterals,
+            borrow_amount: trove.borrow_amount,
+            amount_to_close: trove.amount_to_close,
+        }
+    }
+}
+
+/// A trove repaid its debt and got its SOL back
+#[event]
+pub struct TroveClosed {
+    pub authority: Pubkey,
+    pub repaid_amount: u64,
+    pub returned_lamports: u64,
+}
+
+/// The debt of a trove was offset against the stability pool and its collateral seized,
+/// `collateral_surplus` lamports are left to the owner by a recovery mode liquidation
+#[event]
+pub struct TroveLiquidated {
+    pub authority: Pubkey,
+    pub liquidator: Pubkey,
+    pub debt: u64,
+    pub collateral_ratio: u64,
+    pub seized_lamports: u64,
+    pub liquidator_reward: u64,
+    pub seized_collaterals: [u64; MAX_COLLATERAL_TYPES],
+    pub collateral_surplus: u64,
+    /// Debt left queued in the trove when the pool deposits did not cover all of it
+    pub remaining_debt: u64,
+}
+
+/// The liquidation keeper or the admin queued an undercollateralized trove for liquidation, or
+/// took a queued trove back above the liquidation ratio out of the queue
+#[event]
+pub struct TroveReceived {
+    pub keeper: Pubkey,
+    pub authority: Pubkey,
+    pub collateral_ratio: u64,
+    pub sol_price: u64,
+    pub is_received: bool,
+}
+
+/// Stable coins were deposited into the stability pool, `token_amount` is the deposit after it
+#[event]
+pub struct DepositAdded {
+    pub authority: Pubkey,
+    pub amount: u64,
+    pub token_amount: u64,
+}
+
+/// Stable coins were withdrawn from the stability pool, `token_amount` is the deposit after it
+#[event]
+pub struct DepositWithdrawn {
+    pub authority: Pubkey,
+    pub amount: u64,
+    pub token_amount: u64,
+}
+
+/// A depositor claimed its rewards, SOL in lamports and the tokens in base units of their mint
+#[event]
+pub struct RewardClaimed {
+    pub authority: Pubkey,
+    pub coin_amount: u64,
+    pub token_amount: u64,
+    pub governance_amount: u64,
+    pub collateral_mint: Option<Pubkey>,
+    pub collateral_amount: u64,
+}
+
+/// SOL fee income was swept from the depositors fee account into the reward vault
+#[event]
+pub struct RewardVaultFunded {
+    pub authority: Pubkey,
+    pub lamports: u64,
+}
+
+/// The stable coin fee income of the depositors was distributed over the stability pool
+#[event]
+pub struct FeeIncomeDistributed {
+    pub authority: Pubkey,
+    pub amount: u64,
+}
+
+/// The admin distributed rewards over every deposit of the stability pool
+#[event]
+pub struct DepositRewardAdded {
+    pub authority: Pubkey,
+    pub coin: u64,
+    pub token: u64,
+    pub governance: u64,
+}
+
+/// The governance emission of the stability pool was started or changed
+#[event]
+pub struct GovernanceEmissionSet {
+    pub authority: Pubkey,
+    pub supply_cap: u64,
+    pub issuance_factor: u128,
+}
+
+/// An account written with an older layout was upgraded to `version`
+#[event]
+pub struct AccountMigrated {
+    pub authority: Pubkey,
+    pub account: Pubkey,
+    pub version: u8,
+}
+
+/// Config fields changed by an instruction, with their new values
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub enum ConfigChange {
+    Initialized {
+        mint_account_authority: Pubkey,
+        admin_account_authority: Pubkey,
+    },
+    Config {
+        is_initialized: bool,
+        mint_account_authority: Pubkey,
+    },
+    PendingAdmin {
+        pending_admin: Pubkey,
+    },
+    Admin {
+        admin_account_authority: Pubkey,
+    },
+    Params {
+        params: ProtocolParams,
+    },
+    Oracle {
+        pyth_sol_account: Pubkey,
+        secondary_price_source: Pubkey,
+        max_price_staleness: u64,
+        max_price_confidence: u64,
+        max_price_deviation: u64,
+    },
+    LiquidationKeeper {
+        liquidation_keeper: Pubkey,
+    },
+    Guardian {
+        guardian: Pubkey,
+    },
+    Paused {
+        paused: u8,
+    },
+    CollateralType {
+        index: u8,
+        collateral_type: CollateralType,
+    },
+    Migrated {
+        legacy_config: Pubkey,
+        admin_account_authority: Pubkey,
+    },
+}
+
+/// The config changed, carries the changed fields
+#[event]
+pub struct ConfigChanged {
+    pub authority: Pubkey,
+    pub change: ConfigChange,
+}
+
+/// The admin proposed `pending_admin` as the next admin
+#[event]
+pub struct AdminProposed {
+    pub authority: Pubkey,
+    pub pending_admin: Pubkey,
+}
+
+/// The pending admin accepted, `admin_account` is its admin PDA now held by the config
+#[event]
+pub struct AdminAccepted {
+    pub authority: Pubkey,
+    pub admin_account: Pubkey,
+    pub previous_admin_account: Pubkey,
+}
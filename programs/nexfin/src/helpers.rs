@@ -1,20 +1,32 @@
-use crate::params::{DEPOSIT_FEE, TEAM_FEE, MIN_DEPOSIT_FEE, MIN_TEAM_FEE, TOTAL_FEE, MIN_TOTAL_FEE};
-use crate::{params::GAS_FEE, params::MIN_COLLATERAL};
+use crate::params::{ProtocolParams, LIQUIDATOR_REWARD_DENOMINATOR};
+use crate::params::{BETA, DECIMAL_PRECISION, MINUTE_DECAY_FACTOR, NICR_PRECISION, REDEMPTION_FEE_FLOOR, SECONDS_IN_ONE_MINUTE};
+use crate::params::{FEE_TO_LAMPORTS, SCALE_FACTOR, SECONDS_PER_YEAR};
+use crate::error::NexfinError;
 use anchor_lang::solana_program::native_token::lamports_to_sol;
+use anchor_lang::solana_program::{entrypoint::ProgramResult, msg, program_error::ProgramError};
+use std::convert::TryInto;
 use std::ops::Mul;
 
-pub fn check_min_collateral_include_gas_fee(amount: u64, lamports: u64) -> bool {
-    get_lamport_price(lamports - GAS_FEE) / amount as f64 >= MIN_COLLATERAL
+pub fn check_min_collateral_include_gas_fee(amount: u64, lamports: u64, params: &ProtocolParams) -> Result<bool, ProgramError> {
+    let lamports = lamports.checked_sub(params.gas_fee).ok_or(NexfinError::MathOverflow)?;
+    Ok(get_lamport_price(lamports) / amount as f64 >= params.min_collateral as f64 / 100.0)
 }
 
 pub fn get_trove_sent_amount(
-    amount: u64
-) -> u64 {
-    get_trove_debt_amount(amount)*1000 - get_depositors_fee(amount) - get_team_fee(amount)
+    amount: u64,
+    params: &ProtocolParams
+) -> Result<u64, ProgramError> {
+    get_trove_debt_amount(amount)
+        .checked_mul(1000).ok_or(NexfinError::MathOverflow)?
+        .checked_sub(get_depositors_fee(amount, params)?).ok_or(NexfinError::MathOverflow)?
+        .checked_sub(get_team_fee(amount, params)?).ok_or_else(|| NexfinError::MathOverflow.into())
 }
 
-pub fn add_fees_on_pay(amount: u64) -> u64{
-    amount * 1000 + get_team_fee(amount) + get_depositors_fee(amount)
+pub fn add_fees_on_pay(amount: u64, params: &ProtocolParams) -> Result<u64, ProgramError> {
+    amount
+        .checked_mul(1000).ok_or(NexfinError::MathOverflow)?
+        .checked_add(get_team_fee(amount, params)?).ok_or(NexfinError::MathOverflow)?
+        .checked_add(get_depositors_fee(amount, params)?).ok_or_else(|| NexfinError::MathOverflow.into())
 }
 
 pub fn get_trove_debt_amount(
@@ -26,34 +38,358 @@ pub fn get_trove_debt_amount(
 }
 
 pub fn get_depositors_fee(
-    amount: u64
-) -> u64 {
-    let dep_fee = get_trove_debt_amount(amount) * (DEPOSIT_FEE);
-    if dep_fee < MIN_DEPOSIT_FEE 
-        { MIN_DEPOSIT_FEE }
-     else 
-         {dep_fee }
-    
+    amount: u64,
+    params: &ProtocolParams
+) -> Result<u64, ProgramError> {
+    let dep_fee = get_trove_debt_amount(amount).checked_mul(params.deposit_fee).ok_or(NexfinError::MathOverflow)?;
+    Ok(std::cmp::max(dep_fee, params.min_deposit_fee))
 }
 
 pub fn get_team_fee(
-    amount: u64
-) -> u64 {
-    let team_fee = get_trove_debt_amount(amount) * (TEAM_FEE);
-    if team_fee < MIN_TEAM_FEE 
-       { MIN_TEAM_FEE }
-     else 
-       { team_fee }
-    
-}
-
-pub fn get_total_fee(amount:u64) -> u64{
-    let total_fee = amount * (TOTAL_FEE);
-    if total_fee < (MIN_TOTAL_FEE) 
-       { MIN_TOTAL_FEE }
-     else 
-       { total_fee }
-    
+    amount: u64,
+    params: &ProtocolParams
+) -> Result<u64, ProgramError> {
+    let team_fee = get_trove_debt_amount(amount).checked_mul(params.team_fee).ok_or(NexfinError::MathOverflow)?;
+    Ok(std::cmp::max(team_fee, params.min_team_fee))
+}
+
+/// Depositor and team fee in basis points, the floor of the borrowing fee
+pub fn get_fee_floor(params: &ProtocolParams) -> Result<u64, ProgramError> {
+    params.deposit_fee.checked_add(params.team_fee).ok_or_else(|| NexfinError::MathOverflow.into())
+}
+
+/// Borrowing fee rate in basis points, the depositor and team fee floor raised by the
+/// decayed base rate up to `max_borrowing_fee`
+pub fn get_borrowing_fee_rate(base_rate: u64, params: &ProtocolParams) -> Result<u64, ProgramError> {
+    let fee_floor = get_fee_floor(params)?;
+    let base_rate_bps = (base_rate as u128 * 10_000 / DECIMAL_PRECISION) as u64;
+
+    Ok(std::cmp::min(fee_floor.saturating_add(base_rate_bps), params.max_borrowing_fee))
+}
+
+/// Depositor and team fees charged on `amount` at the current base rate,
+/// split in the proportion of the depositor and team fee params
+pub fn get_borrowing_fees(amount: u64, base_rate: u64, params: &ProtocolParams) -> Result<(u64, u64), ProgramError> {
+    let fee_floor = get_fee_floor(params)?;
+    if fee_floor == 0 {
+        return Ok((params.min_deposit_fee, params.min_team_fee));
+    }
+
+    let total_fee = get_trove_debt_amount(amount)
+        .checked_mul(get_borrowing_fee_rate(base_rate, params)?).ok_or(NexfinError::MathOverflow)?;
+    let dep_fee = (total_fee as u128 * params.deposit_fee as u128 / fee_floor as u128) as u64;
+    let team_fee = total_fee - dep_fee;
+
+    Ok((
+        std::cmp::max(dep_fee, params.min_deposit_fee),
+        std::cmp::max(team_fee, params.min_team_fee),
+    ))
+}
+
+/// Lamports worth `fee` in 1/10000 stable coin, `sol_price` is the pyth aggregate price with an
+/// exponent of -8
+pub fn get_fee_in_lamports(fee: u64, sol_price: u128) -> Result<u64, ProgramError> {
+    let lamports = (fee as u128)
+        .checked_mul(FEE_TO_LAMPORTS).ok_or(NexfinError::MathOverflow)?
+        .checked_div(sol_price).ok_or(NexfinError::MathOverflow)?;
+
+    lamports.try_into().map_err(|_| NexfinError::MathOverflow.into())
+}
+
+/// Flash mint fee in whole stable coins on `amount` coins, rounded up to the next coin
+pub fn get_flash_mint_fee(amount: u64, params: &ProtocolParams) -> Result<u64, ProgramError> {
+    let fee = (amount as u128)
+        .checked_mul(params.flash_mint_fee as u128).ok_or(NexfinError::MathOverflow)?
+        .checked_add(9_999).ok_or(NexfinError::MathOverflow)?
+        / 10_000;
+
+    fee.try_into().map_err(|_| NexfinError::MathOverflow.into())
+}
+
+pub fn get_total_fee(amount:u64, params: &ProtocolParams) -> Result<u64, ProgramError> {
+    let total_fee = amount.checked_mul(get_fee_floor(params)?).ok_or(NexfinError::MathOverflow)?;
+    let min_total_fee = params.min_deposit_fee.checked_add(params.min_team_fee).ok_or(NexfinError::MathOverflow)?;
+
+    Ok(std::cmp::max(total_fee, min_total_fee))
+}
+
+/// Collateral ratio in percent for `lamports` of collateral against `debt` whole stable coins.
+/// `sol_price` is the pyth aggregate price with an exponent of -8.
+pub fn get_collateral_ratio(lamports: u64, debt: u64, sol_price: u128) -> Result<u64, ProgramError> {
+    if debt == 0 {
+        return Ok(u64::MAX);
+    }
+
+    let collateral_price = sol_price.checked_mul(lamports as u128).ok_or(NexfinError::MathOverflow)?;
+    let collateral_ratio = collateral_price
+        .checked_mul(100).ok_or(NexfinError::MathOverflow)?
+        .checked_div(debt as u128).ok_or(NexfinError::MathOverflow)?
+        .checked_div(1_000_000_000).ok_or(NexfinError::MathOverflow)?
+        .checked_div(100_000_000).ok_or(NexfinError::MathOverflow)?;
+
+    Ok(collateral_ratio.try_into().unwrap_or(u64::MAX))
+}
+
+/// Check that a trove holding `lamports` against `debt` stays at or above `min_ratio`
+pub fn check_collateral_ratio(lamports: u64, debt: u64, sol_price: u128, min_ratio: u64) -> ProgramResult {
+    let collateral_ratio = get_collateral_ratio(lamports, debt, sol_price)?;
+    msg!("Collateral ratio after the update is {}", collateral_ratio);
+
+    if collateral_ratio < min_ratio {
+        return Err(NexfinError::InvalidCollateral.into());
+    }
+
+    Ok(())
+}
+
+/// Check that a trove either has no debt or at least the minimum borrow amount, dust troves
+/// would fill the sorted trove list
+pub fn check_min_debt(debt: u64, params: &ProtocolParams) -> ProgramResult {
+    if debt > 0 && debt < params.min_borrow_amount {
+        msg!("Trove debt {} is under the minimum {}", debt, params.min_borrow_amount);
+        return Err(NexfinError::DebtBelowMinimum.into());
+    }
+
+    Ok(())
+}
+
+/// Lamports paid to the liquidator out of the seized collateral
+pub fn get_liquidator_reward(lamports: u64, params: &ProtocolParams) -> Result<u64, ProgramError> {
+    let reward = (lamports as u128)
+        .checked_mul(params.liquidator_reward as u128).ok_or(NexfinError::MathOverflow)?
+        .checked_div(LIQUIDATOR_REWARD_DENOMINATOR as u128).ok_or(NexfinError::MathOverflow)?;
+
+    Ok(reward as u64)
+}
+
+/// Pro rata share of `amount` for a deposit of `share` out of `total`
+pub fn get_pro_rata_share(amount: u64, share: u64, total: u64) -> Result<u64, ProgramError> {
+    let part = (amount as u128)
+        .checked_mul(share as u128).ok_or(NexfinError::MathOverflow)?
+        .checked_div(total as u128).ok_or(NexfinError::MathOverflow)?;
+
+    Ok(part as u64)
+}
+
+/// Nominal collateral ratio, independent of the SOL price, used to sort the troves
+pub fn get_nominal_collateral_ratio(lamports: u64, debt: u64) -> u128 {
+    if debt == 0 {
+        return u128::MAX;
+    }
+
+    (lamports as u128)
+        .checked_mul(NICR_PRECISION)
+        .map(|nicr| nicr / debt as u128)
+        .unwrap_or(u128::MAX)
+}
+
+/// Lamports worth `debt` whole stable coins at face value
+pub fn get_lamports_for_debt(debt: u64, sol_price: u128) -> Result<u64, ProgramError> {
+    let lamports = (debt as u128)
+        .checked_mul(1_000_000_000).ok_or(NexfinError::MathOverflow)?
+        .checked_mul(100_000_000).ok_or(NexfinError::MathOverflow)?
+        .checked_div(sol_price).ok_or(NexfinError::MathOverflow)?;
+
+    lamports.try_into().map_err(|_| NexfinError::MathOverflow.into())
+}
+
+/// Multiply two decimals with `DECIMAL_PRECISION`, rounding half up
+pub fn dec_mul(x: u128, y: u128) -> Result<u128, ProgramError> {
+    let product = x.checked_mul(y).ok_or(NexfinError::MathOverflow)?;
+
+    Ok(product
+        .checked_add(DECIMAL_PRECISION / 2).ok_or(NexfinError::MathOverflow)?
+        / DECIMAL_PRECISION)
+}
+
+/// `base` to the power of `minutes` using exponentiation by squaring
+pub fn dec_pow(base: u128, minutes: u64) -> Result<u128, ProgramError> {
+    // cap to avoid overflow, 1000 years in minutes
+    let mut n = std::cmp::min(minutes, 525_600_000);
+    if n == 0 {
+        return Ok(DECIMAL_PRECISION);
+    }
+
+    let mut x = base;
+    let mut y = DECIMAL_PRECISION;
+    while n > 1 {
+        if n % 2 != 0 {
+            y = dec_mul(x, y)?;
+        }
+        x = dec_mul(x, x)?;
+        n /= 2;
+    }
+
+    dec_mul(x, y)
+}
+
+/// Base rate decayed by the minutes elapsed since the last fee operation
+pub fn get_decayed_base_rate(base_rate: u64, last_fee_operation_time: i64, now: i64) -> Result<u64, ProgramError> {
+    let minutes = now.saturating_sub(last_fee_operation_time).max(0) / SECONDS_IN_ONE_MINUTE;
+    let decay_factor = dec_pow(MINUTE_DECAY_FACTOR, minutes as u64)?;
+
+    Ok(dec_mul(base_rate as u128, decay_factor)? as u64)
+}
+
+/// Base rate after minting or redeeming `amount` out of the `total_supply`
+pub fn get_increased_base_rate(decayed_base_rate: u64, amount: u64, total_supply: u64) -> Result<u64, ProgramError> {
+    let amount_fraction = (amount as u128)
+        .checked_mul(DECIMAL_PRECISION).ok_or(NexfinError::MathOverflow)?
+        .checked_div(total_supply as u128).ok_or(NexfinError::MathOverflow)?;
+
+    let base_rate = (decayed_base_rate as u128)
+        .checked_add(amount_fraction / BETA as u128).ok_or(NexfinError::MathOverflow)?;
+
+    Ok(std::cmp::min(base_rate, DECIMAL_PRECISION) as u64)
+}
+
+/// Redemption fee in lamports for `lamports` drawn from the troves
+pub fn get_redemption_fee(base_rate: u64, lamports: u64) -> Result<u64, ProgramError> {
+    let fee_rate = std::cmp::min(
+        (REDEMPTION_FEE_FLOOR as u128).checked_add(base_rate as u128).ok_or(NexfinError::MathOverflow)?,
+        DECIMAL_PRECISION,
+    );
+
+    let fee = (lamports as u128)
+        .checked_mul(fee_rate).ok_or(NexfinError::MathOverflow)?
+        / DECIMAL_PRECISION;
+
+    Ok(fee as u64)
+}
+
+/// Cumulative interest index after `elapsed` seconds at the yearly `interest_rate` in basis points
+pub fn get_accrued_interest_index(index: u128, interest_rate: u64, elapsed: i64) -> Result<u128, ProgramError> {
+    if elapsed <= 0 || interest_rate == 0 {
+        return Ok(index);
+    }
+
+    let interest = index
+        .checked_mul(interest_rate as u128).ok_or(NexfinError::MathOverflow)?
+        .checked_mul(elapsed as u128).ok_or(NexfinError::MathOverflow)?
+        .checked_div(10_000 * SECONDS_PER_YEAR as u128).ok_or(NexfinError::MathOverflow)?;
+
+    Ok(index.checked_add(interest).ok_or(NexfinError::MathOverflow)?)
+}
+
+/// Interest accrued on `debt` since the trove took its `snapshot` of the index
+pub fn get_accrued_interest(debt: u64, index: u128, snapshot: u128) -> Result<u64, ProgramError> {
+    let debt_with_interest = (debt as u128)
+        .checked_mul(index).ok_or(NexfinError::MathOverflow)?
+        .checked_div(snapshot).ok_or(NexfinError::MathOverflow)?;
+
+    let interest = debt_with_interest.saturating_sub(debt as u128);
+    interest.try_into().map_err(|_| NexfinError::MathOverflow.into())
+}
+
+/// Value in lamports at the SOL `sol_price` of a collateral token `amount` priced at `price` * 10^`expo`
+pub fn get_collateral_lamports(amount: u64, decimals: u8, price: u128, expo: i32, sol_price: u128) -> Result<u64, ProgramError> {
+    // lamports = amount / 10^decimals * price * 10^expo * 10^9 / (sol_price * 10^-8)
+    let exponent = 17 + expo - decimals as i32;
+    let value = (amount as u128).checked_mul(price).ok_or(NexfinError::MathOverflow)?;
+
+    let value = if exponent >= 0 {
+        value.checked_mul(10u128.checked_pow(exponent as u32).ok_or(NexfinError::MathOverflow)?).ok_or(NexfinError::MathOverflow)?
+    } else {
+        value / 10u128.checked_pow(-exponent as u32).ok_or(NexfinError::MathOverflow)?
+    };
+
+    let lamports = value.checked_div(sol_price).ok_or(NexfinError::MathOverflow)?;
+    lamports.try_into().map_err(|_| NexfinError::MathOverflow.into())
+}
+
+/// Collateral lamports counted against the SOL `ratio` in percent for a collateral type
+/// weighted by `weight` in basis points, its max LTV or liquidation threshold
+pub fn get_weighted_collateral_lamports(lamports: u64, weight: u64, ratio: u64) -> Result<u64, ProgramError> {
+    let weighted = (lamports as u128)
+        .checked_mul(weight as u128).ok_or(NexfinError::MathOverflow)?
+        .checked_mul(ratio as u128).ok_or(NexfinError::MathOverflow)?
+        / 1_000_000;
+
+    weighted.try_into().map_err(|_| NexfinError::MathOverflow.into())
+}
+
+/// Governance tokens issued `minutes` after the emission started out of the `supply_cap`
+pub fn get_cumulative_issuance(supply_cap: u64, issuance_factor: u128, minutes: u64) -> Result<u64, ProgramError> {
+    let issued_fraction = DECIMAL_PRECISION.saturating_sub(dec_pow(issuance_factor, minutes)?);
+
+    let issuance = (supply_cap as u128)
+        .checked_mul(issued_fraction).ok_or(NexfinError::MathOverflow)?
+        / DECIMAL_PRECISION;
+
+    Ok(issuance as u64)
+}
+
+/// Loss of the stability pool per deposited coin when `debt` is offset, the division
+/// rounds up so the deposits never add up to more than the pool holds
+///
+/// Returns the loss per unit scaled by 1e18 and the error to feed into the next offset
+pub fn get_loss_per_unit_staked(debt: u64, total_deposits: u64, last_error: u128) -> Result<(u128, u128), ProgramError> {
+    if debt == total_deposits {
+        return Ok((DECIMAL_PRECISION, 0));
+    }
+
+    let loss_numerator = (debt as u128)
+        .checked_mul(DECIMAL_PRECISION).ok_or(NexfinError::MathOverflow)?
+        .checked_sub(last_error).ok_or(NexfinError::MathOverflow)?;
+    let loss_per_unit = loss_numerator
+        .checked_div(total_deposits as u128).ok_or(NexfinError::MathOverflow)?
+        .checked_add(1).ok_or(NexfinError::MathOverflow)?;
+    let error = loss_per_unit
+        .checked_mul(total_deposits as u128).ok_or(NexfinError::MathOverflow)?
+        .checked_sub(loss_numerator).ok_or(NexfinError::MathOverflow)?;
+
+    Ok((loss_per_unit, error))
+}
+
+/// Gain of the stability pool per deposited coin when `gain` is distributed
+///
+/// Returns the gain per unit scaled by 1e18 and the remainder to carry into the next distribution
+pub fn get_gain_per_unit_staked(gain: u64, total_deposits: u64, last_error: u128) -> Result<(u128, u128), ProgramError> {
+    let gain_numerator = (gain as u128)
+        .checked_mul(DECIMAL_PRECISION).ok_or(NexfinError::MathOverflow)?
+        .checked_add(last_error).ok_or(NexfinError::MathOverflow)?;
+    let gain_per_unit = gain_numerator.checked_div(total_deposits as u128).ok_or(NexfinError::MathOverflow)?;
+    let error = gain_numerator - gain_per_unit * total_deposits as u128;
+
+    Ok((gain_per_unit, error))
+}
+
+/// Deposit left after the liquidations since the depositor took the `snapshot_p` of the pool product
+pub fn get_compounded_deposit(initial: u64, snapshot_p: u128, p: u128, scale_diff: u64) -> Result<u64, ProgramError> {
+    let compounded = match scale_diff {
+        0 => (initial as u128)
+            .checked_mul(p).ok_or(NexfinError::MathOverflow)?
+            .checked_div(snapshot_p).ok_or(NexfinError::MathOverflow)?,
+        1 => (initial as u128)
+            .checked_mul(p).ok_or(NexfinError::MathOverflow)?
+            .checked_div(snapshot_p).ok_or(NexfinError::MathOverflow)?
+            / SCALE_FACTOR,
+        // the deposit lost more than 1e9 of its value
+        _ => 0,
+    };
+
+    // a remainder below a billionth of the initial deposit is rounding noise
+    if compounded < initial as u128 / SCALE_FACTOR {
+        return Ok(0);
+    }
+
+    compounded.try_into().map_err(|_| NexfinError::MathOverflow.into())
+}
+
+/// Gain of a deposit out of the sum accrued at its snapshot scale and the one following it
+pub fn get_depositor_gain(initial: u64, snapshot_p: u128, snapshot_sum: u128, first_sum: u128, second_sum: u128) -> Result<u64, ProgramError> {
+    if initial == 0 || snapshot_p == 0 {
+        return Ok(0);
+    }
+
+    let first_portion = first_sum.saturating_sub(snapshot_sum);
+    let second_portion = second_sum / SCALE_FACTOR;
+
+    let gain = (initial as u128)
+        .checked_mul(first_portion.checked_add(second_portion).ok_or(NexfinError::MathOverflow)?).ok_or(NexfinError::MathOverflow)?
+        .checked_div(snapshot_p).ok_or(NexfinError::MathOverflow)?;
+
+    gain.try_into().map_err(|_| NexfinError::MathOverflow.into())
 }
 
 fn get_lamport_price(lamports: u64) -> f64 {
@@ -1,34 +1,47 @@
 use anchor_lang::prelude::*;
 // use std::{cell::{Ref, RefMut},mem::size_of};
-use std::mem::size_of;
+pub mod amount;
 pub mod error;
+pub mod events;
 pub mod helpers;
+pub mod migration;
+pub mod oracle;
 pub mod params;
 pub mod pc;
-use pc::Price;
 pub mod state;
-use crate::helpers::{get_depositors_fee, get_team_fee};
+use crate::helpers::{check_collateral_ratio, check_min_debt, get_collateral_ratio, get_liquidator_reward, get_pro_rata_share};
+use crate::helpers::{get_decayed_base_rate, get_lamports_for_debt, get_nominal_collateral_ratio, get_increased_base_rate, get_redemption_fee};
+use crate::helpers::{get_borrowing_fee_rate, get_borrowing_fees, get_fee_floor, get_fee_in_lamports, get_flash_mint_fee};
+use crate::params::{ProtocolParams, ACCOUNT_VERSION, DECIMAL_PRECISION, GOVERNANCE_ISSUANCE_FACTOR, MAX_COLLATERAL_TYPES, MAX_POOL_SCALES, MAX_SORTED_TROVES, SCALE_FACTOR, SECONDS_IN_ONE_MINUTE};
+use crate::params::{PAUSE_ALL, PAUSE_BORROWING, PAUSE_DEPOSITS, PAUSE_LIQUIDATIONS, PAUSE_REDEMPTIONS, PAUSE_WITHDRAWALS};
+use crate::helpers::{get_accrued_interest, get_accrued_interest_index};
+use crate::helpers::{get_compounded_deposit, get_cumulative_issuance, get_depositor_gain, get_gain_per_unit_staked, get_loss_per_unit_staked};
+use crate::helpers::{get_collateral_lamports, get_weighted_collateral_lamports};
+use anchor_lang::solana_program::sysvar::Sysvar as SolanaSysvar;
 // use crate::params::SYSTEM_ACCOUNT_ADDRESS;
 // use std::ops::{Add, Sub};
 
 // use bytemuck::{from_bytes, from_bytes_mut, Pod, Zeroable};
 
+use crate::amount::{StableAmount, REWARD_DECIMALS};
 use crate::error::NexfinError;
+use crate::events::{AccountMigrated, AdminAccepted, AdminProposed, ConfigChange, ConfigChanged, DepositAdded, DepositRewardAdded, DepositWithdrawn, FeeIncomeDistributed};
+use crate::events::{GovernanceEmissionSet, RewardClaimed, RewardVaultFunded, TroveAdjusted, TroveClosed, TroveLiquidated, TroveOpened, TroveReceived};
 // use anchor_lang::AccountsClose;
-use anchor_spl::token::{self, Burn, Mint, MintTo, TokenAccount};
-use anchor_lang::solana_program::{program::invoke, system_instruction};
+use anchor_spl::token::{self, Burn, Mint, MintTo, TokenAccount, Transfer};
+use anchor_lang::solana_program::{instruction::{AccountMeta, Instruction}, program::{invoke, invoke_signed}, system_instruction};
+use anchor_lang::solana_program::{bpf_loader_upgradeable::{self, UpgradeableLoaderState}, program_utils::limited_deserialize};
 
 use std::convert::TryInto;
 
 // for pyth price for borrow
-use pyth_client;
-
-pub const COLLATERAL_RATIO:i32 = 110;
+use crate::oracle::{check_sol_price_expo, get_collateral_price, get_sol_price, load_pyth_price};
+use crate::migration::{load_versioned, Versioned};
+use crate::state::{layout_len, BOOL_LEN, I64_LEN, PUBKEY_LEN, U128_LEN, U64_LEN, U8_LEN, VEC_PREFIX_LEN};
 
 declare_id!("HPwvr8B9KtM3CZwQg7V8pevfgsZfZBLiR3gL1HcEsGiD");
 
 // TODO: Initialize the reserve(TVL) for the deposit
-// TODO: Need to add versioning to accounts 
 #[program]
 pub mod nexfin {
     use super::*;
@@ -40,43 +53,55 @@ pub mod nexfin {
     /// 2. `[]` The rent sysvar
     pub fn borrow(ctx: Context<Borrow>, borrow_amount: u64, lamports: u64, trove_account_bump: u8, sol_account_bump:u8, mint_account_bump: u8, fee_account_bump:u8, team_fee_account_bump:u8) -> ProgramResult {
         msg!("Instruction Borrow");
+        ctx.accounts.config.check_not_paused(PAUSE_BORROWING)?;
+
         let trove = &mut ctx.accounts.trove_account;
         let sol_trove = &mut ctx.accounts.sol_trove;
 
+        // an open trove is changed through the trove instructions, only a new or liquidated trove is opened
+        if trove.is_initialized && !trove.is_liquidated {
+            return Err(NexfinError::AlreadyInitialized.into());
+        }
+
+        // the token collateral of a liquidated trove was seized, the trove is opened with SOL only
+        if trove.collaterals.iter().any(|amount| *amount > 0) {
+            return Err(NexfinError::InvalidCollateral.into());
+        }
+
         let fee = &mut ctx.accounts.fee_account;
         let team_fee = &mut ctx.accounts.team_fee_account;
 
         let borrower = &ctx.accounts.authority;
+        let params = ctx.accounts.config.params;
 
         // check if the user has sufficent amount in the wallet
         if **ctx.accounts.authority.lamports.borrow() < lamports {
             return Err(NexfinError::InsufficientLiquidity.into());
         }
 
-        // check if the amount is not less than 100 token value
-        if borrow_amount < 100 {
+        // check if the amount is not less than the minimum borrow amount
+        if borrow_amount < params.min_borrow_amount {
             return Err(NexfinError::InvalidAmount.into());
         }
 
-        // check for SOL price
-        let pyth_price_info = &ctx.accounts.pyth_sol_account;
-        let pyth_price_data = &pyth_price_info.try_borrow_data()?;
-        let pyth_price = pyth_client::cast::<pyth_client::Price>(pyth_price_data);
+        check_debt_ceiling(&ctx.accounts.stable_coin, borrow_amount, &params)?;
 
-        let sol_price = pyth_price.agg.price as u128;
+        // check for SOL price
+        let sol_price = get_sol_price(&ctx.accounts.pyth_sol_account, &ctx.accounts.secondary_price, &ctx.accounts.config, &ctx.accounts.clock)?.for_borrow()?;
         
-        // check the collateral ratio (110%)
+        // check the collateral ratio
         let collateral_price = sol_price.checked_mul(lamports as u128).ok_or(NexfinError::MathOverflow)?; 
 
         let collateral_ratio = collateral_price.checked_mul(100).ok_or(NexfinError::MathOverflow)?.checked_div(borrow_amount as u128).ok_or(NexfinError::MathOverflow)?.checked_div(1_000_000_000).ok_or(NexfinError::MathOverflow)?.checked_div(100_000_000).ok_or(NexfinError::MathOverflow)?;
 
-        // calculate the fee in sol
-        let dep_fee_in_gens = get_depositors_fee(borrow_amount);
-        let team_fee_in_gens = get_team_fee(borrow_amount);
-        let dep_fee_in_sol = dep_fee_in_gens.checked_mul(10_000_000_000_000).ok_or(NexfinError::MathOverflow)?.checked_div(sol_price.try_into().unwrap()).ok_or(NexfinError::MathOverflow)?;
-        let team_fee_in_sol = team_fee_in_gens.checked_mul(10_000_000_000_000).ok_or(NexfinError::MathOverflow)?.checked_div(sol_price.try_into().unwrap()).ok_or(NexfinError::MathOverflow)?;
+        // calculate the fee in sol at the decayed base rate
+        let base_rate = ctx.accounts.trove_manager.decay_base_rate(ctx.accounts.clock.unix_timestamp)?;
+        let (dep_fee_in_gens, team_fee_in_gens) = get_borrowing_fees(borrow_amount, base_rate, &params)?;
+        msg!("Borrowing fee rate is {} basis points", get_borrowing_fee_rate(base_rate, &params)?);
+        let dep_fee_in_sol = get_fee_in_lamports(dep_fee_in_gens, sol_price)?;
+        let team_fee_in_sol = get_fee_in_lamports(team_fee_in_gens, sol_price)?;
 
-        if collateral_ratio > COLLATERAL_RATIO.try_into().unwrap() {
+        if collateral_ratio > params.collateral_ratio as u128 {
             invoke(
                 &system_instruction::transfer(
                     ctx.accounts.authority.key,
@@ -117,10 +142,12 @@ pub mod nexfin {
             )?;
 
             // adding fee info
+            fee.version = ACCOUNT_VERSION;
             fee.bump = fee_account_bump;
             fee.is_initialized = true;
             fee.sol_amount = fee.sol_amount.checked_add(dep_fee_in_sol).ok_or(NexfinError::MathOverflow)?;
 
+            team_fee.version = ACCOUNT_VERSION;
             team_fee.bump = team_fee_account_bump;
             team_fee.is_initialized = true;
             team_fee.sol_amount = team_fee.sol_amount.checked_add(team_fee_in_sol).ok_or(NexfinError::MathOverflow)?;
@@ -140,24 +167,45 @@ pub mod nexfin {
             let cpi_program = ctx.accounts.token_program.to_account_info();
             let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
 
-            // token::mint_to(cpi_ctx, (borrow_amount)*1_000_000_000)?;
-            token::mint_to(cpi_ctx,borrow_amount*1_00)?;
+            let amount_to_mint = StableAmount::from_mint(borrow_amount, &ctx.accounts.stable_coin)?.base_units();
+            token::mint_to(cpi_ctx, amount_to_mint)?;
 
+            // large mints raise the base rate
+            ctx.accounts.stable_coin.reload()?;
+            ctx.accounts.trove_manager.increase_base_rate(amount_to_mint, ctx.accounts.stable_coin.supply)?;
 
+
+            trove.version = ACCOUNT_VERSION;
             trove.bump = trove_account_bump;
             trove.sol_bump = sol_account_bump;
             trove.is_initialized = true; // initialize for newly created account
             trove.is_liquidated = false;
             trove.is_received = false;
+            trove.received_at = 0;
+            trove.received_price = 0;
             trove.borrow_amount = borrow_amount;
             trove.lamports_amount = lamports;
-            trove.depositor_fee = get_depositors_fee(borrow_amount);
-            trove.team_fee = get_team_fee(borrow_amount);
+            trove.depositor_fee = dep_fee_in_gens;
+            trove.team_fee = team_fee_in_gens;
             trove.amount_to_close = borrow_amount;
             trove.authority = *borrower.key;
 
-            msg!("Trove owner is {}", trove.authority);
-            msg!("The borrow amount is {}", trove.borrow_amount);
+            // the new trove starts accruing interest from now
+            ctx.accounts.config.accrue_interest(ctx.accounts.clock.unix_timestamp)?;
+            trove.interest_index = ctx.accounts.config.cumulative_interest_index;
+
+            // no token collateral, the collaterals were checked to be empty above
+            ctx.accounts.trove_manager.check_recovery_mode(trove, 0, sol_price, &params)?;
+            ctx.accounts.trove_manager.update_trove(trove, 0)?;
+
+            emit!(TroveOpened {
+                authority: trove.authority,
+                lamports_amount: trove.lamports_amount,
+                borrow_amount: trove.borrow_amount,
+                amount_to_close: trove.amount_to_close,
+                depositor_fee: trove.depositor_fee,
+                team_fee: trove.team_fee,
+            });
 
         } else {
             return Err(NexfinError::BorrowTooLarge.into());
@@ -175,24 +223,38 @@ pub mod nexfin {
     /// 2. `[]` The rent sysvar
     pub fn add_borrow(ctx: Context<AddBorrow>, borrow_amount: u64, lamports: u64, mint_account_bump: u8) -> ProgramResult {
         msg!("Instruction Add Borrow");
+        ctx.accounts.config.check_not_paused(PAUSE_BORROWING)?;
+
         let trove = &mut ctx.accounts.trove;
         let sol_trove = &mut ctx.accounts.sol_trove;
 
-        // check for SOL price
-        let pyth_price_info = &ctx.accounts.pyth_sol_account;
-        let pyth_price_data = &pyth_price_info.try_borrow_data()?;
-        let pyth_price = pyth_client::cast::<pyth_client::Price>(pyth_price_data);
+        accrue_trove_interest(trove, &mut ctx.accounts.config, &mut ctx.accounts.fee_account, &mut ctx.accounts.team_fee_account, ctx.accounts.clock.unix_timestamp)?;
 
-        let sol_price = pyth_price.agg.price as u128;
+        // check for SOL price
+        let sol_price = get_sol_price(&ctx.accounts.pyth_sol_account, &ctx.accounts.secondary_price, &ctx.accounts.config, &ctx.accounts.clock)?.for_borrow()?;
+        let (token_lamports, _) = get_token_collateral_lamports(trove, &ctx.accounts.config, ctx.remaining_accounts, sol_price, ctx.accounts.config.params.liquidation_ratio, &ctx.accounts.clock)?;
 
         let mut total_collateral_price = sol_price.checked_mul(trove.lamports_amount as u128).ok_or(NexfinError::MathOverflow)?;
+        total_collateral_price = total_collateral_price.checked_add(sol_price.checked_mul(token_lamports as u128).ok_or(NexfinError::MathOverflow)?).ok_or(NexfinError::MathOverflow)?;
         total_collateral_price = total_collateral_price.checked_add(sol_price.checked_mul(lamports as u128).ok_or(NexfinError::MathOverflow)?).ok_or(NexfinError::MathOverflow)?;
 
         let total_borrow_amount = trove.borrow_amount.checked_add(borrow_amount).ok_or(NexfinError::MathOverflow)?;
 
+        check_debt_ceiling(&ctx.accounts.stable_coin, borrow_amount, &ctx.accounts.config.params)?;
+
         let collateral_ratio = total_collateral_price.checked_mul(100).ok_or(NexfinError::MathOverflow)?.checked_div(total_borrow_amount as u128).ok_or(NexfinError::MathOverflow)?.checked_div(1_000_000_000).ok_or(NexfinError::MathOverflow)?.checked_div(100_000_000).ok_or(NexfinError::MathOverflow)?;
 
-        if collateral_ratio > COLLATERAL_RATIO.try_into().unwrap() {
+        // calculate the fee in sol at the decayed base rate
+        let params = ctx.accounts.config.params;
+        let base_rate = ctx.accounts.trove_manager.decay_base_rate(ctx.accounts.clock.unix_timestamp)?;
+        let (dep_fee_in_gens, team_fee_in_gens) = get_borrowing_fees(borrow_amount, base_rate, &params)?;
+        let dep_fee_in_sol = get_fee_in_lamports(dep_fee_in_gens, sol_price)?;
+        let team_fee_in_sol = get_fee_in_lamports(team_fee_in_gens, sol_price)?;
+        msg!("Borrowing fee rate is {} basis points", get_borrowing_fee_rate(base_rate, &params)?);
+
+        if collateral_ratio > params.collateral_ratio as u128 {
+            let fee = &mut ctx.accounts.fee_account;
+            let team_fee = &mut ctx.accounts.team_fee_account;
 
             invoke(
                 &system_instruction::transfer(
@@ -207,6 +269,35 @@ pub mod nexfin {
                 ]
             )?;
 
+            invoke(
+                &system_instruction::transfer(
+                    ctx.accounts.authority.key,
+                    &fee.key(),
+                    dep_fee_in_sol
+                ),
+                &[
+                    ctx.accounts.authority.to_account_info().clone(),
+                    fee.to_account_info().clone(),
+                    ctx.accounts.system_program.to_account_info().clone()
+                ]
+            )?;
+
+            invoke(
+                &system_instruction::transfer(
+                    ctx.accounts.authority.key,
+                    &team_fee.key(),
+                    team_fee_in_sol
+                ),
+                &[
+                    ctx.accounts.authority.to_account_info().clone(),
+                    team_fee.to_account_info().clone(),
+                    ctx.accounts.system_program.to_account_info().clone()
+                ]
+            )?;
+
+            fee.sol_amount = fee.sol_amount.checked_add(dep_fee_in_sol).ok_or(NexfinError::MathOverflow)?;
+            team_fee.sol_amount = team_fee.sol_amount.checked_add(team_fee_in_sol).ok_or(NexfinError::MathOverflow)?;
+
             // Mint
             let seeds:&[&[u8]; 2] = &[
                 b"mint-authority",
@@ -222,14 +313,26 @@ pub mod nexfin {
             let cpi_program = ctx.accounts.token_program.to_account_info();
             let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
 
-            // token::mint_to(cpi_ctx,borrow_amount*1_000_000_000)?;
-            token::mint_to(cpi_ctx,borrow_amount*1_00)?;
+            let amount_to_mint = StableAmount::from_mint(borrow_amount, &ctx.accounts.stable_coin)?.base_units();
+            token::mint_to(cpi_ctx, amount_to_mint)?;
 
+            // large mints raise the base rate
+            ctx.accounts.stable_coin.reload()?;
+            ctx.accounts.trove_manager.increase_base_rate(amount_to_mint, ctx.accounts.stable_coin.supply)?;
 
+            trove.depositor_fee = trove.depositor_fee.checked_add(dep_fee_in_gens).ok_or(NexfinError::MathOverflow)?;
+            trove.team_fee = trove.team_fee.checked_add(team_fee_in_gens).ok_or(NexfinError::MathOverflow)?;
             trove.lamports_amount = trove.lamports_amount.checked_add(lamports).ok_or(NexfinError::MathOverflow)?;
             trove.amount_to_close = trove.amount_to_close.checked_add(borrow_amount).ok_or(NexfinError::MathOverflow)?;
             trove.borrow_amount = trove.borrow_amount.checked_add(borrow_amount).ok_or(NexfinError::MathOverflow)?;
-        
+            check_min_debt(trove.amount_to_close, &params)?;
+
+            let trove_token_lamports = get_trove_token_lamports(trove, &ctx.accounts.config, ctx.remaining_accounts, sol_price, &ctx.accounts.clock)?;
+            ctx.accounts.trove_manager.check_recovery_mode(trove, trove_token_lamports, sol_price, &params)?;
+            ctx.accounts.trove_manager.update_trove(trove, trove_token_lamports)?;
+            dequeue_healthy_trove(trove, &ctx.accounts.trove_manager, &ctx.accounts.config, ctx.remaining_accounts, sol_price, &ctx.accounts.clock)?;
+
+            emit!(TroveAdjusted::new(trove));
         }
 
         Ok(())
@@ -246,6 +349,8 @@ pub mod nexfin {
     /// 3. `[]` User token acc
     /// 4. `[]` Mint Token key
     pub fn close_trove(ctx: Context<CloseTrove>, _sol_account_bump:u8) -> ProgramResult {
+        ctx.accounts.config.check_not_paused(PAUSE_WITHDRAWALS)?;
+
         let trove = &mut ctx.accounts.trove;
         let sol_trove = ctx.accounts.sol_trove.to_account_info();
 
@@ -253,12 +358,18 @@ pub mod nexfin {
             return Err(NexfinError::TroveAlreadyLiquidated.into());
         }
 
+        // the token collateral is withdrawn before the trove account is closed
+        if trove.collaterals.iter().any(|amount| *amount > 0) {
+            return Err(NexfinError::InvalidCollateral.into());
+        }
+
+        accrue_trove_interest(trove, &mut ctx.accounts.config, &mut ctx.accounts.fee_account, &mut ctx.accounts.team_fee_account, ctx.accounts.clock.unix_timestamp)?;
+
         let borrower = ctx.accounts.authority.lamports();
         let user_token = &mut ctx.accounts.user_token;
         let mint_token = &mut ctx.accounts.token_mint;
 
-        // let amount_to_burn = trove.amount_to_close * 1_000_000_000;
-        let amount_to_burn = trove.amount_to_close * 1_00;
+        let amount_to_burn = StableAmount::from_mint(trove.amount_to_close, mint_token)?.base_units();
 
 
         let burn_ctx = CpiContext::new(
@@ -273,6 +384,14 @@ pub mod nexfin {
         msg!("Calling the token program to transfer tokens to the escrow's initializer...");
         token::burn(burn_ctx, amount_to_burn)?;
 
+        ctx.accounts.trove_manager.remove(&trove.authority)?;
+
+        emit!(TroveClosed {
+            authority: trove.authority,
+            repaid_amount: trove.amount_to_close,
+            returned_lamports: sol_trove.lamports(),
+        });
+
         msg!("Send back the lamports!");
         **ctx.accounts.authority.lamports.borrow_mut() = borrower
         .checked_add(sol_trove.lamports())
@@ -284,13 +403,42 @@ pub mod nexfin {
 
     /// Liquidate Trove
     ///
+    /// Anyone can liquidate a trove which is below the minimum collateral ratio once it has
+    /// spent the grace period in the liquidation queue, see `receive_trove`. In recovery mode
+    /// the troves under the critical collateral ratio are liquidatable as well, those above the
+    /// liquidation ratio lose the collateral worth the liquidation ratio of their debt and their
+    /// owner claims the remaining SOL through `claim_collateral_surplus`.
+    /// The trove debt is offset against the stability pool and the seized SOL is moved into the
+    /// reward vault, the depositors settle their share lazily through the pool snapshots.
+    /// A trove owing more than the pool deposits is liquidated pro rata up to the deposits, the
+    /// rest of its debt and collateral stays queued until the pool is refilled.
+    ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` The account of the person taking the trade
+    /// 0. `[signer]` The liquidator
     /// 1. `[writable]` The Trove account
-    /// 2. `[writable]` The Trove owner
-    pub fn liquidate_trove(ctx: Context<LiquidateTrove>, _trove_bump:u8) -> ProgramResult {
+    /// 2. `[writable]` The Sol Trove account
+    /// 3. `[writable]` The Reward vault
+    /// 4. `[writable]` The Stability pool
+    /// 5. `[writable]` The Deposit vault
+    /// 6. `[writable]` Mint Token key
+    /// 7. `[]` Pyth SOL price account
+    /// 8. `[]` System program
+    /// 9. `[]` Token program
+    ///
+    /// For each collateral type held by the trove, in the order of the config, the remaining
+    /// accounts are its pyth price account, the trove collateral vault and the pool collateral vault.
+    pub fn liquidate_trove(ctx: Context<LiquidateTrove>, _trove_bump:u8, _reward_vault_bump: u8) -> ProgramResult {
+        ctx.accounts.config.check_not_paused(PAUSE_LIQUIDATIONS)?;
+
         let trove = &mut ctx.accounts.trove;
+        let sol_trove = ctx.accounts.sol_trove.to_account_info();
+        let reward_vault = ctx.accounts.reward_coin_vault.to_account_info();
+
+        if !trove.is_initialized {
+            return Err(NexfinError::TroveIsNotInitialized.into());
+        }
+
         if trove.is_liquidated {
             return Err(NexfinError::TroveAlreadyLiquidated.into());
         }
@@ -299,6 +447,194 @@ pub mod nexfin {
             return Err(NexfinError::TroveIsNotReceived.into());
         }
 
+        let grace_period_end = trove.received_at.checked_add(ctx.accounts.config.params.liquidation_grace_period as i64).ok_or(NexfinError::MathOverflow)?;
+        if ctx.accounts.clock.unix_timestamp < grace_period_end {
+            return Err(NexfinError::LiquidationGracePeriod.into());
+        }
+
+        // exactly one triple per held collateral type, the liquidator cannot leave a vault out or pass one twice
+        let held_collaterals = trove.collaterals.iter().filter(|amount| **amount > 0).count();
+        if ctx.remaining_accounts.len() != held_collaterals * 3 {
+            return Err(NexfinError::InvalidAccountInput.into());
+        }
+
+        accrue_trove_interest(trove, &mut ctx.accounts.config, &mut ctx.accounts.fee_account, &mut ctx.accounts.team_fee_account, ctx.accounts.clock.unix_timestamp)?;
+
+        // check for SOL price
+        let sol_price = get_sol_price(&ctx.accounts.pyth_sol_account, &ctx.accounts.secondary_price, &ctx.accounts.config, &ctx.accounts.clock)?.price;
+        msg!("Trove was received at price {}, liquidated at price {}", trove.received_price, sol_price);
+
+        let liquidation_ratio = ctx.accounts.trove_manager.get_liquidation_ratio(sol_price, &ctx.accounts.config.params)?;
+        let oracle_infos: Vec<AccountInfo> = ctx.remaining_accounts.chunks(3).map(|accounts| accounts[0].clone()).collect();
+        let (_, token_lamports) = get_token_collateral_lamports(trove, &ctx.accounts.config, &oracle_infos, sol_price, liquidation_ratio, &ctx.accounts.clock)?;
+
+        let collateral_lamports = trove.lamports_amount.checked_add(token_lamports).ok_or(NexfinError::MathOverflow)?;
+        let collateral_ratio = get_collateral_ratio(collateral_lamports, trove.amount_to_close, sol_price)?;
+        msg!("Trove collateral ratio is {}", collateral_ratio);
+
+        if collateral_ratio >= liquidation_ratio {
+            return Err(NexfinError::ObligationHealthy.into());
+        }
+
+        // the seized collateral can never exceed what the sol trove holds
+        let available_lamports = std::cmp::min(trove.lamports_amount, sol_trove.lamports());
+
+        // a trove above the liquidation ratio is only liquidatable in recovery mode, it loses the
+        // collateral worth the liquidation ratio of its debt and keeps the rest of its SOL
+        let seized_lamports = if collateral_ratio >= ctx.accounts.config.params.liquidation_ratio {
+            let debt_lamports = get_lamports_for_debt(trove.amount_to_close, sol_price)?;
+            let capped_lamports = get_pro_rata_share(debt_lamports, ctx.accounts.config.params.liquidation_ratio, 100)?;
+            std::cmp::min(available_lamports, capped_lamports.saturating_sub(token_lamports))
+        } else {
+            available_lamports
+        };
+
+        // the pool offsets the debt its deposits cover and the same share of the collateral is seized
+        let debt = trove.amount_to_close;
+        let offset_debt = std::cmp::min(debt, ctx.accounts.stability_pool.total_deposits);
+        if offset_debt == 0 {
+            return Err(NexfinError::InsufficientLiquidity.into());
+        }
+        let is_partial = offset_debt < debt;
+
+        // the surplus of a recovery mode liquidation is left to the owner once the whole debt is offset
+        let (seized_lamports, collateral_surplus) = if is_partial {
+            (get_pro_rata_share(seized_lamports, offset_debt, debt)?, 0)
+        } else {
+            (seized_lamports, available_lamports.checked_sub(seized_lamports).ok_or(NexfinError::MathOverflow)?)
+        };
+        let mut seized_collaterals = [0; MAX_COLLATERAL_TYPES];
+        for (seized, amount) in seized_collaterals.iter_mut().zip(trove.collaterals.iter()) {
+            *seized = get_pro_rata_share(*amount, offset_debt, debt)?;
+        }
+
+        let liquidator_reward = get_liquidator_reward(seized_lamports, &ctx.accounts.config.params)?;
+        let depositors_reward = seized_lamports.checked_sub(liquidator_reward).ok_or(NexfinError::MathOverflow)?;
+
+        // burn the debt from the deposits and credit the seized SOL to the pool sums
+        let stability_pool = &mut ctx.accounts.stability_pool;
+        stability_pool.issue_governance(ctx.accounts.clock.unix_timestamp)?;
+
+        // the token collateral moves from the trove vaults to the pool vaults before the offset
+        let collateral_types = ctx.accounts.config.collateral_types;
+        let trove_seeds: &[&[u8]] = &[b"borrowertrove".as_ref(), trove.authority.as_ref(), &[trove.bump]];
+        let mut collateral_accounts = ctx.remaining_accounts.chunks(3);
+        for (index, amount) in trove.collaterals.iter().enumerate() {
+            if *amount == 0 {
+                continue;
+            }
+
+            let accounts = collateral_accounts.next().filter(|accounts| accounts.len() == 3).ok_or(NexfinError::InvalidAccountInput)?;
+            let (collateral_vault, pool_collateral_vault) = (&accounts[1], &accounts[2]);
+            let mint = collateral_types[index].mint;
+            let (collateral_vault_key, _) = Pubkey::find_program_address(&[b"collateralvault".as_ref(), trove.authority.as_ref(), mint.as_ref()], ctx.program_id);
+            let pool_collateral_vault_key = Pubkey::create_program_address(&[b"poolcollateral".as_ref(), mint.as_ref(), &[collateral_types[index].vault_bump]], ctx.program_id)?;
+            if *collateral_vault.key != collateral_vault_key || *pool_collateral_vault.key != pool_collateral_vault_key {
+                return Err(NexfinError::InvalidAccountInput.into());
+            }
+
+            let transfer_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.clone(),
+                Transfer {
+                    from: collateral_vault.clone(),
+                    to: pool_collateral_vault.clone(),
+                    authority: trove.to_account_info(),
+                },
+                &[trove_seeds],
+            );
+            token::transfer(transfer_ctx, seized_collaterals[index])?;
+
+            stability_pool.distribute_collateral(index, seized_collaterals[index])?;
+            msg!("Seized {} of the collateral {}", seized_collaterals[index], mint);
+        }
+
+        stability_pool.offset(offset_debt, depositors_reward)?;
+
+        let seeds:&[&[u8]; 2] = &[
+            b"stabilitypool",
+            &[stability_pool.bump]
+        ];
+        let signer = &[&seeds[..]];
+        let burn_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.clone(),
+            Burn {
+                authority: stability_pool.to_account_info(),
+                mint: ctx.accounts.stable_coin.to_account_info(),
+                to: ctx.accounts.deposit_vault.to_account_info(),
+            },
+            signer,
+        );
+        token::burn(burn_ctx, StableAmount::from_mint(offset_debt, &ctx.accounts.stable_coin)?.base_units())?;
+
+        check_deposit_vault(&mut ctx.accounts.deposit_vault, stability_pool, &ctx.accounts.stable_coin)?;
+
+        msg!("Send the seized lamports to the reward vault and the liquidator");
+        **sol_trove.try_borrow_mut_lamports()? -= seized_lamports;
+        **reward_vault.try_borrow_mut_lamports()? += depositors_reward;
+        **ctx.accounts.liquidator.to_account_info().try_borrow_mut_lamports()? += liquidator_reward;
+
+        emit!(TroveLiquidated {
+            authority: trove.authority,
+            liquidator: ctx.accounts.liquidator.key(),
+            debt: offset_debt,
+            collateral_ratio,
+            seized_lamports,
+            liquidator_reward,
+            seized_collaterals,
+            collateral_surplus,
+            remaining_debt: debt - offset_debt,
+        });
+
+        if is_partial {
+            // the trove keeps its place in the queue with the debt the pool could not offset
+            msg!("The pool offset {} of the {} debt, the rest stays queued", offset_debt, debt);
+            trove.lamports_amount = trove.lamports_amount.checked_sub(seized_lamports).ok_or(NexfinError::MathOverflow)?;
+            trove.amount_to_close = debt - offset_debt;
+            for (amount, seized) in trove.collaterals.iter_mut().zip(seized_collaterals.iter()) {
+                *amount = amount.checked_sub(*seized).ok_or(NexfinError::MathOverflow)?;
+            }
+
+            let trove_token_lamports = get_trove_token_lamports(trove, &ctx.accounts.config, &oracle_infos, sol_price, &ctx.accounts.clock)?;
+            ctx.accounts.trove_manager.update_trove(trove, trove_token_lamports)?;
+            return Ok(());
+        }
+
+        trove.is_liquidated = true;
+        trove.collateral_surplus = trove.collateral_surplus.checked_add(collateral_surplus).ok_or(NexfinError::MathOverflow)?;
+        trove.lamports_amount = 0;
+        trove.amount_to_close = 0;
+        trove.collaterals = [0; MAX_COLLATERAL_TYPES];
+
+        ctx.accounts.trove_manager.remove(&trove.authority)?;
+
+        Ok(())
+    }
+
+    /// Claim Collateral Surplus
+    ///
+    /// The owner of a trove liquidated in recovery mode withdraws the SOL left above the
+    /// liquidation ratio of its debt, see `liquidate_trove`.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The trove owner
+    /// 1. `[writable]` The Trove account
+    /// 2. `[writable]` The Sol Trove account
+    /// 3. `[]` Config account
+    pub fn claim_collateral_surplus(ctx: Context<ClaimCollateralSurplus>) -> ProgramResult {
+        ctx.accounts.config.check_not_paused(PAUSE_WITHDRAWALS)?;
+
+        let trove = &mut ctx.accounts.trove;
+        let collateral_surplus = trove.collateral_surplus;
+        if collateral_surplus == 0 {
+            return Err(NexfinError::InvalidAmount.into());
+        }
+
+        trove.collateral_surplus = 0;
+        **ctx.accounts.sol_trove.try_borrow_mut_lamports()? -= collateral_surplus;
+        **ctx.accounts.authority.to_account_info().try_borrow_mut_lamports()? += collateral_surplus;
+
+        msg!("Claimed a collateral surplus of {} lamports", collateral_surplus);
         Ok(())
     }
 
@@ -308,7 +644,14 @@ pub mod nexfin {
     ///
     /// 0. `[signer]` The account of the person taking the trade
     /// 1. `[writable]` The Trove account
+    /// 2. `[writable]` The Trove manager account
+    /// 3. `[writable]` The depositors and team Fee accounts
+    /// 4. `[writable]` The Sol Trove account
+    /// 5. `[]` Pyth SOL price account
+    /// 6. `[writable]` Config account
     pub fn withdraw_coin(ctx: Context<WithdrawCoin>, amount: u64, _trove_bump: u8, ) -> ProgramResult {
+        ctx.accounts.config.check_not_paused(PAUSE_WITHDRAWALS)?;
+
         let trove = &mut ctx.accounts.trove;
 
         if !trove.is_initialized {
@@ -319,6 +662,8 @@ pub mod nexfin {
             return Err(NexfinError::TroveAlreadyLiquidated.into());
         }
 
+        accrue_trove_interest(trove, &mut ctx.accounts.config, &mut ctx.accounts.fee_account, &mut ctx.accounts.team_fee_account, ctx.accounts.clock.unix_timestamp)?;
+
         trove.lamports_amount = trove.lamports_amount.checked_sub(amount).ok_or(NexfinError::MathOverflow)?;
 
         // Does the from account have enough lamports to transfer?
@@ -326,34 +671,345 @@ pub mod nexfin {
             return Err(NexfinError::InsufficientLiquidity.into());
         }
 
-        // TODO: change the logic for chekcing collateral ratio after the sol is withdrawed
+        // check for SOL price
+        let sol_price = get_sol_price(&ctx.accounts.pyth_sol_account, &ctx.accounts.secondary_price, &ctx.accounts.config, &ctx.accounts.clock)?.price;
+        let (token_lamports, _) = get_token_collateral_lamports(trove, &ctx.accounts.config, ctx.remaining_accounts, sol_price, ctx.accounts.config.params.liquidation_ratio, &ctx.accounts.clock)?;
+
+        let collateral_lamports = trove.lamports_amount.checked_add(token_lamports).ok_or(NexfinError::MathOverflow)?;
+        check_collateral_ratio(collateral_lamports, trove.amount_to_close, sol_price, ctx.accounts.config.params.collateral_ratio)?;
 
+        let trove_token_lamports = get_trove_token_lamports(trove, &ctx.accounts.config, ctx.remaining_accounts, sol_price, &ctx.accounts.clock)?;
+        ctx.accounts.trove_manager.check_recovery_mode(trove, trove_token_lamports, sol_price, &ctx.accounts.config.params)?;
+        ctx.accounts.trove_manager.update_trove(trove, trove_token_lamports)?;
+        dequeue_healthy_trove(trove, &ctx.accounts.trove_manager, &ctx.accounts.config, ctx.remaining_accounts, sol_price, &ctx.accounts.clock)?;
 
         // Debit from_account and credit to_account
         **ctx.accounts.sol_trove.try_borrow_mut_lamports()? -= amount;
         **ctx.accounts.authority.try_borrow_mut_lamports()? += amount;
 
+        emit!(TroveAdjusted::new(trove));
+
         Ok(())
     }
 
-    /// Redeem Coin
+    /// Adjust Trove
+    ///
+    /// Adds or withdraws collateral and borrows or repays debt in one instruction, positive
+    /// deltas add collateral and borrow, negative deltas withdraw collateral and repay.
+    /// The resulting collateral ratio is checked once against the oracle price whenever the
+    /// adjustment withdraws collateral or borrows.
     ///
     /// Accounts expected:
     ///
     /// 0. `[signer]` The account of the person taking the trade
     /// 1. `[writable]` The Trove account
-    pub fn redeem_coin(ctx: Context<RedeemCoin>, amount: u64) -> ProgramResult {
-        let _borrower = &mut ctx.accounts.authority;
+    /// 2. `[writable]` The Trove manager account
+    /// 3. `[writable]` The depositors and team Fee accounts
+    /// 4. `[writable]` The Sol Trove account
+    /// 5. `[writable]` Mint Token key
+    /// 6. `[writable]` User token acc
+    /// 7. `[]` Pyth SOL price account
+    pub fn adjust_trove(ctx: Context<AdjustTrove>, collateral_delta: i64, debt_delta: i64, mint_account_bump: u8) -> ProgramResult {
         let trove = &mut ctx.accounts.trove;
 
         if !trove.is_initialized {
             return Err(NexfinError::TroveIsNotInitialized.into());
         }
+
         if trove.is_liquidated {
             return Err(NexfinError::TroveAlreadyLiquidated.into());
         }
 
-        trove.lamports_amount = trove.lamports_amount.checked_sub(amount).ok_or(NexfinError::MathOverflow)?;
+        if collateral_delta == 0 && debt_delta == 0 {
+            return Err(NexfinError::InvalidAmount.into());
+        }
+
+        if debt_delta > 0 {
+            ctx.accounts.config.check_not_paused(PAUSE_BORROWING)?;
+        }
+        if collateral_delta < 0 {
+            ctx.accounts.config.check_not_paused(PAUSE_WITHDRAWALS)?;
+        }
+
+        accrue_trove_interest(trove, &mut ctx.accounts.config, &mut ctx.accounts.fee_account, &mut ctx.accounts.team_fee_account, ctx.accounts.clock.unix_timestamp)?;
+
+        let params = ctx.accounts.config.params;
+        let collateral_amount = collateral_delta.unsigned_abs();
+        let debt_amount = debt_delta.unsigned_abs();
+
+        // check for SOL price, only a borrow needs both feeds to agree
+        let sol_price = get_sol_price(&ctx.accounts.pyth_sol_account, &ctx.accounts.secondary_price, &ctx.accounts.config, &ctx.accounts.clock)?;
+        let sol_price = if debt_delta > 0 { sol_price.for_borrow()? } else { sol_price.price };
+
+        if collateral_delta >= 0 {
+            trove.lamports_amount = trove.lamports_amount.checked_add(collateral_amount).ok_or(NexfinError::MathOverflow)?;
+        } else {
+            trove.lamports_amount = trove.lamports_amount.checked_sub(collateral_amount).ok_or(NexfinError::MathOverflow)?;
+
+            if ctx.accounts.sol_trove.lamports() < collateral_amount {
+                return Err(NexfinError::InsufficientLiquidity.into());
+            }
+        }
+
+        let mut dep_fee_in_sol = 0;
+        let mut team_fee_in_sol = 0;
+        if debt_delta > 0 {
+            check_debt_ceiling(&ctx.accounts.stable_coin, debt_amount, &params)?;
+
+            // calculate the fee in sol at the decayed base rate
+            let base_rate = ctx.accounts.trove_manager.decay_base_rate(ctx.accounts.clock.unix_timestamp)?;
+            let (dep_fee_in_gens, team_fee_in_gens) = get_borrowing_fees(debt_amount, base_rate, &params)?;
+            dep_fee_in_sol = get_fee_in_lamports(dep_fee_in_gens, sol_price)?;
+            team_fee_in_sol = get_fee_in_lamports(team_fee_in_gens, sol_price)?;
+            msg!("Borrowing fee rate is {} basis points", get_borrowing_fee_rate(base_rate, &params)?);
+
+            trove.depositor_fee = trove.depositor_fee.checked_add(dep_fee_in_gens).ok_or(NexfinError::MathOverflow)?;
+            trove.team_fee = trove.team_fee.checked_add(team_fee_in_gens).ok_or(NexfinError::MathOverflow)?;
+            trove.amount_to_close = trove.amount_to_close.checked_add(debt_amount).ok_or(NexfinError::MathOverflow)?;
+            trove.borrow_amount = trove.borrow_amount.checked_add(debt_amount).ok_or(NexfinError::MathOverflow)?;
+        } else {
+            trove.amount_to_close = trove.amount_to_close.checked_sub(debt_amount).ok_or(NexfinError::MathOverflow)?;
+        }
+        check_min_debt(trove.amount_to_close, &params)?;
+
+        // a single health check on the adjusted trove, adding collateral or repaying is always allowed
+        if collateral_delta < 0 || debt_delta > 0 {
+            let (token_lamports, _) = get_token_collateral_lamports(trove, &ctx.accounts.config, ctx.remaining_accounts, sol_price, params.liquidation_ratio, &ctx.accounts.clock)?;
+            let collateral_lamports = trove.lamports_amount.checked_add(token_lamports).ok_or(NexfinError::MathOverflow)?;
+            check_collateral_ratio(collateral_lamports, trove.amount_to_close, sol_price, params.collateral_ratio)?;
+        }
+
+        let trove_token_lamports = get_trove_token_lamports(trove, &ctx.accounts.config, ctx.remaining_accounts, sol_price, &ctx.accounts.clock)?;
+        ctx.accounts.trove_manager.check_recovery_mode(trove, trove_token_lamports, sol_price, &params)?;
+        ctx.accounts.trove_manager.update_trove(trove, trove_token_lamports)?;
+        dequeue_healthy_trove(trove, &ctx.accounts.trove_manager, &ctx.accounts.config, ctx.remaining_accounts, sol_price, &ctx.accounts.clock)?;
+
+        if collateral_delta > 0 {
+            invoke(
+                &system_instruction::transfer(
+                    ctx.accounts.authority.key,
+                    ctx.accounts.sol_trove.key,
+                    collateral_amount
+                ),
+                &[
+                    ctx.accounts.authority.to_account_info().clone(),
+                    ctx.accounts.sol_trove.to_account_info().clone(),
+                    ctx.accounts.system_program.to_account_info().clone()
+                ]
+            )?;
+        }
+
+        if debt_delta > 0 {
+            let fee = &mut ctx.accounts.fee_account;
+            let team_fee = &mut ctx.accounts.team_fee_account;
+
+            invoke(
+                &system_instruction::transfer(
+                    ctx.accounts.authority.key,
+                    &fee.key(),
+                    dep_fee_in_sol
+                ),
+                &[
+                    ctx.accounts.authority.to_account_info().clone(),
+                    fee.to_account_info().clone(),
+                    ctx.accounts.system_program.to_account_info().clone()
+                ]
+            )?;
+
+            invoke(
+                &system_instruction::transfer(
+                    ctx.accounts.authority.key,
+                    &team_fee.key(),
+                    team_fee_in_sol
+                ),
+                &[
+                    ctx.accounts.authority.to_account_info().clone(),
+                    team_fee.to_account_info().clone(),
+                    ctx.accounts.system_program.to_account_info().clone()
+                ]
+            )?;
+
+            fee.sol_amount = fee.sol_amount.checked_add(dep_fee_in_sol).ok_or(NexfinError::MathOverflow)?;
+            team_fee.sol_amount = team_fee.sol_amount.checked_add(team_fee_in_sol).ok_or(NexfinError::MathOverflow)?;
+
+            // Mint
+            let seeds:&[&[u8]; 2] = &[
+                b"mint-authority",
+                &[mint_account_bump]
+            ];
+            let signer = &[&seeds[..]];
+            let cpi_accounts = MintTo {
+                mint: ctx.accounts.stable_coin.to_account_info(),
+                to: ctx.accounts.user_token.to_account_info(),
+                authority: ctx.accounts.token_authority.to_account_info(),
+            };
+
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+            let amount_to_mint = StableAmount::from_mint(debt_amount, &ctx.accounts.stable_coin)?.base_units();
+            token::mint_to(cpi_ctx, amount_to_mint)?;
+
+            // large mints raise the base rate
+            ctx.accounts.stable_coin.reload()?;
+            ctx.accounts.trove_manager.increase_base_rate(amount_to_mint, ctx.accounts.stable_coin.supply)?;
+        } else if debt_delta < 0 {
+            let amount_to_burn = StableAmount::from_mint(debt_amount, &ctx.accounts.stable_coin)?.base_units();
+            let burn_ctx = CpiContext::new(
+                ctx.accounts.token_program.clone(),
+                Burn {
+                    authority: ctx.accounts.authority.to_account_info(),
+                    mint: ctx.accounts.stable_coin.to_account_info(),
+                    to: ctx.accounts.user_token.to_account_info(),
+                },
+            );
+            token::burn(burn_ctx, amount_to_burn)?;
+        }
+
+        // Debit the sol trove last, after the CPIs
+        if collateral_delta < 0 {
+            **ctx.accounts.sol_trove.try_borrow_mut_lamports()? -= collateral_amount;
+            **ctx.accounts.authority.try_borrow_mut_lamports()? += collateral_amount;
+        }
+
+        emit!(TroveAdjusted::new(trove));
+        Ok(())
+    }
+
+    /// Redeem Coin
+    ///
+    /// Burns `amount` stable coins and pays SOL at face value drawn from the riskiest troves,
+    /// minus a redemption fee which is sent to the depositors fee account.
+    /// Troves under the liquidation ratio, their token collateral included, are skipped as they
+    /// have to be liquidated, as are troves without the SOL to pay their redeemed debt.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the person taking the trade
+    /// 1. `[writable]` The Trove manager account
+    /// 2. `[writable]` The depositors and team Fee accounts
+    /// 3. `[writable]` Mint Token key
+    /// 4. `[writable]` User token acc
+    /// 5. `[]` Token program
+    /// 6. `[]` Pyth SOL price account
+    /// 7. `[writable]` Config account
+    /// 8. `[]` The clock sysvar
+    /// 9.. `[writable]` Per trove in the trove manager sorted order, its Trove and Sol Trove accounts
+    ///     followed by the `[]` pyth price accounts of the collateral types it holds
+    pub fn redeem_coin(ctx: Context<RedeemCoin>, amount: u64) -> ProgramResult {
+        ctx.accounts.config.check_not_paused(PAUSE_REDEMPTIONS)?;
+
+        let trove_manager = &mut ctx.accounts.trove_manager;
+
+        if amount == 0 {
+            return Err(NexfinError::InvalidAmount.into());
+        }
+
+        // check for SOL price
+        let sol_price = get_sol_price(&ctx.accounts.pyth_sol_account, &ctx.accounts.secondary_price, &ctx.accounts.config, &ctx.accounts.clock)?.price;
+
+        // snapshot the order, redeemed troves are moved up the list as we go
+        let sorted_troves: Vec<Pubkey> = trove_manager.troves.iter().map(|t| t.authority).collect();
+
+        let mut remaining_amount = amount;
+        let mut redeemed_lamports: u64 = 0;
+        let mut trove_accounts = ctx.remaining_accounts;
+        let mut index = 0;
+        while remaining_amount > 0 && !trove_accounts.is_empty() {
+            if trove_accounts.len() < 2 {
+                return Err(NexfinError::InvalidAccountInput.into());
+            }
+
+            let mut trove = load_trove(&trove_accounts[0], ctx.program_id)?;
+            let sol_trove = &trove_accounts[1];
+
+            // the pyth accounts of the collateral types held by the trove follow its sol trove
+            let held_collaterals = trove.collaterals.iter().filter(|amount| **amount > 0).count();
+            if trove_accounts.len() < 2 + held_collaterals {
+                return Err(NexfinError::InvalidAccountInput.into());
+            }
+            let oracle_infos = &trove_accounts[2..2 + held_collaterals];
+            trove_accounts = &trove_accounts[2 + held_collaterals..];
+
+            if sorted_troves.get(index) != Some(&trove.authority) {
+                return Err(NexfinError::InvalidTroveOrder.into());
+            }
+
+            let sol_trove_key = Pubkey::create_program_address(
+                &[b"solTrove".as_ref(), trove.authority.to_bytes().as_ref(), &[trove.sol_bump]],
+                ctx.program_id,
+            )?;
+            if sol_trove_key != *sol_trove.key {
+                return Err(NexfinError::InvalidAccountInput.into());
+            }
+
+            accrue_trove_interest(&mut trove, &mut ctx.accounts.config, &mut ctx.accounts.fee_account, &mut ctx.accounts.team_fee_account, ctx.accounts.clock.unix_timestamp)?;
+            let trove_token_lamports = get_trove_token_lamports(&trove, &ctx.accounts.config, oracle_infos, sol_price, &ctx.accounts.clock)?;
+
+            // a partial redemption leaves at least the minimum debt in the trove
+            let params = &ctx.accounts.config.params;
+            let collateral_lamports = trove.lamports_amount.checked_add(trove_token_lamports).ok_or(NexfinError::MathOverflow)?;
+            let mut debt = 0;
+            if get_collateral_ratio(collateral_lamports, trove.amount_to_close, sol_price)? >= params.liquidation_ratio {
+                debt = std::cmp::min(remaining_amount, trove.amount_to_close);
+                if debt < trove.amount_to_close {
+                    debt = std::cmp::min(debt, trove.amount_to_close.saturating_sub(params.min_borrow_amount));
+                }
+            }
+
+            // redemptions are paid in SOL, a trove backed by its tokens cannot pay at face value
+            let lamports = get_lamports_for_debt(debt, sol_price)?;
+            if lamports > trove.lamports_amount {
+                debt = 0;
+            }
+
+            if debt > 0 {
+                trove.amount_to_close = trove.amount_to_close.checked_sub(debt).ok_or(NexfinError::MathOverflow)?;
+                trove.lamports_amount = trove.lamports_amount.checked_sub(lamports).ok_or(NexfinError::MathOverflow)?;
+                **sol_trove.try_borrow_mut_lamports()? -= lamports;
+
+                remaining_amount = remaining_amount.checked_sub(debt).ok_or(NexfinError::MathOverflow)?;
+                redeemed_lamports = redeemed_lamports.checked_add(lamports).ok_or(NexfinError::MathOverflow)?;
+
+                emit!(TroveAdjusted::new(&trove));
+            }
+
+            // skipped troves keep the interest accrued above
+            trove_manager.update_trove(&trove, trove_token_lamports)?;
+            dequeue_healthy_trove(&mut trove, trove_manager, &ctx.accounts.config, oracle_infos, sol_price, &ctx.accounts.clock)?;
+            trove.exit(ctx.program_id)?;
+            index += 1;
+        }
+
+        let redeemed_amount = amount.checked_sub(remaining_amount).ok_or(NexfinError::MathOverflow)?;
+        if redeemed_amount == 0 {
+            return Err(NexfinError::InsufficientLiquidity.into());
+        }
+
+        let amount_to_burn = StableAmount::from_mint(redeemed_amount, &ctx.accounts.stable_coin)?.base_units();
+
+        // the base rate grows with the redeemed fraction of the supply
+        trove_manager.decay_base_rate(ctx.accounts.clock.unix_timestamp)?;
+        trove_manager.increase_base_rate(amount_to_burn, ctx.accounts.stable_coin.supply)?;
+
+        let redemption_fee = get_redemption_fee(trove_manager.base_rate, redeemed_lamports)?;
+        msg!("Redeemed {} for {} lamports, the redemption fee is {}", redeemed_amount, redeemed_lamports, redemption_fee);
+
+        let burn_ctx = CpiContext::new(
+            ctx.accounts.token_program.clone(),
+            Burn {
+                authority: ctx.accounts.authority.to_account_info(),
+                mint: ctx.accounts.stable_coin.to_account_info(),
+                to: ctx.accounts.user_token.to_account_info(),
+            },
+        );
+        token::burn(burn_ctx, amount_to_burn)?;
+
+        let fee = &mut ctx.accounts.fee_account;
+        fee.sol_amount = fee.sol_amount.checked_add(redemption_fee).ok_or(NexfinError::MathOverflow)?;
+        **fee.to_account_info().try_borrow_mut_lamports()? += redemption_fee;
+        **ctx.accounts.authority.try_borrow_mut_lamports()? += redeemed_lamports.checked_sub(redemption_fee).ok_or(NexfinError::MathOverflow)?;
+
         Ok(())
     }
 
@@ -363,9 +1019,17 @@ pub mod nexfin {
     ///
     /// 0. `[signer]` The account of the person taking the trade
     /// 1. `[writable]` The Trove account
-    /// 2. `[writable]` The Temp Account to get lamports
+    /// 2. `[writable]` The Trove manager account
+    /// 3. `[writable]` The depositors and team Fee accounts
+    /// 4. `[writable]` The Sol Trove account
+    /// 5. `[]` Pyth SOL price account
+    /// 6. `[writable]` Config account
+    /// 7. `[]` System program
+    ///
+    /// The remaining accounts are the pyth price accounts of the collateral types the trove holds,
+    /// in the order of the config.
     pub fn add_coin(ctx: Context<AddCoin>, amount: u64) -> ProgramResult {
-        let _borrower = &mut ctx.accounts.authority;
+        let borrower = &ctx.accounts.authority;
         let trove = &mut ctx.accounts.trove;
 
         if !trove.is_initialized {
@@ -375,31 +1039,211 @@ pub mod nexfin {
             return Err(NexfinError::TroveAlreadyLiquidated.into());
         }
 
-        let temp_lamport_account = &mut ctx.accounts.temp_lamport_account;
-
-        if temp_lamport_account.lamports() != amount {
-            return Err(NexfinError::ExpectedAmountMismatch.into());
+        if amount == 0 || borrower.lamports() < amount {
+            return Err(NexfinError::InsufficientLiquidity.into());
         }
 
+        accrue_trove_interest(trove, &mut ctx.accounts.config, &mut ctx.accounts.fee_account, &mut ctx.accounts.team_fee_account, ctx.accounts.clock.unix_timestamp)?;
+
+        invoke(
+            &system_instruction::transfer(
+                borrower.key,
+                ctx.accounts.sol_trove.key,
+                amount
+            ),
+            &[
+                borrower.clone(),
+                ctx.accounts.sol_trove.clone(),
+                ctx.accounts.system_program.to_account_info().clone()
+            ]
+        )?;
+
         trove.lamports_amount = trove.lamports_amount.checked_add(amount).ok_or(NexfinError::MathOverflow)?;
+
+        let sol_price = get_sol_price(&ctx.accounts.pyth_sol_account, &ctx.accounts.secondary_price, &ctx.accounts.config, &ctx.accounts.clock)?.price;
+        let trove_token_lamports = get_trove_token_lamports(trove, &ctx.accounts.config, ctx.remaining_accounts, sol_price, &ctx.accounts.clock)?;
+        ctx.accounts.trove_manager.update_trove(trove, trove_token_lamports)?;
+        dequeue_healthy_trove(trove, &ctx.accounts.trove_manager, &ctx.accounts.config, ctx.remaining_accounts, sol_price, &ctx.accounts.clock)?;
+
+        emit!(TroveAdjusted::new(trove));
         Ok(())
     }
 
-    /// Add deposit
+    /// Reconcile Trove
+    ///
+    /// View comparing the collateral recorded by the trove with the balance of its sol trove,
+    /// fails when the trove records more than it holds. The rent exempt minimum funded when the
+    /// sol trove was created is held on top of the collateral and logged as unrecorded.
     ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` The account of the person taking the trade
-    /// 1. `[writable]` The Deposit account
-    /// 2. `[]` The rent sysvar
-    /// 3. `[]` Token program
-    /// 4. `[]` User token acc
-    /// 4. `[]` User governance token acc
-    /// 5. `[]` Mint Token key
+    /// 0. `[]` The Trove account
+    /// 1. `[]` The Sol Trove account
+    pub fn reconcile_trove(ctx: Context<ReconcileTrove>) -> ProgramResult {
+        let trove = &ctx.accounts.trove;
+        let sol_trove = &ctx.accounts.sol_trove;
+
+        // every lamport of the sol trove can be seized or withdrawn, the rent is not set aside
+        let held_lamports = sol_trove.lamports();
+
+        msg!("Trove records {} lamports, sol trove holds {} lamports", trove.lamports_amount, held_lamports);
+
+        if trove.lamports_amount > held_lamports {
+            msg!("Missing {} lamports", trove.lamports_amount - held_lamports);
+            return Err(NexfinError::ExpectedAmountMismatch.into());
+        }
+
+        if held_lamports > trove.lamports_amount {
+            msg!("Unrecorded {} lamports", held_lamports - trove.lamports_amount);
+        }
+
+        Ok(())
+    }
+
+    /// Deposit Collateral
+    ///
+    /// Moves a registered collateral token into the trove collateral vault, owned by the trove.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the person taking the trade
+    /// 1. `[writable]` The Trove account
+    /// 2. `[writable]` The Trove manager account
+    /// 3. `[writable]` The depositors and team Fee accounts
+    /// 4. `[writable]` Config account
+    /// 5. `[]` The collateral mint
+    /// 6. `[writable]` The Trove collateral vault
+    /// 7. `[writable]` User collateral token acc
+    /// 8. `[]` Pyth SOL price account
+    /// 9. `[]` Token program
+    ///
+    /// The remaining accounts are the pyth price accounts of the collateral types the trove holds
+    /// after the deposit, in the order of the config.
+    pub fn deposit_collateral(ctx: Context<DepositCollateral>, amount: u64, _collateral_vault_bump: u8) -> ProgramResult {
+        let trove = &mut ctx.accounts.trove;
+
+        if !trove.is_initialized {
+            return Err(NexfinError::TroveIsNotInitialized.into());
+        }
+        if trove.is_liquidated {
+            return Err(NexfinError::TroveAlreadyLiquidated.into());
+        }
+        if amount == 0 {
+            return Err(NexfinError::InvalidAmount.into());
+        }
+
+        accrue_trove_interest(trove, &mut ctx.accounts.config, &mut ctx.accounts.fee_account, &mut ctx.accounts.team_fee_account, ctx.accounts.clock.unix_timestamp)?;
+
+        let (index, _) = ctx.accounts.config.get_collateral_type(&ctx.accounts.collateral_mint.key())?;
+
+        let transfer_ctx = CpiContext::new(
+            ctx.accounts.token_program.clone(),
+            Transfer {
+                from: ctx.accounts.user_collateral.to_account_info(),
+                to: ctx.accounts.collateral_vault.to_account_info(),
+                authority: ctx.accounts.authority.to_account_info(),
+            },
+        );
+        token::transfer(transfer_ctx, amount)?;
+
+        trove.collaterals[index] = trove.collaterals[index].checked_add(amount).ok_or(NexfinError::MathOverflow)?;
+
+        let sol_price = get_sol_price(&ctx.accounts.pyth_sol_account, &ctx.accounts.secondary_price, &ctx.accounts.config, &ctx.accounts.clock)?.price;
+        let trove_token_lamports = get_trove_token_lamports(trove, &ctx.accounts.config, ctx.remaining_accounts, sol_price, &ctx.accounts.clock)?;
+        ctx.accounts.trove_manager.update_trove(trove, trove_token_lamports)?;
+        dequeue_healthy_trove(trove, &ctx.accounts.trove_manager, &ctx.accounts.config, ctx.remaining_accounts, sol_price, &ctx.accounts.clock)?;
+
+        emit!(TroveAdjusted::new(trove));
+        Ok(())
+    }
+
+    /// Withdraw Collateral
+    ///
+    /// Moves a collateral token out of the trove collateral vault, a trove with debt has to stay
+    /// above the collateral ratio.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the person taking the trade
+    /// 1. `[writable]` The Trove account
+    /// 2. `[writable]` The Trove manager account
+    /// 3. `[writable]` The depositors and team Fee accounts
+    /// 4. `[writable]` Config account
+    /// 5. `[]` The collateral mint
+    /// 6. `[writable]` The Trove collateral vault
+    /// 7. `[writable]` User collateral token acc
+    /// 8. `[]` Pyth SOL price account
+    /// 9. `[]` Token program
+    ///
+    /// The remaining accounts are the pyth price accounts of the collateral types the trove still
+    /// holds after the withdrawal, in the order of the config.
+    pub fn withdraw_collateral(ctx: Context<WithdrawCollateral>, amount: u64, _collateral_vault_bump: u8) -> ProgramResult {
+        ctx.accounts.config.check_not_paused(PAUSE_WITHDRAWALS)?;
+
+        let trove = &mut ctx.accounts.trove;
+
+        if !trove.is_initialized {
+            return Err(NexfinError::TroveIsNotInitialized.into());
+        }
+        if trove.is_liquidated {
+            return Err(NexfinError::TroveAlreadyLiquidated.into());
+        }
+
+        accrue_trove_interest(trove, &mut ctx.accounts.config, &mut ctx.accounts.fee_account, &mut ctx.accounts.team_fee_account, ctx.accounts.clock.unix_timestamp)?;
+
+        let (index, _) = ctx.accounts.config.get_collateral_type(&ctx.accounts.collateral_mint.key())?;
+        trove.collaterals[index] = trove.collaterals[index].checked_sub(amount).ok_or(NexfinError::InsufficientLiquidity)?;
+
+        if trove.amount_to_close > 0 {
+            let config = &ctx.accounts.config;
+            let sol_price = get_sol_price(&ctx.accounts.pyth_sol_account, &ctx.accounts.secondary_price, config, &ctx.accounts.clock)?.price;
+            let (token_lamports, trove_token_lamports) = get_token_collateral_lamports(trove, config, ctx.remaining_accounts, sol_price, config.params.liquidation_ratio, &ctx.accounts.clock)?;
+
+            let collateral_lamports = trove.lamports_amount.checked_add(token_lamports).ok_or(NexfinError::MathOverflow)?;
+            check_collateral_ratio(collateral_lamports, trove.amount_to_close, sol_price, config.params.collateral_ratio)?;
+            ctx.accounts.trove_manager.check_recovery_mode(trove, trove_token_lamports, sol_price, &config.params)?;
+            ctx.accounts.trove_manager.update_trove(trove, trove_token_lamports)?;
+            dequeue_healthy_trove(trove, &ctx.accounts.trove_manager, config, ctx.remaining_accounts, sol_price, &ctx.accounts.clock)?;
+        } else {
+            ctx.accounts.trove_manager.update_trove(trove, 0)?;
+        }
+
+        let seeds: &[&[u8]] = &[b"borrowertrove".as_ref(), trove.authority.as_ref(), &[trove.bump]];
+        let transfer_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.clone(),
+            Transfer {
+                from: ctx.accounts.collateral_vault.to_account_info(),
+                to: ctx.accounts.user_collateral.to_account_info(),
+                authority: trove.to_account_info(),
+            },
+            &[seeds],
+        );
+        token::transfer(transfer_ctx, amount)?;
+
+        emit!(TroveAdjusted::new(trove));
+        Ok(())
+    }
+
+    /// Add deposit
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the person taking the trade
+    /// 1. `[writable]` The Deposit account
+    /// 2. `[writable]` The Stability pool
+    /// 3. `[writable]` The Deposit vault
+    /// 4. `[]` The rent sysvar
+    /// 5. `[]` Token program
+    /// 6. `[]` User token acc
+    /// 7. `[]` User governance token acc
+    /// 8. `[]` Mint Token key
+    /// 9. `[]` Config account
    
     // TODO: Add admin as a signer
     // TODO: Check admin pubkey with the config account admin field
     pub fn add_deposit(ctx: Context<AddDeposit>, amount: u64, deposit_account_bump: u8) -> ProgramResult {
+        ctx.accounts.config.check_not_paused(PAUSE_DEPOSITS)?;
+
         let depositor = &mut ctx.accounts.authority;
         let deposit = &mut ctx.accounts.deposit_account;
       
@@ -412,35 +1256,48 @@ pub mod nexfin {
         let temp_pda_token = &mut ctx.accounts.user_token;
         let temp_governance_token = &mut ctx.accounts.user_gov_token;
         let token_mint = &mut ctx.accounts.token_mint;
+        let stability_pool = &mut ctx.accounts.stability_pool;
+        stability_pool.issue_governance(Clock::get()?.unix_timestamp)?;
 
         if deposit.is_initialized {
-            deposit.token_amount = deposit.token_amount.checked_add(amount).ok_or(NexfinError::MathOverflow)?;
+            stability_pool.settle(deposit)?;
         } else {
+            deposit.version = ACCOUNT_VERSION;
             deposit.bump = deposit_account_bump;
             deposit.is_initialized = true;
-            deposit.token_amount = amount;
+            deposit.token_amount = 0;
             deposit.reward_token_amount = 0;
             deposit.reward_governance_token_amount = 0;
             deposit.reward_coin_amount = 0;
-            deposit.bank = temp_pda_token.key();
             deposit.governance_bank = temp_governance_token.key();
             deposit.authority = *depositor.key;
+            deposit.snapshot = stability_pool.snapshot();
         }
+        deposit.bank = ctx.accounts.deposit_vault.key();
 
-        // let amount_to_burn = amount * 1_000_000_000;
-        let amount_to_burn = amount * 1_00;
-    
-        let burn_ctx = CpiContext::new(
+        stability_pool.add_to_deposit(deposit, amount)?;
+
+        let amount_to_transfer = StableAmount::from_mint(amount, token_mint)?.base_units();
+
+        let transfer_ctx = CpiContext::new(
             ctx.accounts.token_program.clone(),
-            Burn {
+            Transfer {
+                from: temp_pda_token.to_account_info(),
+                to: ctx.accounts.deposit_vault.to_account_info(),
                 authority: depositor.to_account_info(),
-                mint: token_mint.to_account_info(),
-                to: temp_pda_token.to_account_info(),
             },
         );
 
-        msg!("Calling the token program to transfer tokens to the escrow's initializer...");
-        token::burn(burn_ctx, amount_to_burn)?;
+        msg!("Calling the token program to transfer tokens to the deposit vault...");
+        token::transfer(transfer_ctx, amount_to_transfer)?;
+
+        check_deposit_vault(&mut ctx.accounts.deposit_vault, stability_pool, token_mint)?;
+
+        emit!(DepositAdded {
+            authority: deposit.authority,
+            amount,
+            token_amount: deposit.token_amount,
+        });
 
         Ok(())
     }
@@ -451,33 +1308,46 @@ pub mod nexfin {
     ///
     /// 0. `[signer]` The account of the person taking the trade
     /// 1. `[writable]` The Deposit account
-    pub fn withdraw_deposit(ctx: Context<WithdrawDeposit>, amount: u64, mint_account_bump: u8, _deposit_account_bump: u8) -> ProgramResult {
+    /// 2. `[writable]` The Stability pool
+    /// 3. `[writable]` The Deposit vault
+    /// 4. `[]` Config account
+    pub fn withdraw_deposit(ctx: Context<WithdrawDeposit>, amount: u64, _deposit_account_bump: u8) -> ProgramResult {
+        ctx.accounts.config.check_not_paused(PAUSE_WITHDRAWALS)?;
+
         let deposit = &mut ctx.accounts.deposit;
+        let stability_pool = &mut ctx.accounts.stability_pool;
 
-        if amount > deposit.token_amount {
-            return Err(NexfinError::AttemptToWithdrawTooMuch.into());
-        }
+        // the deposit shrinks by its share of the liquidations since the last snapshot
+        stability_pool.issue_governance(Clock::get()?.unix_timestamp)?;
+        stability_pool.settle(deposit)?;
+
+        stability_pool.withdraw_from_deposit(deposit, amount)?;
 
-        // Mint
+        // Transfer out of the vault
         let seeds:&[&[u8]; 2] = &[
-            b"mint-authority",
-            &[mint_account_bump]
+            b"stabilitypool",
+            &[stability_pool.bump]
         ];
         let signer = &[&seeds[..]];
-        let cpi_accounts = MintTo {
-            mint: ctx.accounts.stable_coin.to_account_info(),
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.deposit_vault.to_account_info(),
             to: ctx.accounts.user_token.to_account_info(),
-            authority: ctx.accounts.token_authority.to_account_info(),
+            authority: stability_pool.to_account_info(),
         };
 
         let cpi_program = ctx.accounts.token_program.to_account_info();
         let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
 
-        // token::mint_to(cpi_ctx, amount.checked_mul(1_000_000_000).ok_or(NexfinError::MathOverflow)?)?;
-        token::mint_to(cpi_ctx, amount.checked_mul(1_00).ok_or(NexfinError::MathOverflow)?)?;
+        let amount_to_transfer = StableAmount::from_mint(amount, &ctx.accounts.stable_coin)?.base_units();
+        token::transfer(cpi_ctx, amount_to_transfer)?;
+
+        check_deposit_vault(&mut ctx.accounts.deposit_vault, stability_pool, &ctx.accounts.stable_coin)?;
 
-        deposit.token_amount = deposit.token_amount.checked_sub(amount).ok_or(NexfinError::MathOverflow)?;
-        msg!("the new deposit token amount is {}", deposit.token_amount);
+        emit!(DepositWithdrawn {
+            authority: deposit.authority,
+            amount,
+            token_amount: deposit.token_amount,
+        });
 
         Ok(())
     }
@@ -488,10 +1358,21 @@ pub mod nexfin {
     ///
     /// 0. `[signer]` The account of the person taking the trade
     /// 1. `[writable]` The Deposit account
-    //TODO: change the pool share and token deposit after the claim depending on the sol received
-    pub fn claim_deposit_reward(ctx: Context<ClaimDepositReward>, mint_account_bump: u8, _deposit_account_bump: u8, _reward_vault_bump: u8) -> ProgramResult {
+    /// 2. `[writable]` The Stability pool
+    /// 3. `[writable]` The Reward vault
+    /// 4. `[writable]` The Governance mint
+    /// 5. `[writable]` User governance token acc, the deposit governance bank
+    /// 6. `[]` System program
+    /// 7. `[]` Config account
+    pub fn claim_deposit_reward(ctx: Context<ClaimDepositReward>, mint_account_bump: u8, _deposit_account_bump: u8, reward_vault_bump: u8) -> ProgramResult {
+        ctx.accounts.config.check_not_paused(PAUSE_WITHDRAWALS)?;
+
         let deposit = &mut ctx.accounts.deposit;
 
+        // credit the rewards and the governance emission accrued since the last snapshot
+        ctx.accounts.stability_pool.issue_governance(Clock::get()?.unix_timestamp)?;
+        ctx.accounts.stability_pool.settle(deposit)?;
+
         let seeds:&[&[u8]; 2] = &[
             b"mint-authority",
             &[mint_account_bump]
@@ -508,58 +1389,389 @@ pub mod nexfin {
 
         msg!("User token reward is {}", deposit.reward_token_amount);
         msg!("User coin reward is {}", deposit.reward_coin_amount);
+        msg!("User governance reward is {}", deposit.reward_governance_token_amount);
+
+        // the stable coin rewards are recorded in hundredths of a coin
+        let reward_to_mint = StableAmount::from_scaled(deposit.reward_token_amount, REWARD_DECIMALS, ctx.accounts.stable_coin.decimals)?;
+        token::mint_to(cpi_ctx, reward_to_mint.base_units())?;
+
+        // the governance rewards are recorded in base units of the governance mint
+        if deposit.reward_governance_token_amount > 0 {
+            let cpi_accounts = MintTo {
+                mint: ctx.accounts.governance_mint.to_account_info(),
+                to: ctx.accounts.user_governance_token.to_account_info(),
+                authority: ctx.accounts.token_authority.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+            token::mint_to(cpi_ctx, deposit.reward_governance_token_amount)?;
+        }
+
+        // the vault stays rent exempt, anything above the minimum belongs to the depositors
+        let reward_vault = &ctx.accounts.reward_coin_vault;
+        let available_lamports = reward_vault.lamports().saturating_sub(Rent::get()?.minimum_balance(0));
+        if deposit.reward_coin_amount > available_lamports {
+            return Err(NexfinError::InsufficientLiquidity.into());
+        }
 
-        token::mint_to(cpi_ctx, deposit.reward_token_amount.checked_mul(10_000_000).ok_or(NexfinError::MathOverflow)?)?;
+        if deposit.reward_coin_amount > 0 {
+            let seeds:&[&[u8]; 2] = &[
+                b"rewardVault",
+                &[reward_vault_bump]
+            ];
+            invoke_signed(
+                &system_instruction::transfer(
+                    reward_vault.key,
+                    ctx.accounts.authority.key,
+                    deposit.reward_coin_amount
+                ),
+                &[
+                    reward_vault.clone(),
+                    ctx.accounts.authority.clone(),
+                    ctx.accounts.system_program.to_account_info().clone()
+                ],
+                &[&seeds[..]]
+            )?;
+        }
 
-        //TODO add sol rewards from the reward coin vault
+        emit!(RewardClaimed {
+            authority: deposit.authority,
+            coin_amount: deposit.reward_coin_amount,
+            token_amount: reward_to_mint.base_units(),
+            governance_amount: deposit.reward_governance_token_amount,
+            collateral_mint: None,
+            collateral_amount: 0,
+        });
 
-        deposit.reward_governance_token_amount = 0; // not finalised yet on this !!!
+        deposit.reward_governance_token_amount = 0; // governance emission set to zero after withdrawl
         deposit.reward_token_amount = 0; // stable coin reward from the borrow fees set to zero after withdrawl
         deposit.reward_coin_amount = 0;  // sol rewards from the liquidated trove fees set to zero after withdrawl
 
         Ok(())
     }
 
+    /// Claim collateral reward
+    ///
+    /// Pays the depositor its share of a collateral token seized by the liquidations.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the person taking the trade
+    /// 1. `[writable]` The Deposit account
+    /// 2. `[writable]` The Stability pool
+    /// 3. `[]` Config account
+    /// 4. `[]` The collateral mint
+    /// 5. `[writable]` The pool collateral vault
+    /// 6. `[writable]` User collateral token acc
+    /// 7. `[]` Token program
+    pub fn claim_collateral_reward(ctx: Context<ClaimCollateralReward>, _deposit_account_bump: u8, _pool_collateral_vault_bump: u8) -> ProgramResult {
+        ctx.accounts.config.check_not_paused(PAUSE_WITHDRAWALS)?;
+
+        let deposit = &mut ctx.accounts.deposit;
+        let stability_pool = &mut ctx.accounts.stability_pool;
+
+        stability_pool.issue_governance(Clock::get()?.unix_timestamp)?;
+        stability_pool.settle(deposit)?;
+
+        let (index, _) = ctx.accounts.config.get_collateral_type(&ctx.accounts.collateral_mint.key())?;
+        let reward = deposit.reward_collateral_amounts[index];
+        msg!("User collateral reward is {}", reward);
+
+        if reward > 0 {
+            let seeds:&[&[u8]; 2] = &[
+                b"stabilitypool",
+                &[stability_pool.bump]
+            ];
+            let signer = &[&seeds[..]];
+            let transfer_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.clone(),
+                Transfer {
+                    from: ctx.accounts.pool_collateral_vault.to_account_info(),
+                    to: ctx.accounts.user_collateral.to_account_info(),
+                    authority: stability_pool.to_account_info(),
+                },
+                signer,
+            );
+            token::transfer(transfer_ctx, reward)?;
+        }
+
+        deposit.reward_collateral_amounts[index] = 0;
+
+        emit!(RewardClaimed {
+            authority: deposit.authority,
+            coin_amount: 0,
+            token_amount: 0,
+            governance_amount: 0,
+            collateral_mint: Some(ctx.accounts.collateral_mint.key()),
+            collateral_amount: reward,
+        });
+        Ok(())
+    }
+
     /// Trove received
     ///
+    /// The liquidation keeper or the admin queues an undercollateralized trove for liquidation,
+    /// recording the price it was seen at. The trove can be liquidated once the grace period
+    /// of the protocol params has elapsed. A queued trove back above the liquidation ratio
+    /// leaves the queue, as it does after any trove instruction leaving it healthy.
     ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` Sys acc
-    /// 1. `[writable]` The Trove account
+    /// 0. `[signer]` The liquidation keeper or the admin
+    /// 1. `[writable]` Config account
+    /// 2. `[writable]` The Trove account
+    /// 3. `[writable]` The Trove manager account
+    /// 4. `[writable]` The depositors and team Fee accounts
+    /// 5. `[]` Pyth SOL price account
     pub fn receive_trove(ctx: Context<ReceiveTrove>, _trove_account: Pubkey) -> ProgramResult {
+        let authority = ctx.accounts.authority.key;
+        if *authority != ctx.accounts.config.liquidation_keeper && !is_admin(authority, &ctx.accounts.config, ctx.program_id) {
+            return Err(NexfinError::OnlyForAdmin.into());
+        }
+        ctx.accounts.config.check_not_paused(PAUSE_LIQUIDATIONS)?;
+
         let trove =  &mut ctx.accounts.trove;
         if trove.is_liquidated {
             return Err(NexfinError::TroveAlreadyLiquidated.into());
         }
-        trove.is_received = true;
 
+        // the trove is queued on its debt with the accrued interest
+        accrue_trove_interest(trove, &mut ctx.accounts.config, &mut ctx.accounts.fee_account, &mut ctx.accounts.team_fee_account, ctx.accounts.clock.unix_timestamp)?;
+        let config = &ctx.accounts.config;
+
+        let sol_price = get_sol_price(&ctx.accounts.pyth_sol_account, &ctx.accounts.secondary_price, config, &ctx.accounts.clock)?.price;
+        let trove_token_lamports = get_trove_token_lamports(trove, config, ctx.remaining_accounts, sol_price, &ctx.accounts.clock)?;
+        ctx.accounts.trove_manager.update_trove(trove, trove_token_lamports)?;
+        let liquidation_ratio = ctx.accounts.trove_manager.get_liquidation_ratio(sol_price, &config.params)?;
+        let (_, token_lamports) = get_token_collateral_lamports(trove, config, ctx.remaining_accounts, sol_price, liquidation_ratio, &ctx.accounts.clock)?;
+
+        let collateral_lamports = trove.lamports_amount.checked_add(token_lamports).ok_or(NexfinError::MathOverflow)?;
+        let collateral_ratio = get_collateral_ratio(collateral_lamports, trove.amount_to_close, sol_price)?;
+        msg!("Trove collateral ratio is {}", collateral_ratio);
+
+        let received_price: u64 = sol_price.try_into().map_err(|_| NexfinError::MathOverflow)?;
+
+        if collateral_ratio >= liquidation_ratio {
+            // a stale entry is dropped, the trove gets a new grace period when it falls again
+            if trove.is_received {
+                msg!("Trove is back above the liquidation ratio");
+                trove.dequeue();
+                emit!(TroveReceived {
+                    keeper: *authority,
+                    authority: trove.authority,
+                    collateral_ratio,
+                    sol_price: received_price,
+                    is_received: false,
+                });
+                return Ok(());
+            }
+            return Err(NexfinError::ObligationHealthy.into());
+        }
+
+        // the grace period runs from the first time the trove was queued
+        if trove.is_received {
+            msg!("Trove was received at {}", trove.received_at);
+            return Ok(());
+        }
+
+        trove.is_received = true;
+        trove.received_at = ctx.accounts.clock.unix_timestamp;
+        trove.received_price = received_price;
+
+        emit!(TroveReceived {
+            keeper: *authority,
+            authority: trove.authority,
+            collateral_ratio,
+            sol_price: received_price,
+            is_received: true,
+        });
         Ok(())
     }
 
-    /// Set Deposit reward
+    /// Add Deposit reward
     ///
+    /// Distribute the rewards over every deposit of the stability pool
     ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` Sys acc
-    /// 1. `[writable]` The Deposit account
+    /// 0. `[signer]` Admin
+    /// 1. `[writable]` The Stability pool
     pub fn add_deposit_reward(
         ctx: Context<AddDepositReward>,
         coin: u64,
         governance: u64,
         token: u64,
     ) -> ProgramResult {
-        let deposit =  &mut ctx.accounts.deposit;
+        ctx.accounts.stability_pool.distribute(coin, token, governance)?;
+
+        emit!(DepositRewardAdded {
+            authority: ctx.accounts.authority.key(),
+            coin,
+            token,
+            governance,
+        });
+        Ok(())
+    }
+
+    /// Fund reward vault
+    ///
+    /// Anyone can sweep the SOL fee income of the depositors Fee account into the reward vault,
+    /// the lamports are distributed over the stability pool and paid out by `claim_deposit_reward`
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` Anyone
+    /// 1. `[writable]` The depositors Fee account
+    /// 2. `[writable]` The Reward vault
+    /// 3. `[writable]` The Stability pool
+    pub fn fund_reward_vault(ctx: Context<FundRewardVault>, _reward_vault_bump: u8) -> ProgramResult {
+        let fee = &mut ctx.accounts.fee_account;
+        let stability_pool = &mut ctx.accounts.stability_pool;
+
+        // keep the income in the fee account until there is someone to pay it to
+        if stability_pool.total_deposits == 0 {
+            return Ok(());
+        }
+
+        let fee_info = fee.to_account_info();
+        let rent_exempt_lamports = Rent::get()?.minimum_balance(fee_info.data_len());
+        let lamports = std::cmp::min(fee.sol_amount, fee_info.lamports().saturating_sub(rent_exempt_lamports));
+        if lamports == 0 {
+            return Ok(());
+        }
+
+        **fee_info.try_borrow_mut_lamports()? -= lamports;
+        **ctx.accounts.reward_coin_vault.try_borrow_mut_lamports()? += lamports;
+
+        fee.sol_amount = fee.sol_amount.checked_sub(lamports).ok_or(NexfinError::MathOverflow)?;
+        stability_pool.distribute(lamports, 0, 0)?;
+
+        msg!("Swept fee income is {} lamports", lamports);
+        emit!(RewardVaultFunded {
+            authority: ctx.accounts.authority.key(),
+            lamports,
+        });
+        Ok(())
+    }
+
+    /// Distribute fee income
+    ///
+    /// Anyone can move the depositors share of the accrued interest into the stability pool
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` Anyone
+    /// 1. `[writable]` The depositors Fee account
+    /// 2. `[writable]` The Stability pool
+    pub fn distribute_fee_income(ctx: Context<DistributeFeeIncome>) -> ProgramResult {
+        let fee = &mut ctx.accounts.fee_account;
+        let stability_pool = &mut ctx.accounts.stability_pool;
+
+        // keep the income in the fee account until there is someone to pay it to
+        if fee.token_amount == 0 || stability_pool.total_deposits == 0 {
+            return Ok(());
+        }
+
+        let token = StableAmount::from_scaled(fee.token_amount, 0, REWARD_DECIMALS)?.base_units();
+        stability_pool.distribute(0, token, 0)?;
+
+        msg!("Distributed fee income is {}", fee.token_amount);
+        emit!(FeeIncomeDistributed {
+            authority: ctx.accounts.authority.key(),
+            amount: fee.token_amount,
+        });
+        fee.token_amount = 0;
+
+        Ok(())
+    }
+
+    /// Flash mint
+    ///
+    /// Mints `amount` stable coins to the user token account and calls the receiver program with
+    /// `data` and the remaining accounts. The amount plus the flash mint fee is burned back from
+    /// the user token account once the receiver returns, the fee is credited to the depositors.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the person taking the trade
+    /// 1. `[writable]` User token acc
+    /// 2. `[writable]` Mint Token key
+    /// 3. `[writable]` The depositors Fee account
+    /// 4. `[]` Config account
+    /// 5. `[]` The receiver program
+    /// 6. `[]` Token program
+    pub fn flash_mint(ctx: Context<FlashMint>, amount: u64, data: Vec<u8>, mint_account_bump: u8) -> ProgramResult {
+        let receiver_program = &ctx.accounts.receiver_program;
+        if !receiver_program.executable || *receiver_program.key == *ctx.program_id || *receiver_program.key == spl_token::ID {
+            return Err(NexfinError::InvalidFlashLoanReceiverProgram.into());
+        }
+
+        if amount == 0 {
+            return Err(NexfinError::InvalidAmount.into());
+        }
+
+        ctx.accounts.config.check_not_paused(PAUSE_BORROWING)?;
+
+        let params = ctx.accounts.config.params;
+        check_debt_ceiling(&ctx.accounts.stable_coin, amount, &params)?;
+
+        let fee = get_flash_mint_fee(amount, &params)?;
+        let amount_to_mint = StableAmount::from_mint(amount, &ctx.accounts.stable_coin)?.base_units();
+        let amount_to_burn = StableAmount::from_mint(amount.checked_add(fee).ok_or(NexfinError::MathOverflow)?, &ctx.accounts.stable_coin)?.base_units();
+
+        let seeds:&[&[u8]; 2] = &[
+            b"mint-authority",
+            &[mint_account_bump]
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_accounts = MintTo {
+            mint: ctx.accounts.stable_coin.to_account_info(),
+            to: ctx.accounts.user_token.to_account_info(),
+            authority: ctx.accounts.token_authority.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+        token::mint_to(cpi_ctx, amount_to_mint)?;
+
+        msg!("Calling the flash mint receiver {}", receiver_program.key);
+        let instruction = Instruction {
+            program_id: *receiver_program.key,
+            accounts: ctx.remaining_accounts
+                .iter()
+                .map(|account| AccountMeta {
+                    pubkey: *account.key,
+                    is_signer: account.is_signer,
+                    is_writable: account.is_writable,
+                })
+                .collect(),
+            data,
+        };
+        let mut account_infos = ctx.remaining_accounts.to_vec();
+        account_infos.push(receiver_program.clone());
+        invoke(&instruction, &account_infos)?;
+
+        ctx.accounts.user_token.reload()?;
+        if ctx.accounts.user_token.amount < amount_to_burn {
+            msg!("User token holds {}, {} must be burned back", ctx.accounts.user_token.amount, amount_to_burn);
+            return Err(NexfinError::NotEnoughLiquidityAfterFlashLoan.into());
+        }
+
+        let burn_ctx = CpiContext::new(
+            ctx.accounts.token_program.clone(),
+            Burn {
+                authority: ctx.accounts.authority.to_account_info(),
+                mint: ctx.accounts.stable_coin.to_account_info(),
+                to: ctx.accounts.user_token.to_account_info(),
+            },
+        );
+        token::burn(burn_ctx, amount_to_burn)?;
 
-        deposit.reward_coin_amount = deposit.reward_coin_amount.checked_add(coin).ok_or(NexfinError::MathOverflow)?;
-        deposit.reward_governance_token_amount = deposit.reward_governance_token_amount.checked_add(governance).ok_or(NexfinError::MathOverflow)?;
-        deposit.reward_token_amount = deposit.reward_token_amount.checked_add(token).ok_or(NexfinError::MathOverflow)?;
+        let fee_account = &mut ctx.accounts.fee_account;
+        fee_account.token_amount = fee_account.token_amount.checked_add(fee).ok_or(NexfinError::MathOverflow)?;
+        msg!("Flash minted {}, fee is {}", amount, fee);
 
         Ok(())
     }
 
-    /// Burn amount  * 1_000_000_000 from user_token
+    /// Burn amount of stable coins from user_token
     /// Update Trove
     ///
     ///
@@ -567,24 +1779,30 @@ pub mod nexfin {
     ///
     /// 0. `[signer]` The account of the person taking the trade
     /// 1. `[writable]` The Trove account
-    /// 2. `[]` Token program
-    /// 3. `[]` User token acc
-    /// 4. `[]` Mint Token key
+    /// 2. `[]` Pyth SOL price account
+    /// 3. `[]` Token program
+    /// 4. `[]` User token acc
+    /// 5. `[]` Mint Token key
+    ///
+    /// The remaining accounts are the pyth price accounts of the collateral types the trove holds,
+    /// in the order of the config.
     pub fn update_trove(ctx: Context<UpdateTrove>, amount: u64) -> ProgramResult {
         let borrower = &ctx.accounts.authority;
         let user_token =  &mut ctx.accounts.user_token;
         let mint_token =  &mut ctx.accounts.token_mint;
         let trove =  &mut ctx.accounts.trove;
 
+        accrue_trove_interest(trove, &mut ctx.accounts.config, &mut ctx.accounts.fee_account, &mut ctx.accounts.team_fee_account, ctx.accounts.clock.unix_timestamp)?;
+
         // update the amount to close price
         trove.amount_to_close = (trove.amount_to_close).checked_sub(amount).ok_or(NexfinError::MathOverflow)?;
+        check_min_debt(trove.amount_to_close, &ctx.accounts.config.params)?;
 
         msg!("the amount is {}", amount);
         msg!("amount to close is {}", trove.amount_to_close);
         msg!("Calling the token program to transfer tokens to the escrow's initializer...");
 
-        // let amount_to_burn = amount * 1_000_000_000;
-        let amount_to_burn = amount * 1_00; // TODO only on devnet for 2 decimal token
+        let amount_to_burn = StableAmount::from_mint(amount, mint_token)?.base_units();
         let burn_ctx = CpiContext::new(
             ctx.accounts.token_program.clone(),
             Burn {
@@ -595,62 +1813,874 @@ pub mod nexfin {
         );
         token::burn(burn_ctx, amount_to_burn)?;
 
+        let sol_price = get_sol_price(&ctx.accounts.pyth_sol_account, &ctx.accounts.secondary_price, &ctx.accounts.config, &ctx.accounts.clock)?.price;
+        let trove_token_lamports = get_trove_token_lamports(trove, &ctx.accounts.config, ctx.remaining_accounts, sol_price, &ctx.accounts.clock)?;
+        ctx.accounts.trove_manager.update_trove(trove, trove_token_lamports)?;
+        dequeue_healthy_trove(trove, &ctx.accounts.trove_manager, &ctx.accounts.config, ctx.remaining_accounts, sol_price, &ctx.accounts.clock)?;
+
+        emit!(TroveAdjusted::new(trove));
         Ok(())
     }
 
-    pub fn load_price(ctx: Context<LoadPrice>, _bump: u8) -> ProgramResult {
+    /// Load price
+    ///
+    /// Crank copying the secondary pyth feed into the program owned price account,
+    /// used as a fallback when the primary feed is stale or halted.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The crank paying for the price account
+    /// 1. `[]` The secondary pyth price account
+    /// 2. `[writable]` The program owned price account
+    pub fn load_price(ctx: Context<LoadPrice>, bump: u8) -> ProgramResult {
         msg!("Calling load price");
-        let oracle = &ctx.accounts.price;
-        let price_oracle = Price::load(&oracle).unwrap();
+        let secondary_price = &mut ctx.accounts.secondary_price;
+        let pyth_price = load_pyth_price(&ctx.accounts.price, &ctx.accounts.config.secondary_price_source)?;
+        check_sol_price_expo(pyth_price.expo)?;
+
+        // only move forward, an older publish would roll the fallback price back
+        if pyth_price.pub_slot < secondary_price.pub_slot {
+            return Err(NexfinError::ReserveStale.into());
+        }
+
+        secondary_price.bump = bump;
+        secondary_price.price = pyth_price.price;
+        secondary_price.conf = pyth_price.conf;
+        secondary_price.pub_slot = pyth_price.pub_slot;
 
-        msg!("Price_oracle price {:?}", price_oracle.agg.price);
+        msg!("Price_oracle price {:?}", secondary_price.price);
 
         Ok(())
     }
 
-    pub fn set_price(ctx: Context<SetPrice>, price: i64) -> ProgramResult {
-        let oracle = &ctx.accounts.price;
-        let mut price_oracle = Price::load(&oracle).unwrap();
-        price_oracle.agg.price = price as i64;
-        Ok(())
+    /// Set price
+    ///
+    /// Moves the mock price account of `feed`, created on the first call, so integration tests
+    /// can simulate price movements. Only compiled with the `test-oracle` feature, see
+    /// `set_mock_price`.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The admin authority
+    /// 1. `[]` The Admin account
+    /// 2. `[]` The Config account
+    /// 3. `[writable]` The mock price account
+    /// 4. `[]` System program
+    pub fn set_price(ctx: Context<SetPrice>, feed: Pubkey, bump: u8, price: i64, conf: u64, pub_slot: u64, is_trading: bool) -> ProgramResult {
+        set_mock_price(ctx, feed, bump, price, conf, pub_slot, is_trading)
     }
 
+    /// Create the config singleton, only the upgrade authority of the program can call it so the
+    /// deployment cannot be front-run
     pub fn initialize_config(ctx: Context<InitializeConfig>, _config_account_bump: u8, bump:u8, is_initialized:bool, mint_account_authority: Pubkey, admin_account_authority: Pubkey) -> ProgramResult {
+        check_upgrade_authority(ctx.accounts.authority.key, &ctx.accounts.program_data, ctx.program_id)?;
+
+        ctx.accounts.config_account.version = ACCOUNT_VERSION;
         ctx.accounts.config_account.bump = bump;
         ctx.accounts.config_account.is_initialized = is_initialized;
         ctx.accounts.config_account.mint_account_authority = mint_account_authority;
         ctx.accounts.config_account.admin_account_authority = admin_account_authority;        
+        ctx.accounts.config_account.params = ProtocolParams::default();
+        ctx.accounts.config_account.cumulative_interest_index = DECIMAL_PRECISION;
+        ctx.accounts.config_account.last_interest_update = Clock::get()?.unix_timestamp;
+
+        emit_config_changed(&ctx.accounts.authority, ConfigChange::Initialized { mint_account_authority, admin_account_authority });
         Ok(())
     }
 
-    pub fn change_config(ctx: Context<ChangeConfig>, is_initialized:bool, mint_account_authority: Pubkey, admin_account_authority: Pubkey) -> ProgramResult {
+    /// The admin is changed with `propose_admin` and `accept_admin`
+    pub fn change_config(ctx: Context<ChangeConfig>, is_initialized:bool, mint_account_authority: Pubkey) -> ProgramResult {
         ctx.accounts.config_account.is_initialized = is_initialized;
         ctx.accounts.config_account.mint_account_authority = mint_account_authority;
-        ctx.accounts.config_account.admin_account_authority = admin_account_authority;        
+
+        emit_config_changed(&ctx.accounts.authority, ConfigChange::Config { is_initialized, mint_account_authority });
         Ok(())
     }
 
-    pub fn initialize_admin(ctx: Context<InitializeAdmin>, admin_account_bump:u8, admin_account_authority: Pubkey) -> ProgramResult {
-        ctx.accounts.admin_account.bump = admin_account_bump;
-        ctx.accounts.admin_account.authority = admin_account_authority;        
+    /// Propose `pending_admin` as the next admin, it takes over once it calls `accept_admin`.
+    /// Proposing the default pubkey cancels the pending proposal.
+    pub fn propose_admin(ctx: Context<ChangeConfig>, pending_admin: Pubkey) -> ProgramResult {
+        ctx.accounts.config_account.propose_admin(pending_admin);
+
+        emit!(AdminProposed {
+            authority: ctx.accounts.authority.key(),
+            pending_admin,
+        });
+        emit_config_changed(&ctx.accounts.authority, ConfigChange::PendingAdmin { pending_admin });
         Ok(())
     }
 
-    pub fn initialize_token_acc(_ctx: Context<InitializeTokenAcc>, _mint_account_bump: u8) -> ProgramResult {
+    /// Accept admin
+    ///
+    /// The pending admin takes over the config. Its admin account is created if needed, paid by
+    /// the payer so that the admin can be the PDA of a multisig program signing through CPI.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The pending admin
+    /// 1. `[signer, writable]` The payer of the admin account
+    /// 2. `[writable]` The admin account of the pending admin
+    /// 3. `[writable]` Config account
+    /// 4. `[]` System program
+    pub fn accept_admin(ctx: Context<AcceptAdmin>, admin_account_bump: u8) -> ProgramResult {
+        let config = &mut ctx.accounts.config;
+        let authority = ctx.accounts.authority.key();
+
+        let admin_account = &mut ctx.accounts.admin_account;
+        let previous_admin_account = config.accept_admin(&authority, admin_account.key())?;
+
+        admin_account.bump = admin_account_bump;
+        admin_account.authority = authority;
+
+        emit!(AdminAccepted {
+            authority,
+            admin_account: admin_account.key(),
+            previous_admin_account,
+        });
+        emit_config_changed(&ctx.accounts.authority, ConfigChange::Admin { admin_account_authority: config.admin_account_authority });
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-pub struct SetPrice<'info> {
-    #[account(mut)]
-    pub price: AccountInfo<'info>,
-}
+    /// Update the protocol parameters, rejected when a value is outside of its allowed range
+    pub fn update_params(ctx: Context<ChangeConfig>, params: ProtocolParams) -> ProgramResult {
+        params.validate()?;
 
-#[derive(Accounts)]
-#[instruction(bump: u8)]
+        // settle the interest at the previous rate
+        ctx.accounts.config_account.accrue_interest(Clock::get()?.unix_timestamp)?;
+
+        ctx.accounts.config_account.params = params;
+        msg!("Protocol params updated {:?}", params);
+
+        emit_config_changed(&ctx.accounts.authority, ConfigChange::Params { params });
+        Ok(())
+    }
+
+    /// Change the pyth price accounts and the price validation thresholds
+    ///
+    /// `max_price_staleness` is in slots, `max_price_confidence` and `max_price_deviation`
+    /// are in basis points of the price
+    pub fn change_oracle_config(
+        ctx: Context<ChangeConfig>,
+        pyth_sol_account: Pubkey,
+        secondary_price_source: Pubkey,
+        max_price_staleness: u64,
+        max_price_confidence: u64,
+        max_price_deviation: u64,
+    ) -> ProgramResult {
+        if max_price_confidence == 0 || max_price_confidence > 10_000 || max_price_deviation == 0 {
+            return Err(NexfinError::InvalidOracleConfig.into());
+        }
+
+        if pyth_sol_account == secondary_price_source {
+            return Err(NexfinError::InvalidOracleConfig.into());
+        }
+
+        ctx.accounts.config_account.pyth_sol_account = pyth_sol_account;
+        ctx.accounts.config_account.secondary_price_source = secondary_price_source;
+        ctx.accounts.config_account.max_price_staleness = max_price_staleness;
+        ctx.accounts.config_account.max_price_confidence = max_price_confidence;
+        ctx.accounts.config_account.max_price_deviation = max_price_deviation;
+
+        emit_config_changed(&ctx.accounts.authority, ConfigChange::Oracle {
+            pyth_sol_account,
+            secondary_price_source,
+            max_price_staleness,
+            max_price_confidence,
+            max_price_deviation,
+        });
+        Ok(())
+    }
+
+    /// Change the keeper allowed to queue troves for liquidation next to the admin
+    pub fn change_liquidation_keeper(ctx: Context<ChangeConfig>, liquidation_keeper: Pubkey) -> ProgramResult {
+        ctx.accounts.config_account.liquidation_keeper = liquidation_keeper;
+
+        emit_config_changed(&ctx.accounts.authority, ConfigChange::LiquidationKeeper { liquidation_keeper });
+        Ok(())
+    }
+
+    /// Change the guardian allowed to pause the protocol next to the admin
+    pub fn change_guardian(ctx: Context<ChangeConfig>, guardian: Pubkey) -> ProgramResult {
+        ctx.accounts.config_account.guardian = guardian;
+
+        emit_config_changed(&ctx.accounts.authority, ConfigChange::Guardian { guardian });
+        Ok(())
+    }
+
+    /// Set the whole pause bitmask, only the admin can unpause
+    pub fn set_pause(ctx: Context<ChangeConfig>, paused: u8) -> ProgramResult {
+        if paused & !PAUSE_ALL != 0 {
+            return Err(NexfinError::InvalidConfig.into());
+        }
+        ctx.accounts.config_account.paused = paused;
+        msg!("Pause flags set to {:#06b}", paused);
+
+        emit_config_changed(&ctx.accounts.authority, ConfigChange::Paused { paused });
+        Ok(())
+    }
+
+    /// Pause
+    ///
+    /// Adds `flags` to the pause bitmask, callable by the guardian or the admin. Nothing can be
+    /// unpaused here, see `set_pause`.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The guardian or the admin
+    /// 1. `[writable]` Config account
+    pub fn pause(ctx: Context<Pause>, flags: u8) -> ProgramResult {
+        let config = &mut ctx.accounts.config;
+        let authority = ctx.accounts.authority.key;
+        let is_guardian = config.guardian != Pubkey::default() && *authority == config.guardian;
+        if !is_guardian && !is_admin(authority, config, ctx.program_id) {
+            return Err(NexfinError::OnlyForGuardian.into());
+        }
+
+        if flags == 0 || flags & !PAUSE_ALL != 0 {
+            return Err(NexfinError::InvalidConfig.into());
+        }
+        config.paused |= flags;
+        msg!("Pause flags set to {:#06b}", config.paused);
+
+        emit_config_changed(&ctx.accounts.authority, ConfigChange::Paused { paused: config.paused });
+        Ok(())
+    }
+
+    /// Register a collateral token for the troves with its pyth price account, max LTV and
+    /// liquidation threshold in basis points, the seized collateral of the liquidations is kept
+    /// in the pool collateral vault
+    pub fn add_collateral_type(ctx: Context<AddCollateralType>, max_ltv: u64, liquidation_threshold: u64, pool_collateral_vault_bump: u8) -> ProgramResult {
+        let config = &mut ctx.accounts.config;
+        let mint = ctx.accounts.collateral_mint.key();
+
+        if config.get_collateral_type(&mint).is_ok() {
+            return Err(NexfinError::AlreadyInitialized.into());
+        }
+
+        let oracle = ctx.accounts.collateral_oracle.key();
+        load_pyth_price(&ctx.accounts.collateral_oracle, &oracle)?;
+
+        let collateral_type = CollateralType {
+            mint,
+            oracle,
+            decimals: ctx.accounts.collateral_mint.decimals,
+            vault_bump: pool_collateral_vault_bump,
+            max_ltv,
+            liquidation_threshold,
+        };
+        collateral_type.validate()?;
+
+        let index = config.collateral_types.iter().position(|c| !c.is_registered()).ok_or(NexfinError::InvalidConfig)?;
+        config.collateral_types[index] = collateral_type;
+
+        emit_config_changed(&ctx.accounts.authority, ConfigChange::CollateralType { index: index as u8, collateral_type });
+        Ok(())
+    }
+
+    /// Change the pyth price account, max LTV and liquidation threshold of a collateral type
+    pub fn change_collateral_type(ctx: Context<ChangeConfig>, mint: Pubkey, oracle: Pubkey, max_ltv: u64, liquidation_threshold: u64) -> ProgramResult {
+        let config = &mut ctx.accounts.config_account;
+        let (index, mut collateral_type) = config.get_collateral_type(&mint)?;
+
+        collateral_type.oracle = oracle;
+        collateral_type.max_ltv = max_ltv;
+        collateral_type.liquidation_threshold = liquidation_threshold;
+        collateral_type.validate()?;
+
+        config.collateral_types[index] = collateral_type;
+
+        emit_config_changed(&ctx.accounts.authority, ConfigChange::CollateralType { index: index as u8, collateral_type });
+        Ok(())
+    }
+
+    pub fn initialize_admin(ctx: Context<InitializeAdmin>, admin_account_bump:u8, admin_account_authority: Pubkey) -> ProgramResult {
+        ctx.accounts.admin_account.bump = admin_account_bump;
+        ctx.accounts.admin_account.authority = admin_account_authority;        
+        Ok(())
+    }
+
+    pub fn initialize_trove_manager(ctx: Context<InitializeTroveManager>, trove_manager_bump: u8) -> ProgramResult {
+        let trove_manager = &mut ctx.accounts.trove_manager;
+        trove_manager.bump = trove_manager_bump;
+        trove_manager.base_rate = 0;
+        trove_manager.last_fee_operation_time = ctx.accounts.clock.unix_timestamp;
+        trove_manager.total_collateral = 0;
+        trove_manager.total_debt = 0;
+        trove_manager.troves = Vec::new();
+        Ok(())
+    }
+
+    pub fn initialize_stability_pool(ctx: Context<InitializeStabilityPool>, stability_pool_bump: u8, deposit_vault_bump: u8) -> ProgramResult {
+        let stability_pool = &mut ctx.accounts.stability_pool;
+        stability_pool.bump = stability_pool_bump;
+        stability_pool.deposit_vault_bump = deposit_vault_bump;
+        stability_pool.governance_mint = ctx.accounts.governance_mint.key();
+        stability_pool.total_deposits = 0;
+        stability_pool.p = DECIMAL_PRECISION;
+        stability_pool.current_scale = 0;
+        stability_pool.current_epoch = 0;
+        stability_pool.sums = Vec::new();
+        Ok(())
+    }
+
+    /// Start the governance emission of the stability pool, `supply_cap` base units of the
+    /// governance mint are issued over time, decaying by `issuance_factor` every minute
+    /// (zero for a yearly halving)
+    pub fn set_governance_emission(ctx: Context<SetGovernanceEmission>, supply_cap: u64, issuance_factor: u128) -> ProgramResult {
+        let issuance_factor = if issuance_factor == 0 { GOVERNANCE_ISSUANCE_FACTOR } else { issuance_factor };
+        if issuance_factor >= DECIMAL_PRECISION {
+            return Err(NexfinError::InvalidConfig.into());
+        }
+
+        let stability_pool = &mut ctx.accounts.stability_pool;
+        let now = ctx.accounts.clock.unix_timestamp;
+
+        // the emission already accrued keeps the old curve
+        stability_pool.issue_governance(now)?;
+        if supply_cap < stability_pool.total_governance_issued {
+            return Err(NexfinError::InvalidConfig.into());
+        }
+
+        if stability_pool.emission_start_time == 0 {
+            stability_pool.emission_start_time = now;
+        }
+        stability_pool.governance_supply_cap = supply_cap;
+        stability_pool.governance_issuance_factor = issuance_factor;
+
+        emit!(GovernanceEmissionSet {
+            authority: ctx.accounts.authority.key(),
+            supply_cap,
+            issuance_factor,
+        });
+        Ok(())
+    }
+
+    /// Migrate Trove
+    ///
+    /// Upgrades a trove written with an older layout to the current one in place, callable by the
+    /// trove owner or the admin. The signer pays the rent of the reallocated account.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The trove owner or the admin
+    /// 1. `[writable]` The Trove account
+    /// 2. `[]` Config account
+    /// 3. `[]` System program
+    pub fn migrate_trove(ctx: Context<MigrateTrove>) -> ProgramResult {
+        let trove_info = &ctx.accounts.trove;
+        let (trove, is_outdated) = load_versioned_account::<Trove>(trove_info, ctx.program_id)?;
+        if !is_outdated {
+            msg!("Trove is already at version {}", trove.version);
+            return Ok(());
+        }
+
+        let authority = ctx.accounts.authority.key;
+        if *authority != trove.authority && !is_admin(authority, &ctx.accounts.config, ctx.program_id) {
+            return Err(NexfinError::OnlyForTroveOwner.into());
+        }
+
+        let trove_key = Pubkey::create_program_address(&[b"borrowertrove".as_ref(), trove.authority.as_ref(), &[trove.bump]], ctx.program_id)?;
+        if trove_key != *trove_info.key {
+            return Err(NexfinError::InvalidAccountInput.into());
+        }
+
+        write_migrated_account(trove_info, &trove, &ctx.accounts.authority.to_account_info(), &ctx.accounts.system_program.to_account_info())
+    }
+
+    /// Migrate Deposit
+    ///
+    /// Upgrades a deposit written with an older layout to the current one in place, callable by
+    /// the depositor or the admin. The signer pays the rent of the reallocated account.
+    /// The deposits made before the stability pool are added to the pool deposits and snapshot
+    /// the current pool state. Their coins were burned by the legacy `add_deposit`, they are
+    /// minted back into the deposit vault so the pool can pay them out.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The depositor or the admin
+    /// 1. `[writable]` The Deposit account
+    /// 2. `[writable]` The Stability pool
+    /// 3. `[writable]` The Deposit vault
+    /// 4. `[writable]` Mint Token key
+    /// 5. `[]` The mint authority
+    /// 6. `[]` Config account
+    /// 7. `[]` System program
+    /// 8. `[]` Token program
+    pub fn migrate_deposit(ctx: Context<MigrateDeposit>, mint_account_bump: u8) -> ProgramResult {
+        let deposit_info = &ctx.accounts.deposit;
+        let (mut deposit, is_outdated) = load_versioned_account::<Deposit>(deposit_info, ctx.program_id)?;
+        if !is_outdated {
+            msg!("Deposit is already at version {}", deposit.version);
+            return Ok(());
+        }
+
+        let authority = ctx.accounts.authority.key;
+        if *authority != deposit.authority && !is_admin(authority, &ctx.accounts.config, ctx.program_id) {
+            return Err(NexfinError::OnlyForDepositOwner.into());
+        }
+
+        let deposit_key = Pubkey::create_program_address(&[b"deposit".as_ref(), deposit.authority.as_ref(), &[deposit.bump]], ctx.program_id)?;
+        if deposit_key != *deposit_info.key {
+            return Err(NexfinError::InvalidAccountInput.into());
+        }
+
+        // the legacy deposits predate the pool, they join it with a fresh snapshot
+        let stability_pool = &mut ctx.accounts.stability_pool;
+        stability_pool.issue_governance(Clock::get()?.unix_timestamp)?;
+        let legacy_amount = stability_pool.add_legacy_deposit(&mut deposit)?;
+
+        if legacy_amount > 0 {
+            let seeds: &[&[u8]; 2] = &[b"mint-authority", &[mint_account_bump]];
+            let mint_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.clone(),
+                MintTo {
+                    mint: ctx.accounts.stable_coin.to_account_info(),
+                    to: ctx.accounts.deposit_vault.to_account_info(),
+                    authority: ctx.accounts.token_authority.to_account_info(),
+                },
+                &[&seeds[..]],
+            );
+            token::mint_to(mint_ctx, StableAmount::from_mint(legacy_amount, &ctx.accounts.stable_coin)?.base_units())?;
+
+            check_deposit_vault(&mut ctx.accounts.deposit_vault, &ctx.accounts.stability_pool, &ctx.accounts.stable_coin)?;
+
+            emit!(DepositAdded {
+                authority: deposit.authority,
+                amount: legacy_amount,
+                token_amount: deposit.token_amount,
+            });
+        }
+
+        write_migrated_account(deposit_info, &deposit, &ctx.accounts.authority.to_account_info(), &ctx.accounts.system_program.to_account_info())
+    }
+
+    /// Migrate Config
+    ///
+    /// Moves the per-authority config of the first deployments, at `[b"config", authority]`, into
+    /// the config singleton and closes it, it has to be migrated before the troves and deposits.
+    /// Anyone could create such a config, so the upgrade authority of the program co-signs and
+    /// pays for the singleton. The admin becomes the admin account of the authority.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The authority which created the legacy config
+    /// 1. `[signer, writable]` The upgrade authority of the program
+    /// 2. `[]` The Admin account of the authority
+    /// 3. `[writable]` The legacy config account
+    /// 4. `[writable]` The config singleton
+    /// 5. `[]` The program data account of the program
+    /// 6. `[]` System program
+    pub fn migrate_config(ctx: Context<MigrateConfig>, config_account_bump: u8) -> ProgramResult {
+        check_upgrade_authority(ctx.accounts.upgrade_authority.key, &ctx.accounts.program_data, ctx.program_id)?;
+
+        let legacy_config_info = &ctx.accounts.legacy_config;
+        let (legacy_config, _) = load_versioned_account::<Config>(legacy_config_info, ctx.program_id)?;
+
+        let legacy_config_key = Pubkey::create_program_address(&[b"config".as_ref(), ctx.accounts.authority.key.as_ref(), &[legacy_config.bump]], ctx.program_id)?;
+        if legacy_config_key != *legacy_config_info.key {
+            return Err(NexfinError::InvalidAccountInput.into());
+        }
+
+        let config = &mut ctx.accounts.config;
+        **config = legacy_config;
+        config.bump = config_account_bump;
+        config.admin_account_authority = ctx.accounts.admin_account_authority.key();
+        config.cumulative_interest_index = DECIMAL_PRECISION;
+        config.last_interest_update = Clock::get()?.unix_timestamp;
+
+        // close the legacy config, its rent goes to the upgrade authority which paid the singleton
+        let upgrade_authority = ctx.accounts.upgrade_authority.to_account_info();
+        **upgrade_authority.try_borrow_mut_lamports()? += legacy_config_info.lamports();
+        **legacy_config_info.try_borrow_mut_lamports()? = 0;
+        legacy_config_info.try_borrow_mut_data()?.fill(0);
+
+        emit_config_changed(&ctx.accounts.authority, ConfigChange::Migrated {
+            legacy_config: *legacy_config_info.key,
+            admin_account_authority: config.admin_account_authority,
+        });
+        Ok(())
+    }
+
+    /// Migrate Trove manager
+    ///
+    /// Rebuilds the sorted list and the total collateral and debt of the trove manager from the
+    /// troves of its list, for a trove manager written before it tracked the totals or whose
+    /// totals drifted from the troves. The admin pays the rent of the reallocated account.
+    ///
+    /// The list entries carry the trove collateral and debt since the totals were added, which
+    /// lowered `MAX_SORTED_TROVES` from 200 to 150 to keep the account under 10KB. A list over
+    /// the new capacity fails with `TroveListFull`.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The admin authority
+    /// 1. `[]` The Admin account
+    /// 2. `[]` Config account
+    /// 3. `[writable]` The Trove manager account
+    /// 4. `[]` Pyth SOL price account
+    /// 5. `[]` The clock sysvar
+    /// 6. `[]` System program
+    ///
+    /// The remaining accounts are, for every trove in the list in any order, its Trove account
+    /// followed by the pyth price accounts of the collateral types it holds.
+    pub fn migrate_trove_manager(ctx: Context<MigrateTroveManager>) -> ProgramResult {
+        let trove_manager_info = &ctx.accounts.trove_manager;
+        if trove_manager_info.owner != ctx.program_id {
+            return Err(NexfinError::InvalidAccountOwner.into());
+        }
+
+        let (mut trove_manager, authorities) = migration::load_trove_manager(&trove_manager_info.try_borrow_data()?)?;
+        let trove_manager_key = Pubkey::create_program_address(&[b"trovemanager".as_ref(), &[trove_manager.bump]], ctx.program_id)?;
+        if trove_manager_key != *trove_manager_info.key {
+            return Err(NexfinError::InvalidAccountInput.into());
+        }
+
+        let sol_price = get_sol_price(&ctx.accounts.pyth_sol_account, &ctx.accounts.secondary_price, &ctx.accounts.config, &ctx.accounts.clock)?.price;
+
+        let mut rebuilt: Vec<Pubkey> = Vec::with_capacity(authorities.len());
+        let mut trove_accounts = ctx.remaining_accounts;
+        while !trove_accounts.is_empty() {
+            let trove_info = &trove_accounts[0];
+            let (trove, _) = load_versioned_account::<Trove>(trove_info, ctx.program_id)?;
+            let trove_key = Pubkey::create_program_address(&[b"borrowertrove".as_ref(), trove.authority.as_ref(), &[trove.bump]], ctx.program_id)?;
+            if trove_key != *trove_info.key || !authorities.contains(&trove.authority) || rebuilt.contains(&trove.authority) {
+                return Err(NexfinError::InvalidAccountInput.into());
+            }
+
+            let held_collaterals = trove.collaterals.iter().filter(|amount| **amount > 0).count();
+            if trove_accounts.len() < 1 + held_collaterals {
+                return Err(NexfinError::InvalidAccountInput.into());
+            }
+            let oracle_infos = &trove_accounts[1..1 + held_collaterals];
+            trove_accounts = &trove_accounts[1 + held_collaterals..];
+
+            let trove_token_lamports = get_trove_token_lamports(&trove, &ctx.accounts.config, oracle_infos, sol_price, &ctx.accounts.clock)?;
+            trove_manager.update_trove(&trove, trove_token_lamports)?;
+            rebuilt.push(trove.authority);
+        }
+
+        // every listed trove exactly once
+        if rebuilt.len() != authorities.len() {
+            return Err(NexfinError::InvalidAccountInput.into());
+        }
+
+        resize_account(trove_manager_info, TroveManager::LEN + 8, &ctx.accounts.authority.to_account_info(), &ctx.accounts.system_program.to_account_info())?;
+
+        let mut data = trove_manager_info.try_borrow_mut_data()?;
+        let mut writer: &mut [u8] = &mut data[..];
+        trove_manager.try_serialize(&mut writer)?;
+
+        msg!("Rebuilt {} troves, total collateral {} and total debt {}", trove_manager.troves.len(), trove_manager.total_collateral, trove_manager.total_debt);
+        Ok(())
+    }
+
+    /// Upgrade the depositors or the team fee account written with an older layout
+    pub fn migrate_fee(ctx: Context<MigrateFee>) -> ProgramResult {
+        let fee_info = &ctx.accounts.fee_account;
+        let (fee, is_outdated) = load_versioned_account::<Fee>(fee_info, ctx.program_id)?;
+        if !is_outdated {
+            return Ok(());
+        }
+
+        let is_fee_account = [b"fee".as_ref(), b"teamfee".as_ref()].iter().any(|seed| {
+            Pubkey::create_program_address(&[*seed, &[fee.bump]], ctx.program_id).map_or(false, |key| key == *fee_info.key)
+        });
+        if !is_fee_account {
+            return Err(NexfinError::InvalidAccountInput.into());
+        }
+
+        write_migrated_account(fee_info, &fee, &ctx.accounts.authority.to_account_info(), &ctx.accounts.system_program.to_account_info())
+    }
+
+    pub fn initialize_token_acc(_ctx: Context<InitializeTokenAcc>, _mint_account_bump: u8) -> ProgramResult {
+        Ok(())
+    }
+}
+
+/// Accrue the global interest index and add the interest accrued since the trove snapshot to its
+/// debt, the interest is credited to the depositors and team fee accounts
+fn accrue_trove_interest(trove: &mut Trove, config: &mut Config, fee: &mut Fee, team_fee: &mut Fee, now: i64) -> ProgramResult {
+    config.accrue_interest(now)?;
+
+    if trove.interest_index == 0 || trove.amount_to_close == 0 {
+        trove.interest_index = config.cumulative_interest_index;
+        return Ok(());
+    }
+
+    let interest = get_accrued_interest(trove.amount_to_close, config.cumulative_interest_index, trove.interest_index)?;
+    // keep the snapshot until a whole stable coin has accrued
+    if interest == 0 {
+        return Ok(());
+    }
+
+    trove.amount_to_close = trove.amount_to_close.checked_add(interest).ok_or(NexfinError::MathOverflow)?;
+    trove.interest_index = config.cumulative_interest_index;
+
+    let fee_floor = get_fee_floor(&config.params)?;
+    let depositors_interest = if fee_floor == 0 {
+        interest
+    } else {
+        get_pro_rata_share(interest, config.params.deposit_fee, fee_floor)?
+    };
+
+    fee.token_amount = fee.token_amount.checked_add(depositors_interest).ok_or(NexfinError::MathOverflow)?;
+    team_fee.token_amount = team_fee.token_amount.checked_add(interest - depositors_interest).ok_or(NexfinError::MathOverflow)?;
+
+    msg!("Accrued interest is {}", interest);
+    Ok(())
+}
+
+#[cfg(feature = "test-oracle")]
+fn set_mock_price(ctx: Context<SetPrice>, _feed: Pubkey, bump: u8, price: i64, conf: u64, pub_slot: u64, is_trading: bool) -> ProgramResult {
+    let mock_price = &mut ctx.accounts.price;
+    mock_price.bump = bump;
+    mock_price.price = price;
+    mock_price.conf = conf;
+    mock_price.expo = -8;
+    mock_price.pub_slot = pub_slot;
+    mock_price.is_trading = is_trading;
+
+    msg!("Mock price is {} with confidence {} at slot {}", price, conf, pub_slot);
+    Ok(())
+}
+
+/// Anchor 0.18 dispatches every handler of the program, without the `test-oracle` feature
+/// `set_price` keeps its signature but none of the mock oracle is compiled in
+#[cfg(not(feature = "test-oracle"))]
+fn set_mock_price(_ctx: Context<SetPrice>, _feed: Pubkey, _bump: u8, _price: i64, _conf: u64, _pub_slot: u64, _is_trading: bool) -> ProgramResult {
+    Err(NexfinError::InvalidInstruction.into())
+}
+
+/// Check that `authority` is the upgrade authority of the program, read from its program data
+/// account of the upgradeable loader
+fn check_upgrade_authority(authority: &Pubkey, program_data: &AccountInfo, program_id: &Pubkey) -> ProgramResult {
+    let (program_data_key, _) = Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+    if *program_data.key != program_data_key || *program_data.owner != bpf_loader_upgradeable::id() {
+        return Err(NexfinError::InvalidAccountInput.into());
+    }
+
+    let upgrade_authority = match limited_deserialize(&program_data.try_borrow_data()?) {
+        Ok(UpgradeableLoaderState::ProgramData { upgrade_authority_address, .. }) => upgrade_authority_address,
+        _ => return Err(NexfinError::InvalidAccountInput.into()),
+    };
+
+    if upgrade_authority != Some(*authority) {
+        return Err(NexfinError::OnlyForAdmin.into());
+    }
+
+    Ok(())
+}
+
+/// Whether `authority` is the admin of the config, which holds the admin PDA of the authority.
+/// The authority can be a keypair or the PDA of a multisig program signing through CPI.
+fn is_admin(authority: &Pubkey, config: &Config, program_id: &Pubkey) -> bool {
+    let (admin_account, _) = Pubkey::find_program_address(&[b"admin".as_ref(), authority.as_ref()], program_id);
+    admin_account == config.admin_account_authority
+}
+
+/// Log the fields changed by an admin for the indexers
+fn emit_config_changed(authority: &Signer, change: ConfigChange) {
+    emit!(ConfigChanged {
+        authority: authority.key(),
+        change,
+    });
+}
+
+/// Check that minting `borrow_amount` keeps the stable coin supply under the debt ceiling
+fn check_debt_ceiling(stable_coin: &Mint, borrow_amount: u64, params: &ProtocolParams) -> ProgramResult {
+    let amount_to_mint = StableAmount::from_mint(borrow_amount, stable_coin)?.base_units();
+    let supply = stable_coin.supply.checked_add(amount_to_mint).ok_or(NexfinError::MathOverflow)?;
+
+    if supply > StableAmount::from_mint(params.debt_ceiling, stable_coin)?.base_units() {
+        return Err(NexfinError::BorrowTooLarge.into());
+    }
+
+    Ok(())
+}
+
+/// Check that the deposit vault holds at least the deposits recorded by the stability pool,
+/// anyone can transfer coins into the vault and the excess is kept as a donation to the pool
+fn check_deposit_vault<'info>(deposit_vault: &mut Account<'info, TokenAccount>, stability_pool: &StabilityPool, stable_coin: &Mint) -> ProgramResult {
+    deposit_vault.reload()?;
+
+    let recorded_amount = StableAmount::from_mint(stability_pool.total_deposits, stable_coin)?.base_units();
+    if deposit_vault.amount < recorded_amount {
+        msg!("Deposit vault holds {}, the pool records {} coins", deposit_vault.amount, stability_pool.total_deposits);
+        return Err(NexfinError::ExpectedAmountMismatch.into());
+    }
+
+    Ok(())
+}
+
+/// Take a queued trove out of the liquidation queue once an instruction leaves it above the
+/// liquidation ratio. `oracle_infos` are the pyth price accounts of the collateral types held
+/// by the trove, in the order of the config.
+fn dequeue_healthy_trove(trove: &mut Trove, trove_manager: &TroveManager, config: &Config, oracle_infos: &[AccountInfo], sol_price: u128, clock: &Clock) -> ProgramResult {
+    if !trove.is_received {
+        return Ok(());
+    }
+
+    let liquidation_ratio = trove_manager.get_liquidation_ratio(sol_price, &config.params)?;
+    let (_, token_lamports) = get_token_collateral_lamports(trove, config, oracle_infos, sol_price, liquidation_ratio, clock)?;
+    let collateral_lamports = trove.lamports_amount.checked_add(token_lamports).ok_or(NexfinError::MathOverflow)?;
+
+    if get_collateral_ratio(collateral_lamports, trove.amount_to_close, sol_price)? >= liquidation_ratio {
+        msg!("Trove is back above the liquidation ratio");
+        trove.dequeue();
+    }
+
+    Ok(())
+}
+
+/// Lamports the token collateral of the trove counts for next to its SOL, weighted by the max LTV
+/// at the collateral ratio for the borrows and by the liquidation threshold at the
+/// `liquidation_ratio` for the liquidations
+///
+/// One pyth account per collateral type held by the trove is expected in `oracle_infos`, in the
+/// order of the config.
+fn get_token_collateral_lamports(trove: &Trove, config: &Config, oracle_infos: &[AccountInfo], sol_price: u128, liquidation_ratio: u64, clock: &Clock) -> Result<(u64, u64), ProgramError> {
+    let mut oracle_infos = oracle_infos.iter();
+    let mut borrow_lamports: u64 = 0;
+    let mut liquidation_lamports: u64 = 0;
+
+    for (index, amount) in trove.collaterals.iter().enumerate() {
+        if *amount == 0 {
+            continue;
+        }
+
+        let collateral_type = &config.collateral_types[index];
+        let oracle_info = oracle_infos.next().ok_or(NexfinError::InvalidAccountInput)?;
+        let (price, expo) = get_collateral_price(oracle_info, collateral_type, config, clock)?;
+        let lamports = get_collateral_lamports(*amount, collateral_type.decimals, price, expo, sol_price)?;
+
+        borrow_lamports = borrow_lamports
+            .checked_add(get_weighted_collateral_lamports(lamports, collateral_type.max_ltv, config.params.collateral_ratio)?)
+            .ok_or(NexfinError::MathOverflow)?;
+        liquidation_lamports = liquidation_lamports
+            .checked_add(get_weighted_collateral_lamports(lamports, collateral_type.liquidation_threshold, liquidation_ratio)?)
+            .ok_or(NexfinError::MathOverflow)?;
+    }
+
+    Ok((borrow_lamports, liquidation_lamports))
+}
+
+/// Lamports the token collateral of the trove counts for in the trove manager, weighted by the
+/// liquidation threshold at the liquidation ratio of the params whatever the recovery mode
+fn get_trove_token_lamports(trove: &Trove, config: &Config, oracle_infos: &[AccountInfo], sol_price: u128, clock: &Clock) -> Result<u64, ProgramError> {
+    let (_, token_lamports) = get_token_collateral_lamports(trove, config, oracle_infos, sol_price, config.params.liquidation_ratio, clock)?;
+    Ok(token_lamports)
+}
+
+/// Read a program account in any of its layouts, returns the account in the current layout and
+/// whether it still has to be migrated
+fn load_versioned_account<T: Versioned>(account_info: &AccountInfo, program_id: &Pubkey) -> Result<(T, bool), ProgramError> {
+    if account_info.owner != program_id {
+        return Err(NexfinError::InvalidAccountOwner.into());
+    }
+
+    let data = account_info.try_borrow_data()?;
+    load_versioned(&data)
+}
+
+/// Reallocate an outdated account to the space of the current layout and write `account` to it,
+/// the rent exemption of the larger account is topped up by `payer` which signed the migration
+fn write_migrated_account<'info, T: Versioned>(account_info: &AccountInfo<'info>, account: &T, payer: &AccountInfo<'info>, system_program: &AccountInfo<'info>) -> ProgramResult {
+    resize_account(account_info, T::SPACE, payer, system_program)?;
+
+    let mut data = account_info.try_borrow_mut_data()?;
+    let mut writer: &mut [u8] = &mut data[..];
+    account.try_serialize(&mut writer)?;
+
+    msg!("Migrated {} to version {}", account_info.key, ACCOUNT_VERSION);
+    emit!(AccountMigrated {
+        authority: *payer.key,
+        account: *account_info.key,
+        version: ACCOUNT_VERSION,
+    });
+    Ok(())
+}
+
+/// Resize an account to `space`, the payer tops up its rent
+fn resize_account<'info>(account_info: &AccountInfo<'info>, space: usize, payer: &AccountInfo<'info>, system_program: &AccountInfo<'info>) -> ProgramResult {
+    let rent_exempt_lamports = Rent::get()?.minimum_balance(space);
+    let lamports = account_info.lamports();
+    if lamports < rent_exempt_lamports {
+        invoke(
+            &system_instruction::transfer(
+                payer.key,
+                account_info.key,
+                rent_exempt_lamports - lamports
+            ),
+            &[
+                payer.clone(),
+                account_info.clone(),
+                system_program.clone()
+            ]
+        )?;
+    }
+
+    account_info.realloc(space, true)
+}
+
+/// Load a trove passed as a remaining account and check it is the borrower's PDA
+fn load_trove<'info>(trove_info: &AccountInfo<'info>, program_id: &Pubkey) -> Result<Account<'info, Trove>, ProgramError> {
+    if !trove_info.is_writable {
+        return Err(NexfinError::InvalidAccountInput.into());
+    }
+
+    let trove: Account<'info, Trove> = Account::try_from(trove_info)?;
+    let trove_key = Pubkey::create_program_address(
+        &[b"borrowertrove".as_ref(), trove.authority.to_bytes().as_ref(), &[trove.bump]],
+        program_id,
+    )?;
+
+    if trove_key != *trove_info.key {
+        return Err(NexfinError::InvalidAccountInput.into());
+    }
+
+    Ok(trove)
+}
+
+#[cfg(feature = "test-oracle")]
+#[derive(Accounts)]
+#[instruction(feed: Pubkey, bump: u8)]
+pub struct SetPrice<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(has_one = authority, seeds = [b"admin".as_ref(), authority.key().as_ref()], bump = admin_account_authority.bump)]
+    pub admin_account_authority: Account<'info, Admin>,
+
+    #[account(has_one = admin_account_authority, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(init_if_needed, seeds = [b"mockprice".as_ref(), feed.as_ref()], bump = bump, payer = authority, space = state::MockPrice::LEN + 8)]
+    pub price: Account<'info, state::MockPrice>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg(not(feature = "test-oracle"))]
+#[derive(Accounts)]
+pub struct SetPrice<'info> {
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(bump: u8)]
 pub struct LoadPrice<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
     pub price: AccountInfo<'info>,
+
+    #[account(init_if_needed, seeds = [b"price".as_ref()], bump = bump, payer = authority, space = state::Price::LEN + 8)]
+    pub secondary_price: Account<'info, state::Price>,
+
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
@@ -662,23 +2692,95 @@ pub struct AddDepositReward<'info> {
     #[account(mut, has_one = authority, seeds = [b"admin".as_ref(), authority.key().as_ref()], bump = admin_account_authority.bump)]
     pub admin_account_authority: Account<'info, Admin>,
 
-    #[account(mut, has_one = admin_account_authority, seeds = [b"config".as_ref(), admin_account_authority.key().to_bytes().as_ref()], bump = config.bump)]
+    #[account(mut, has_one = admin_account_authority, seeds = [b"config".as_ref()], bump = config.bump)]
     pub config: Account<'info, Config>,
 
-    #[account(mut, seeds = [b"deposit", deposit.authority.to_bytes().as_ref()], bump = deposit.bump)]
-    pub deposit: ProgramAccount<'info, Deposit>,
+    #[account(mut, seeds = [b"stabilitypool".as_ref()], bump = stability_pool.bump)]
+    pub stability_pool: Account<'info, StabilityPool>,
+}
+
+#[derive(Accounts)]
+#[instruction(reward_vault_bump: u8)]
+pub struct FundRewardVault<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(mut, seeds = [b"fee".as_ref()], bump = fee_account.bump)]
+    pub fee_account: Account<'info, Fee>,
+
+    #[account(mut, seeds = [b"rewardVault".as_ref()], bump = reward_vault_bump)]
+    pub reward_coin_vault: AccountInfo<'info>,
+
+    #[account(mut, seeds = [b"stabilitypool".as_ref()], bump = stability_pool.bump)]
+    pub stability_pool: Account<'info, StabilityPool>,
 }
+
+#[derive(Accounts)]
+pub struct DistributeFeeIncome<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(mut, seeds = [b"fee".as_ref()], bump = fee_account.bump)]
+    pub fee_account: Account<'info, Fee>,
+
+    #[account(mut, seeds = [b"stabilitypool".as_ref()], bump = stability_pool.bump)]
+    pub stability_pool: Account<'info, StabilityPool>,
+}
+#[derive(Accounts)]
+#[instruction(amount: u64, data: Vec<u8>, mint_account_bump: u8)]
+pub struct FlashMint<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(mut, constraint = user_token.owner == authority.key() && user_token.mint == stable_coin.key())]
+    pub user_token: Account<'info, TokenAccount>,
+
+    #[account(
+        seeds=[
+            b"mint-authority".as_ref()
+        ],
+        bump = mint_account_bump
+    )]
+    pub token_authority: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub stable_coin: Account<'info, Mint>,
+
+    #[account(mut, seeds = [b"fee".as_ref()], bump = fee_account.bump)]
+    pub fee_account: Account<'info, Fee>,
+
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    pub receiver_program: AccountInfo<'info>,
+
+    #[account(address = spl_token::ID)]
+    pub token_program: AccountInfo<'info>,
+}
+
 #[derive(Accounts)]
 #[instruction(trove_account: Pubkey)]
 pub struct ReceiveTrove<'info> {
-    // #[account(mut)]
-    // pub admin_account_authority: Signer<'info>,
+    pub authority: Signer<'info>,
 
-    // #[account(mut, has_one = admin_account_authority, seeds = [b"config".as_ref(), admin_account_authority.key().to_bytes().as_ref()], bump = config.bump)]
-    // pub config: Account<'info, Config>,
+    #[account(mut, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
 
     #[account(mut, seeds = [b"borrowertrove".as_ref(), trove_account.key().to_bytes().as_ref()], bump = trove.bump)]
     pub trove: ProgramAccount<'info, Trove>,
+
+    #[account(mut, seeds = [b"trovemanager".as_ref()], bump = trove_manager.bump)]
+    pub trove_manager: Account<'info, TroveManager>,
+
+    #[account(mut, seeds = [b"fee".as_ref()], bump = fee_account.bump)]
+    pub fee_account: Account<'info, Fee>,
+
+    #[account(mut, seeds = [b"teamfee".as_ref()], bump = team_fee_account.bump)]
+    pub team_fee_account: Account<'info, Fee>,
+
+    pub pyth_sol_account: AccountInfo<'info>,
+
+    #[account(seeds = [b"price".as_ref()], bump = secondary_price.bump)]
+    pub secondary_price: Account<'info, state::Price>,
+
+    pub clock: Sysvar<'info, Clock>,
 }
 
 #[derive(Accounts)]
@@ -691,6 +2793,9 @@ pub struct ClaimDepositReward<'info> {
     #[account(mut, has_one = authority, seeds = [b"deposit".as_ref(), authority.key().to_bytes().as_ref()], bump = deposit_account_bump)]
     pub deposit: ProgramAccount<'info, Deposit>,
 
+    #[account(mut, seeds = [b"stabilitypool".as_ref()], bump = stability_pool.bump)]
+    pub stability_pool: Account<'info, StabilityPool>,
+
     #[account(
         seeds=[
             b"mint-authority"
@@ -705,6 +2810,12 @@ pub struct ClaimDepositReward<'info> {
     #[account(mut)]
     pub user_token_account: Account<'info, TokenAccount>,
 
+    #[account(mut, address = stability_pool.governance_mint)]
+    pub governance_mint: Account<'info, Mint>,
+
+    #[account(mut, address = deposit.governance_bank)]
+    pub user_governance_token: Account<'info, TokenAccount>,
+
     #[account(address = spl_token::ID)]
     pub token_program: AccountInfo<'info>,
 
@@ -712,10 +2823,39 @@ pub struct ClaimDepositReward<'info> {
     pub reward_coin_vault: AccountInfo<'info>,
 
     pub system_program: Program<'info, System>,
+
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(deposit_account_bump: u8, pool_collateral_vault_bump: u8)]
+pub struct ClaimCollateralReward<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(mut, has_one = authority, seeds = [b"deposit".as_ref(), authority.key().to_bytes().as_ref()], bump = deposit_account_bump)]
+    pub deposit: ProgramAccount<'info, Deposit>,
+
+    #[account(mut, seeds = [b"stabilitypool".as_ref()], bump = stability_pool.bump)]
+    pub stability_pool: Account<'info, StabilityPool>,
+
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    pub collateral_mint: Account<'info, Mint>,
+
+    #[account(mut, seeds = [b"poolcollateral".as_ref(), collateral_mint.key().as_ref()], bump = pool_collateral_vault_bump)]
+    pub pool_collateral_vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user_collateral: Account<'info, TokenAccount>,
+
+    #[account(address = spl_token::ID)]
+    pub token_program: AccountInfo<'info>,
 }
 
 #[derive(Accounts)]
-#[instruction(amount: u64, mint_account_bump: u8, deposit_account_bump: u8)]
+#[instruction(amount: u64, deposit_account_bump: u8)]
 pub struct WithdrawDeposit<'info> {
     #[account(mut)]
     pub authority: Signer<'info>,
@@ -723,15 +2863,12 @@ pub struct WithdrawDeposit<'info> {
     #[account(mut, has_one = authority, seeds = [b"deposit".as_ref(),authority.key().to_bytes().as_ref()], bump = deposit_account_bump)]
     pub deposit: ProgramAccount<'info, Deposit>,
 
-    #[account(
-        seeds=[
-            b"mint-authority"
-        ],
-        bump = mint_account_bump
-    )]
-    pub token_authority: AccountInfo<'info>,
+    #[account(mut, seeds = [b"stabilitypool".as_ref()], bump = stability_pool.bump)]
+    pub stability_pool: Account<'info, StabilityPool>,
+
+    #[account(mut, seeds = [b"depositvault".as_ref()], bump = stability_pool.deposit_vault_bump)]
+    pub deposit_vault: Account<'info, TokenAccount>,
 
-    #[account(mut)]
     pub stable_coin: Account<'info, Mint>,
 
     #[account(mut)]
@@ -740,6 +2877,8 @@ pub struct WithdrawDeposit<'info> {
     #[account(address = spl_token::ID)]
     pub token_program: AccountInfo<'info>,
 
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
 }
 
 #[derive(Accounts)]
@@ -760,6 +2899,12 @@ pub struct AddDeposit<'info> {
     )]
     pub deposit_account: Account<'info, Deposit>,
 
+    #[account(mut, seeds = [b"stabilitypool".as_ref()], bump = stability_pool.bump)]
+    pub stability_pool: Account<'info, StabilityPool>,
+
+    #[account(mut, seeds = [b"depositvault".as_ref()], bump = stability_pool.deposit_vault_bump)]
+    pub deposit_vault: Account<'info, TokenAccount>,
+
     pub rent: Sysvar<'info, Rent>,
 
     #[account(address = spl_token::ID)]
@@ -775,6 +2920,9 @@ pub struct AddDeposit<'info> {
     pub token_mint: Account<'info, Mint>,
 
     pub system_program: Program<'info, System>,
+
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
 }
 
 #[derive(Accounts)]
@@ -782,45 +2930,279 @@ pub struct AddCoin<'info> {
     #[account(signer, mut)]
     pub authority: AccountInfo<'info>,
 
-    #[account(mut, seeds = [b"borrowertrove".as_ref(),authority.key().to_bytes().as_ref()], bump = trove.bump)]
-    pub trove: ProgramAccount<'info, Trove>,
+    #[account(mut, seeds = [b"borrowertrove".as_ref(),authority.key().to_bytes().as_ref()], bump = trove.bump)]
+    pub trove: ProgramAccount<'info, Trove>,
+
+    #[account(mut, seeds = [b"trovemanager".as_ref()], bump = trove_manager.bump)]
+    pub trove_manager: Account<'info, TroveManager>,
+
+    #[account(mut, seeds = [b"fee".as_ref()], bump = fee_account.bump)]
+    pub fee_account: Account<'info, Fee>,
+
+    #[account(mut, seeds = [b"teamfee".as_ref()], bump = team_fee_account.bump)]
+    pub team_fee_account: Account<'info, Fee>,
+
+    #[account(mut, seeds = [b"solTrove".as_ref(), authority.key().to_bytes().as_ref()], bump = trove.sol_bump)]
+    pub sol_trove: AccountInfo<'info>,
+
+    pub pyth_sol_account: AccountInfo<'info>,
+
+    #[account(seeds = [b"price".as_ref()], bump = secondary_price.bump)]
+    pub secondary_price: Account<'info, state::Price>,
+
+    #[account(mut, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    pub clock: Sysvar<'info, Clock>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ReconcileTrove<'info> {
+    #[account(seeds = [b"borrowertrove".as_ref(), trove.authority.key().to_bytes().as_ref()], bump = trove.bump)]
+    pub trove: ProgramAccount<'info, Trove>,
+
+    #[account(seeds = [b"solTrove".as_ref(), trove.authority.key().to_bytes().as_ref()], bump = trove.sol_bump)]
+    pub sol_trove: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, collateral_vault_bump: u8)]
+pub struct DepositCollateral<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(mut, has_one = authority, seeds = [b"borrowertrove".as_ref(), authority.key().to_bytes().as_ref()], bump = trove.bump)]
+    pub trove: ProgramAccount<'info, Trove>,
+
+    #[account(mut, seeds = [b"trovemanager".as_ref()], bump = trove_manager.bump)]
+    pub trove_manager: Account<'info, TroveManager>,
+
+    #[account(mut, seeds = [b"fee".as_ref()], bump = fee_account.bump)]
+    pub fee_account: Account<'info, Fee>,
+
+    #[account(mut, seeds = [b"teamfee".as_ref()], bump = team_fee_account.bump)]
+    pub team_fee_account: Account<'info, Fee>,
+
+    #[account(mut, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    pub collateral_mint: Account<'info, Mint>,
+
+    // the token collateral of the trove, only the trove can move it
+    #[account(
+        init_if_needed,
+        seeds = [b"collateralvault".as_ref(), authority.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = collateral_vault_bump,
+        payer = authority,
+        token::mint = collateral_mint,
+        token::authority = trove
+    )]
+    pub collateral_vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user_collateral: Account<'info, TokenAccount>,
+
+    pub pyth_sol_account: AccountInfo<'info>,
+
+    #[account(seeds = [b"price".as_ref()], bump = secondary_price.bump)]
+    pub secondary_price: Account<'info, state::Price>,
+
+    pub clock: Sysvar<'info, Clock>,
+
+    pub system_program: Program<'info, System>,
+
+    #[account(address = spl_token::ID)]
+    pub token_program: AccountInfo<'info>,
+
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, collateral_vault_bump: u8)]
+pub struct WithdrawCollateral<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(mut, has_one = authority, seeds = [b"borrowertrove".as_ref(), authority.key().to_bytes().as_ref()], bump = trove.bump)]
+    pub trove: ProgramAccount<'info, Trove>,
+
+    #[account(mut, seeds = [b"trovemanager".as_ref()], bump = trove_manager.bump)]
+    pub trove_manager: Account<'info, TroveManager>,
+
+    #[account(mut, seeds = [b"fee".as_ref()], bump = fee_account.bump)]
+    pub fee_account: Account<'info, Fee>,
+
+    #[account(mut, seeds = [b"teamfee".as_ref()], bump = team_fee_account.bump)]
+    pub team_fee_account: Account<'info, Fee>,
+
+    #[account(mut, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    pub collateral_mint: Account<'info, Mint>,
+
+    #[account(mut, seeds = [b"collateralvault".as_ref(), authority.key().as_ref(), collateral_mint.key().as_ref()], bump = collateral_vault_bump)]
+    pub collateral_vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user_collateral: Account<'info, TokenAccount>,
+
+    pub pyth_sol_account: AccountInfo<'info>,
+
+    #[account(seeds = [b"price".as_ref()], bump = secondary_price.bump)]
+    pub secondary_price: Account<'info, state::Price>,
+
+    pub clock: Sysvar<'info, Clock>,
+
+    #[account(address = spl_token::ID)]
+    pub token_program: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, trove_bump: u8)]
+pub struct WithdrawCoin<'info> {
+    #[account(signer, mut)]
+    pub authority: AccountInfo<'info>,
+
+    #[account(mut, seeds = [b"borrowertrove".as_ref(),authority.key.to_bytes().as_ref()], bump = trove.bump)]
+    pub trove: ProgramAccount<'info, Trove>,
+
+    #[account(mut, seeds = [b"trovemanager".as_ref()], bump = trove_manager.bump)]
+    pub trove_manager: Account<'info, TroveManager>,
+
+    #[account(mut, seeds = [b"fee".as_ref()], bump = fee_account.bump)]
+    pub fee_account: Account<'info, Fee>,
+
+    #[account(mut, seeds = [b"teamfee".as_ref()], bump = team_fee_account.bump)]
+    pub team_fee_account: Account<'info, Fee>,
+
+    #[account(mut, seeds = [b"solTrove".as_ref(),authority.key().to_bytes().as_ref()], bump = trove.sol_bump)]
+    pub sol_trove: AccountInfo<'info>,
+
+    pub pyth_sol_account: AccountInfo<'info>,
+
+    #[account(seeds = [b"price".as_ref()], bump = secondary_price.bump)]
+    pub secondary_price: Account<'info, state::Price>,
+
+    #[account(mut, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    pub clock: Sysvar<'info, Clock>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(collateral_delta: i64, debt_delta: i64, mint_account_bump: u8)]
+pub struct AdjustTrove<'info> {
+    #[account(signer, mut)]
+    pub authority: AccountInfo<'info>,
+
+    #[account(mut, has_one = authority, seeds = [b"borrowertrove".as_ref(), authority.key().to_bytes().as_ref()], bump = trove.bump)]
+    pub trove: ProgramAccount<'info, Trove>,
+
+    #[account(mut, seeds = [b"trovemanager".as_ref()], bump = trove_manager.bump)]
+    pub trove_manager: Account<'info, TroveManager>,
+
+    #[account(mut, seeds = [b"fee".as_ref()], bump = fee_account.bump)]
+    pub fee_account: Account<'info, Fee>,
+
+    #[account(mut, seeds = [b"teamfee".as_ref()], bump = team_fee_account.bump)]
+    pub team_fee_account: Account<'info, Fee>,
+
+    #[account(mut, seeds = [b"solTrove".as_ref(), authority.key().to_bytes().as_ref()], bump = trove.sol_bump)]
+    pub sol_trove: AccountInfo<'info>,
+
+    #[account(
+        seeds=[
+            b"mint-authority".as_ref()
+        ],
+        bump = mint_account_bump
+    )]
+    pub token_authority: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub stable_coin: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub user_token: Account<'info, TokenAccount>,
+
+    pub pyth_sol_account: AccountInfo<'info>,
+
+    #[account(seeds = [b"price".as_ref()], bump = secondary_price.bump)]
+    pub secondary_price: Account<'info, state::Price>,
+
+    #[account(mut, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    pub clock: Sysvar<'info, Clock>,
+
+    pub system_program: Program<'info, System>,
+
+    #[account(address = spl_token::ID)]
+    pub token_program: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RedeemCoin<'info> {
+    #[account(signer, mut)]
+    pub authority: AccountInfo<'info>,
+
+    #[account(mut, seeds = [b"trovemanager".as_ref()], bump = trove_manager.bump)]
+    pub trove_manager: Account<'info, TroveManager>,
+
+    #[account(mut, seeds = [b"fee".as_ref()], bump = fee_account.bump)]
+    pub fee_account: Account<'info, Fee>,
+
+    #[account(mut, seeds = [b"teamfee".as_ref()], bump = team_fee_account.bump)]
+    pub team_fee_account: Account<'info, Fee>,
+
+    #[account(mut)]
+    pub stable_coin: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub user_token: Account<'info, TokenAccount>,
+
+    #[account(address = spl_token::ID)]
+    pub token_program: AccountInfo<'info>,
+
+    pub pyth_sol_account: AccountInfo<'info>,
+
+    #[account(seeds = [b"price".as_ref()], bump = secondary_price.bump)]
+    pub secondary_price: Account<'info, state::Price>,
 
-    #[account(signer, mut)]
-    pub temp_lamport_account: AccountInfo<'info>,
+    #[account(mut, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    pub clock: Sysvar<'info, Clock>,
 }
 
 #[derive(Accounts)]
-#[instruction(amount: u64, trove_bump: u8)]
-pub struct WithdrawCoin<'info> {
+pub struct UpdateTrove<'info> {
     #[account(signer, mut)]
     pub authority: AccountInfo<'info>,
 
-    #[account(mut, seeds = [b"borrowertrove".as_ref(),authority.key.to_bytes().as_ref()], bump = trove.bump)]
+    #[account(mut, has_one = authority, seeds = [b"borrowertrove",authority.key().to_bytes().as_ref()], bump = trove.bump)]
     pub trove: ProgramAccount<'info, Trove>,
 
-    #[account(mut, seeds = [b"solTrove".as_ref(),authority.key().to_bytes().as_ref()], bump = trove.sol_bump)]
-    pub sol_trove: AccountInfo<'info>,
+    #[account(mut, seeds = [b"trovemanager".as_ref()], bump = trove_manager.bump)]
+    pub trove_manager: Account<'info, TroveManager>,
 
+    #[account(mut, seeds = [b"fee".as_ref()], bump = fee_account.bump)]
+    pub fee_account: Account<'info, Fee>,
 
-    pub system_program: Program<'info, System>,
-}
+    #[account(mut, seeds = [b"teamfee".as_ref()], bump = team_fee_account.bump)]
+    pub team_fee_account: Account<'info, Fee>,
 
-#[derive(Accounts)]
-pub struct RedeemCoin<'info> {
-    #[account(signer, mut)]
-    pub authority: AccountInfo<'info>,
+    #[account(mut, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
 
-    #[account(mut, seeds = [b"borrowertrove".as_ref(), authority.key.to_bytes().as_ref()], bump = trove.bump)]
-    pub trove: ProgramAccount<'info, Trove>,
-}
+    pub pyth_sol_account: AccountInfo<'info>,
 
-#[derive(Accounts)]
-pub struct UpdateTrove<'info> {
-    #[account(signer, mut)]
-    pub authority: AccountInfo<'info>,
+    #[account(seeds = [b"price".as_ref()], bump = secondary_price.bump)]
+    pub secondary_price: Account<'info, state::Price>,
 
-    #[account(mut, has_one = authority, seeds = [b"borrowertrove",authority.key().to_bytes().as_ref()], bump = trove.bump)]
-    pub trove: ProgramAccount<'info, Trove>,
+    pub clock: Sysvar<'info, Clock>,
 
     #[account(mut, seeds = [b"solTrove".as_ref(),authority.key().to_bytes().as_ref()], bump = trove.sol_bump)]
     pub sol_trove: AccountInfo<'info>,
@@ -845,6 +3227,20 @@ pub struct CloseTrove<'info> {
     #[account(mut, close = authority, seeds = [b"borrowertrove".as_ref(),authority.key().to_bytes().as_ref()], bump = trove.bump)]
     pub trove: ProgramAccount<'info, Trove>,
 
+    #[account(mut, seeds = [b"trovemanager".as_ref()], bump = trove_manager.bump)]
+    pub trove_manager: Account<'info, TroveManager>,
+
+    #[account(mut, seeds = [b"fee".as_ref()], bump = fee_account.bump)]
+    pub fee_account: Account<'info, Fee>,
+
+    #[account(mut, seeds = [b"teamfee".as_ref()], bump = team_fee_account.bump)]
+    pub team_fee_account: Account<'info, Fee>,
+
+    #[account(mut, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    pub clock: Sysvar<'info, Clock>,
+
     #[account(mut, seeds = [b"solTrove".as_ref(),authority.key().to_bytes().as_ref()], bump = sol_account_bump)]
     pub sol_trove: AccountInfo<'info>,
 
@@ -860,26 +3256,68 @@ pub struct CloseTrove<'info> {
 
 
 #[derive(Accounts)]
-#[instruction(trove_bump:u8)]
-pub struct LiquidateTrove<'info> {
-
+pub struct ClaimCollateralSurplus<'info> {
     #[account(mut)]
     pub authority: Signer<'info>,
 
-    #[account(
-        mut,
-        has_one = authority,
-        seeds = [b"admin".as_ref(), authority.key().as_ref()],
-        bump = admin_account_authority.bump,
-    )]
-    pub admin_account_authority: Account<'info, Admin>,
-    
-    #[account(mut, has_one = admin_account_authority, seeds = [b"config".as_ref(), admin_account_authority.key().to_bytes().as_ref()], bump = config.bump)]
+    #[account(mut, has_one = authority, seeds = [b"borrowertrove".as_ref(), authority.key().to_bytes().as_ref()], bump = trove.bump)]
+    pub trove: ProgramAccount<'info, Trove>,
+
+    #[account(mut, seeds = [b"solTrove".as_ref(), authority.key().to_bytes().as_ref()], bump = trove.sol_bump)]
+    pub sol_trove: AccountInfo<'info>,
+
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
     pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(trove_bump:u8, reward_vault_bump: u8)]
+pub struct LiquidateTrove<'info> {
+
+    #[account(mut)]
+    pub liquidator: Signer<'info>,
 
-    #[account(mut, close = admin_account_authority, seeds = [b"borrowertrove".as_ref(), trove.authority.key().to_bytes().as_ref()], bump = trove_bump)]
+    #[account(mut, seeds = [b"borrowertrove".as_ref(), trove.authority.key().to_bytes().as_ref()], bump = trove_bump)]
     pub trove: ProgramAccount<'info, Trove>,
 
+    #[account(mut, seeds = [b"trovemanager".as_ref()], bump = trove_manager.bump)]
+    pub trove_manager: Account<'info, TroveManager>,
+
+    #[account(mut, seeds = [b"fee".as_ref()], bump = fee_account.bump)]
+    pub fee_account: Account<'info, Fee>,
+
+    #[account(mut, seeds = [b"teamfee".as_ref()], bump = team_fee_account.bump)]
+    pub team_fee_account: Account<'info, Fee>,
+
+    #[account(mut, seeds = [b"solTrove".as_ref(), trove.authority.key().to_bytes().as_ref()], bump = trove.sol_bump)]
+    pub sol_trove: AccountInfo<'info>,
+
+    #[account(mut, seeds = [b"rewardVault".as_ref()], bump = reward_vault_bump)]
+    pub reward_coin_vault: AccountInfo<'info>,
+
+    #[account(mut, seeds = [b"stabilitypool".as_ref()], bump = stability_pool.bump)]
+    pub stability_pool: Account<'info, StabilityPool>,
+
+    #[account(mut, seeds = [b"depositvault".as_ref()], bump = stability_pool.deposit_vault_bump)]
+    pub deposit_vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub stable_coin: Account<'info, Mint>,
+
+    pub pyth_sol_account: AccountInfo<'info>,
+
+    #[account(seeds = [b"price".as_ref()], bump = secondary_price.bump)]
+    pub secondary_price: Account<'info, state::Price>,
+
+    #[account(mut, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    pub clock: Sysvar<'info, Clock>,
+
+    pub system_program: Program<'info, System>,
+
+    #[account(address = spl_token::ID)]
+    pub token_program: AccountInfo<'info>,
 }
 
 #[derive(Accounts)]
@@ -900,6 +3338,9 @@ pub struct Borrow<'info> {
     )]
     pub trove_account: Account<'info, Trove>,
 
+    #[account(mut, seeds = [b"trovemanager".as_ref()], bump = trove_manager.bump)]
+    pub trove_manager: Account<'info, TroveManager>,
+
     #[account(init_if_needed, seeds = [b"solTrove",authority.key().to_bytes().as_ref()], bump = sol_account_bump, payer = authority, space =  0)]
     pub sol_trove: AccountInfo<'info>,
 
@@ -941,6 +3382,14 @@ pub struct Borrow<'info> {
 
     pub pyth_sol_account: AccountInfo<'info>,
 
+    #[account(seeds = [b"price".as_ref()], bump = secondary_price.bump)]
+    pub secondary_price: Account<'info, state::Price>,
+
+    #[account(mut, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    pub clock: Sysvar<'info, Clock>,
+
     pub system_program: Program<'info, System>,
 
     #[account(address = spl_token::ID)]
@@ -958,72 +3407,317 @@ pub struct AddBorrow<'info> {
     #[account(mut, seeds=[b"borrowertrove", authority.key.to_bytes().as_ref()], bump = trove.bump)]
     pub trove: ProgramAccount<'info, Trove>,
 
-    #[account(mut, seeds = [b"solTrove".as_ref(),authority.key().to_bytes().as_ref()], bump = trove.sol_bump)]
-    pub sol_trove: AccountInfo<'info>,
+    #[account(mut, seeds = [b"trovemanager".as_ref()], bump = trove_manager.bump)]
+    pub trove_manager: Account<'info, TroveManager>,
+
+    #[account(mut, seeds = [b"fee".as_ref()], bump = fee_account.bump)]
+    pub fee_account: Account<'info, Fee>,
+
+    #[account(mut, seeds = [b"teamfee".as_ref()], bump = team_fee_account.bump)]
+    pub team_fee_account: Account<'info, Fee>,
+
+    #[account(mut, seeds = [b"solTrove".as_ref(),authority.key().to_bytes().as_ref()], bump = trove.sol_bump)]
+    pub sol_trove: AccountInfo<'info>,
+
+    #[account(
+        seeds=[
+            b"mint-authority".as_ref()
+        ],
+        bump = mint_account_bump
+    )]
+    pub token_authority: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub stable_coin: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub user_token: Account<'info, TokenAccount>,
+
+    pub pyth_sol_account: AccountInfo<'info>,
+
+    #[account(seeds = [b"price".as_ref()], bump = secondary_price.bump)]
+    pub secondary_price: Account<'info, state::Price>,
+
+    #[account(mut, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    pub clock: Sysvar<'info, Clock>,
+
+    pub system_program: Program<'info, System>,
+
+    #[account(address = spl_token::ID)]
+    pub token_program: AccountInfo<'info>,
+
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(mint_account_bump: u8)]
+pub struct InitializeTokenAcc<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        init,
+        seeds=[
+            b"mint-authority"
+        ],
+        bump = mint_account_bump,
+        space =  8 + 2 + 4 + 200 + 1,
+        payer = authority 
+    )]
+    pub token_authority: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(config_account_bump: u8)]
+pub struct InitializeConfig<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(init, seeds = [b"config".as_ref()], bump = config_account_bump, payer = authority, space = Config::LEN + 8)]
+    pub config_account: Account<'info, Config>,
+
+    // holds the upgrade authority, checked by the instruction
+    pub program_data: AccountInfo<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(admin_account_bump: u8)]
+pub struct InitializeAdmin<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(init, seeds = [b"admin".as_ref(), authority.key().as_ref()], bump = admin_account_bump, payer = authority, space = Admin::LEN + 8)]
+    pub admin_account: Account<'info, Admin>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(trove_manager_bump: u8)]
+pub struct InitializeTroveManager<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(has_one = authority, seeds = [b"admin".as_ref(), authority.key().as_ref()], bump = admin_account_authority.bump)]
+    pub admin_account_authority: Account<'info, Admin>,
+
+    #[account(has_one = admin_account_authority, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(init, seeds = [b"trovemanager".as_ref()], bump = trove_manager_bump, payer = authority, space = TroveManager::LEN + 8)]
+    pub trove_manager: Account<'info, TroveManager>,
+
+    pub clock: Sysvar<'info, Clock>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(stability_pool_bump: u8, deposit_vault_bump: u8)]
+pub struct InitializeStabilityPool<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(has_one = authority, seeds = [b"admin".as_ref(), authority.key().as_ref()], bump = admin_account_authority.bump)]
+    pub admin_account_authority: Account<'info, Admin>,
+
+    #[account(has_one = admin_account_authority, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(init, seeds = [b"stabilitypool".as_ref()], bump = stability_pool_bump, payer = authority, space = StabilityPool::LEN + 8)]
+    pub stability_pool: Account<'info, StabilityPool>,
+
+    pub stable_coin: Account<'info, Mint>,
+
+    pub governance_mint: Account<'info, Mint>,
+
+    // the deposits are escrowed here, only the stability pool can move them
+    #[account(
+        init,
+        seeds = [b"depositvault".as_ref()],
+        bump = deposit_vault_bump,
+        payer = authority,
+        token::mint = stable_coin,
+        token::authority = stability_pool
+    )]
+    pub deposit_vault: Account<'info, TokenAccount>,
+
+    pub system_program: Program<'info, System>,
+
+    #[account(address = spl_token::ID)]
+    pub token_program: AccountInfo<'info>,
+
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct SetGovernanceEmission<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(has_one = authority, seeds = [b"admin".as_ref(), authority.key().as_ref()], bump = admin_account_authority.bump)]
+    pub admin_account_authority: Account<'info, Admin>,
+
+    #[account(has_one = admin_account_authority, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(mut, seeds = [b"stabilitypool".as_ref()], bump = stability_pool.bump)]
+    pub stability_pool: Account<'info, StabilityPool>,
+
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+#[instruction(max_ltv: u64, liquidation_threshold: u64, pool_collateral_vault_bump: u8)]
+pub struct AddCollateralType<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(has_one = authority, seeds = [b"admin".as_ref(), authority.key().as_ref()], bump = admin_account_authority.bump)]
+    pub admin_account_authority: Account<'info, Admin>,
+
+    #[account(mut, has_one = admin_account_authority, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(seeds = [b"stabilitypool".as_ref()], bump = stability_pool.bump)]
+    pub stability_pool: Account<'info, StabilityPool>,
+
+    pub collateral_mint: Account<'info, Mint>,
+
+    pub collateral_oracle: AccountInfo<'info>,
+
+    // the collateral seized by the liquidations waits here for the depositors
+    #[account(
+        init,
+        seeds = [b"poolcollateral".as_ref(), collateral_mint.key().as_ref()],
+        bump = pool_collateral_vault_bump,
+        payer = authority,
+        token::mint = collateral_mint,
+        token::authority = stability_pool
+    )]
+    pub pool_collateral_vault: Account<'info, TokenAccount>,
+
+    pub system_program: Program<'info, System>,
+
+    #[account(address = spl_token::ID)]
+    pub token_program: AccountInfo<'info>,
+
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateTrove<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // may still have an older layout, the instruction checks the owner and the seeds
+    #[account(mut)]
+    pub trove: AccountInfo<'info>,
+
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(mint_account_bump: u8)]
+pub struct MigrateDeposit<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // may still have an older layout, the instruction checks the owner and the seeds
+    #[account(mut)]
+    pub deposit: AccountInfo<'info>,
+
+    #[account(mut, seeds = [b"stabilitypool".as_ref()], bump = stability_pool.bump)]
+    pub stability_pool: Account<'info, StabilityPool>,
 
-    #[account(
-        seeds=[
-            b"mint-authority".as_ref()
-        ],
-        bump = mint_account_bump
-    )]
-    pub token_authority: AccountInfo<'info>,
+    #[account(mut, seeds = [b"depositvault".as_ref()], bump = stability_pool.deposit_vault_bump)]
+    pub deposit_vault: Account<'info, TokenAccount>,
 
     #[account(mut)]
     pub stable_coin: Account<'info, Mint>,
 
-    #[account(mut)]
-    pub user_token: Account<'info, TokenAccount>,
+    #[account(seeds = [b"mint-authority".as_ref()], bump = mint_account_bump)]
+    pub token_authority: AccountInfo<'info>,
 
-    pub pyth_sol_account: AccountInfo<'info>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
 
     pub system_program: Program<'info, System>,
 
     #[account(address = spl_token::ID)]
     pub token_program: AccountInfo<'info>,
-
-    pub rent: Sysvar<'info, Rent>,
 }
 
 #[derive(Accounts)]
-#[instruction(mint_account_bump: u8)]
-pub struct InitializeTokenAcc<'info> {
-    #[account(mut)]
+#[instruction(config_account_bump: u8)]
+pub struct MigrateConfig<'info> {
     pub authority: Signer<'info>,
 
-    #[account(
-        init,
-        seeds=[
-            b"mint-authority"
-        ],
-        bump = mint_account_bump,
-        space =  8 + 2 + 4 + 200 + 1,
-        payer = authority 
-    )]
-    pub token_authority: AccountInfo<'info>,
+    #[account(mut)]
+    pub upgrade_authority: Signer<'info>,
+
+    #[account(has_one = authority, seeds = [b"admin".as_ref(), authority.key().as_ref()], bump = admin_account_authority.bump)]
+    pub admin_account_authority: Account<'info, Admin>,
+
+    // still has the layout of the first deployments, the instruction checks the owner and the seeds
+    #[account(mut)]
+    pub legacy_config: AccountInfo<'info>,
+
+    #[account(init, seeds = [b"config".as_ref()], bump = config_account_bump, payer = upgrade_authority, space = Config::LEN + 8)]
+    pub config: Account<'info, Config>,
+
+    // holds the upgrade authority, checked by the instruction
+    pub program_data: AccountInfo<'info>,
+
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-#[instruction(config_account_bump: u8)]
-pub struct InitializeConfig<'info> {
+pub struct MigrateTroveManager<'info> {
     #[account(mut)]
     pub authority: Signer<'info>,
 
-    #[account(init, seeds = [b"config".as_ref(), authority.key().as_ref()], bump = config_account_bump, payer = authority, space = Config::LEN + 8)]
-    pub config_account: Account<'info, Config>,
+    #[account(has_one = authority, seeds = [b"admin".as_ref(), authority.key().as_ref()], bump = admin_account_authority.bump)]
+    pub admin_account_authority: Account<'info, Admin>,
+
+    #[account(has_one = admin_account_authority, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    // may still have an older layout, the instruction checks the owner and the seeds
+    #[account(mut)]
+    pub trove_manager: AccountInfo<'info>,
+
+    pub pyth_sol_account: AccountInfo<'info>,
+
+    #[account(seeds = [b"price".as_ref()], bump = secondary_price.bump)]
+    pub secondary_price: Account<'info, state::Price>,
+
+    pub clock: Sysvar<'info, Clock>,
 
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-#[instruction(admin_account_bump: u8)]
-pub struct InitializeAdmin<'info> {
+pub struct MigrateFee<'info> {
     #[account(mut)]
     pub authority: Signer<'info>,
 
-    #[account(init, seeds = [b"admin".as_ref(), authority.key().as_ref()], bump = admin_account_bump, payer = authority, space = Admin::LEN + 8)]
-    pub admin_account: Account<'info, Admin>,
+    #[account(has_one = authority, seeds = [b"admin".as_ref(), authority.key().as_ref()], bump = admin_account_authority.bump)]
+    pub admin_account_authority: Account<'info, Admin>,
+
+    #[account(has_one = admin_account_authority, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    // may still have an older layout, the instruction checks the owner and the seeds
+    #[account(mut)]
+    pub fee_account: AccountInfo<'info>,
 
     pub system_program: Program<'info, System>,
 }
@@ -1042,27 +3736,179 @@ pub struct ChangeConfig<'info>{
     pub admin_account_authority: Account<'info, Admin>,
 
     // Info: can validate that pda account is created by the different using seeds:program
-    #[account(mut, seeds = [b"config".as_ref(), config_account.admin_account_authority.key().to_bytes().as_ref()], bump = config_account.bump, has_one = admin_account_authority)]
+    #[account(mut, seeds = [b"config".as_ref()], bump = config_account.bump, has_one = admin_account_authority)]
     pub config_account: Account<'info, Config>
 }
 
+#[derive(Accounts)]
+#[instruction(admin_account_bump: u8)]
+pub struct AcceptAdmin<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(init_if_needed, seeds = [b"admin".as_ref(), authority.key().as_ref()], bump = admin_account_bump, payer = payer, space = Admin::LEN + 8)]
+    pub admin_account: Account<'info, Admin>,
+
+    #[account(mut, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Pause<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(mut, seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+}
+
 #[account]
 #[derive(Default, Debug)]
 pub struct Config {
+    pub version: u8,
     pub bump: u8,
     pub is_initialized: bool,
     pub mint_account_authority: Pubkey,
     pub admin_account_authority: Pubkey,
+    pub pyth_sol_account: Pubkey,
+    pub secondary_price_source: Pubkey,
+    pub max_price_staleness: u64,
+    pub max_price_confidence: u64,
+    pub max_price_deviation: u64,
+    pub params: ProtocolParams,
+    pub cumulative_interest_index: u128,
+    pub last_interest_update: i64,
+    pub liquidation_keeper: Pubkey,
+    pub collateral_types: [CollateralType; MAX_COLLATERAL_TYPES],
+    /// Can pause instructions next to the admin but never unpause them
+    pub guardian: Pubkey,
+    /// Bitmask of the paused instruction groups, see the `PAUSE_` flags
+    pub paused: u8,
+    /// Proposed by the admin, becomes the admin once it accepts
+    pub pending_admin: Pubkey,
+    pub reserved: [u64; 4],
 }
 
 impl Config {
-    /// space = 8 + 1 + 32 + 32 
-    pub const LEN: usize = size_of::<Config>() + 8;
+    pub const LEN: usize = layout_len(&[
+        U8_LEN,                                     // version
+        U8_LEN,                                     // bump
+        BOOL_LEN,                                   // is_initialized
+        PUBKEY_LEN,                                 // mint_account_authority
+        PUBKEY_LEN,                                 // admin_account_authority
+        PUBKEY_LEN,                                 // pyth_sol_account
+        PUBKEY_LEN,                                 // secondary_price_source
+        U64_LEN,                                    // max_price_staleness
+        U64_LEN,                                    // max_price_confidence
+        U64_LEN,                                    // max_price_deviation
+        ProtocolParams::LEN,                        // params
+        U128_LEN,                                   // cumulative_interest_index
+        I64_LEN,                                    // last_interest_update
+        PUBKEY_LEN,                                 // liquidation_keeper
+        MAX_COLLATERAL_TYPES * CollateralType::LEN, // collateral_types
+        PUBKEY_LEN,                                 // guardian
+        U8_LEN,                                     // paused
+        PUBKEY_LEN,                                 // pending_admin
+        4 * U64_LEN,                                // reserved
+    ]);
+
+    /// Propose `pending_admin` as the next admin, the default pubkey cancels the proposal
+    pub fn propose_admin(&mut self, pending_admin: Pubkey) {
+        self.pending_admin = pending_admin;
+    }
+
+    /// Hand the config over to `admin_account`, the admin account of the pending admin
+    /// `authority`. Returns the admin account it replaces.
+    pub fn accept_admin(&mut self, authority: &Pubkey, admin_account: Pubkey) -> Result<Pubkey, ProgramError> {
+        if self.pending_admin == Pubkey::default() || self.pending_admin != *authority {
+            return Err(NexfinError::OnlyForPendingAdmin.into());
+        }
+
+        let previous_admin_account = self.admin_account_authority;
+        self.admin_account_authority = admin_account;
+        self.pending_admin = Pubkey::default();
+        Ok(previous_admin_account)
+    }
+
+    /// Fails when the config is not initialized or any of the `flags` is paused
+    pub fn check_not_paused(&self, flags: u8) -> ProgramResult {
+        if !self.is_initialized || self.paused & flags != 0 {
+            return Err(NexfinError::ProtocolPaused.into());
+        }
+        Ok(())
+    }
+
+    /// Index and parameters of the collateral type registered for `mint`
+    pub fn get_collateral_type(&self, mint: &Pubkey) -> Result<(usize, CollateralType), ProgramError> {
+        self.collateral_types
+            .iter()
+            .position(|c| c.is_registered() && c.mint == *mint)
+            .map(|index| (index, self.collateral_types[index]))
+            .ok_or_else(|| NexfinError::InvalidCollateral.into())
+    }
+
+    /// Move the cumulative interest index forward to `now`
+    pub fn accrue_interest(&mut self, now: i64) -> ProgramResult {
+        if self.cumulative_interest_index == 0 {
+            self.cumulative_interest_index = DECIMAL_PRECISION;
+            self.last_interest_update = now;
+        }
+
+        let elapsed = now - self.last_interest_update;
+        if elapsed <= 0 {
+            return Ok(());
+        }
+
+        self.cumulative_interest_index = get_accrued_interest_index(self.cumulative_interest_index, self.params.interest_rate, elapsed)?;
+        self.last_interest_update = now;
+        Ok(())
+    }
+}
+
+/// Token accepted as trove collateral next to SOL, priced through its own pyth feed
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug)]
+pub struct CollateralType {
+    pub mint: Pubkey,
+    pub oracle: Pubkey,
+    pub decimals: u8,
+    pub vault_bump: u8,
+    /// Share of the collateral value which can be borrowed against, in basis points
+    pub max_ltv: u64,
+    /// Share of the collateral value counted before the trove is liquidated, in basis points
+    pub liquidation_threshold: u64,
+}
+
+impl CollateralType {
+    pub const LEN: usize = layout_len(&[
+        PUBKEY_LEN, // mint
+        PUBKEY_LEN, // oracle
+        U8_LEN,     // decimals
+        U8_LEN,     // vault_bump
+        U64_LEN,    // max_ltv
+        U64_LEN,    // liquidation_threshold
+    ]);
+
+    /// Empty config slots keep the default mint
+    pub fn is_registered(&self) -> bool {
+        self.mint != Pubkey::default()
+    }
+
+    pub fn validate(&self) -> ProgramResult {
+        if self.max_ltv == 0 || self.max_ltv > self.liquidation_threshold || self.liquidation_threshold > 10_000 {
+            return Err(NexfinError::InvalidConfig.into());
+        }
+
+        Ok(())
+    }
 }
 
 #[account]
 #[derive(Default, Debug)]
 pub struct Deposit {
+    pub version: u8,
     pub bump: u8,
     pub is_initialized: bool,
     pub token_amount: u64,
@@ -1072,16 +3918,33 @@ pub struct Deposit {
     pub bank: Pubkey,
     pub governance_bank: Pubkey,
     pub authority: Pubkey,
+    pub snapshot: DepositSnapshot,
+    pub reward_collateral_amounts: [u64; MAX_COLLATERAL_TYPES],
+    pub reserved: [u64; 8],
 }
 
 impl Deposit {
-    /// space = 8 + 1 + 8 + 8 + 8 + 8 + 32 + 32 + 32
-    pub const LEN: usize = size_of::<Deposit>() + 8;
+    pub const LEN: usize = layout_len(&[
+        U8_LEN,                         // version
+        U8_LEN,                         // bump
+        BOOL_LEN,                       // is_initialized
+        U64_LEN,                        // token_amount
+        U64_LEN,                        // reward_token_amount
+        U64_LEN,                        // reward_governance_token_amount
+        U64_LEN,                        // reward_coin_amount
+        PUBKEY_LEN,                     // bank
+        PUBKEY_LEN,                     // governance_bank
+        PUBKEY_LEN,                     // authority
+        DepositSnapshot::LEN,           // snapshot
+        MAX_COLLATERAL_TYPES * U64_LEN, // reward_collateral_amounts
+        8 * U64_LEN,                    // reserved
+    ]);
 }
 
 #[account]
 #[derive(Default, Debug)]
 pub struct Trove {
+    pub version: u8,
     pub bump: u8,
     pub sol_bump: u8,
     pub is_initialized: bool,
@@ -1093,24 +3956,66 @@ pub struct Trove {
     pub depositor_fee: u64,
     pub amount_to_close: u64,
     pub authority: Pubkey,
+    pub interest_index: u128,
+    pub received_at: i64,
+    pub received_price: u64,
+    /// Token collateral held in the trove vaults, indexed like the config collateral types
+    pub collaterals: [u64; MAX_COLLATERAL_TYPES],
+    /// Lamports left in the sol trove by a recovery mode liquidation, claimable by the owner
+    pub collateral_surplus: u64,
+    pub reserved: [u64; 7],
 }
 
 impl Trove {
-    /// space = 8 + 8 + 1 + 1 + 1 + 8 + 8 + 8 + 8 + 8 + 32
-    pub const LEN: usize = size_of::<Trove>() + 8;
+    pub const LEN: usize = layout_len(&[
+        U8_LEN,                         // version
+        U8_LEN,                         // bump
+        U8_LEN,                         // sol_bump
+        BOOL_LEN,                       // is_initialized
+        BOOL_LEN,                       // is_received
+        BOOL_LEN,                       // is_liquidated
+        U64_LEN,                        // borrow_amount
+        U64_LEN,                        // lamports_amount
+        U64_LEN,                        // team_fee
+        U64_LEN,                        // depositor_fee
+        U64_LEN,                        // amount_to_close
+        PUBKEY_LEN,                     // authority
+        U128_LEN,                       // interest_index
+        I64_LEN,                        // received_at
+        U64_LEN,                        // received_price
+        MAX_COLLATERAL_TYPES * U64_LEN, // collaterals
+        U64_LEN,                        // collateral_surplus
+        7 * U64_LEN,                    // reserved
+    ]);
+
+    /// Leave the liquidation queue, the grace period starts over the next time the trove is received
+    pub fn dequeue(&mut self) {
+        self.is_received = false;
+        self.received_at = 0;
+        self.received_price = 0;
+    }
 }
 
 #[account]
 #[derive(Default, Debug)]
 pub struct Fee {
+    pub version: u8,
     pub bump: u8,
     pub is_initialized: bool,
     pub sol_amount: u64,
+    pub token_amount: u64,
+    pub reserved: [u64; 8],
 }
 
 impl Fee {
-    /// space = 8 + 1 + 8 + 8 + 8
-    pub const LEN: usize = size_of::<Fee>() + 8;
+    pub const LEN: usize = layout_len(&[
+        U8_LEN,      // version
+        U8_LEN,      // bump
+        BOOL_LEN,    // is_initialized
+        U64_LEN,     // sol_amount
+        U64_LEN,     // token_amount
+        8 * U64_LEN, // reserved
+    ]);
 }
 
 #[account]
@@ -1121,6 +4026,498 @@ pub struct Admin {
 }
 
 impl Admin {
-    /// space = 8 + 1 + 8
-    pub const LEN: usize = size_of::<Deposit>() + 8;
-}
\ No newline at end of file
+    pub const LEN: usize = layout_len(&[
+        U8_LEN,     // bump
+        PUBKEY_LEN, // authority
+    ]);
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug)]
+pub struct SortedTrove {
+    pub authority: Pubkey,
+    pub nicr: u128,
+    pub collateral: u64,
+    pub debt: u64,
+}
+
+impl SortedTrove {
+    pub const LEN: usize = layout_len(&[
+        PUBKEY_LEN, // authority
+        U128_LEN,   // nicr
+        U64_LEN,    // collateral
+        U64_LEN,    // debt
+    ]);
+}
+
+/// Troves sorted by their nominal collateral ratio, the riskiest first, along with the total
+/// collateral and debt of the system
+///
+/// The collateral of a trove is its SOL plus its token collateral weighted by the liquidation
+/// threshold at the liquidation ratio, the token collateral is valued at the last update of the
+/// trove.
+#[account]
+#[derive(Default, Debug)]
+pub struct TroveManager {
+    pub bump: u8,
+    pub base_rate: u64,
+    pub last_fee_operation_time: i64,
+    pub total_collateral: u64,
+    pub total_debt: u64,
+    pub troves: Vec<SortedTrove>,
+}
+
+impl TroveManager {
+    pub const LEN: usize = layout_len(&[
+        U8_LEN,                                                // bump
+        U64_LEN,                                               // base_rate
+        I64_LEN,                                               // last_fee_operation_time
+        U64_LEN,                                               // total_collateral
+        U64_LEN,                                               // total_debt
+        VEC_PREFIX_LEN + MAX_SORTED_TROVES * SortedTrove::LEN, // troves
+    ]);
+
+    pub fn get(&self, authority: &Pubkey) -> Option<&SortedTrove> {
+        self.troves.iter().find(|t| t.authority == *authority)
+    }
+
+    pub fn remove(&mut self, authority: &Pubkey) -> ProgramResult {
+        if let Some(index) = self.troves.iter().position(|t| t.authority == *authority) {
+            let trove = self.troves.remove(index);
+            self.total_collateral = self.total_collateral.checked_sub(trove.collateral).ok_or(NexfinError::MathOverflow)?;
+            self.total_debt = self.total_debt.checked_sub(trove.debt).ok_or(NexfinError::MathOverflow)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn insert(&mut self, trove: SortedTrove) -> ProgramResult {
+        self.remove(&trove.authority)?;
+
+        if self.troves.len() >= MAX_SORTED_TROVES {
+            return Err(NexfinError::TroveListFull.into());
+        }
+
+        self.total_collateral = self.total_collateral.checked_add(trove.collateral).ok_or(NexfinError::MathOverflow)?;
+        self.total_debt = self.total_debt.checked_add(trove.debt).ok_or(NexfinError::MathOverflow)?;
+
+        let index = self.troves.iter().position(|t| t.nicr > trove.nicr).unwrap_or(self.troves.len());
+        self.troves.insert(index, trove);
+        Ok(())
+    }
+
+    /// Total collateral ratio of the system in percent
+    pub fn get_total_collateral_ratio(&self, sol_price: u128) -> Result<u64, ProgramError> {
+        get_collateral_ratio(self.total_collateral, self.total_debt, sol_price)
+    }
+
+    /// The system is in recovery mode while its total collateral ratio is under the critical ratio
+    pub fn is_recovery_mode(&self, sol_price: u128, params: &ProtocolParams) -> Result<bool, ProgramError> {
+        Ok(self.get_total_collateral_ratio(sol_price)? < params.critical_collateral_ratio)
+    }
+
+    /// Collateral ratio under which a trove can be liquidated
+    pub fn get_liquidation_ratio(&self, sol_price: u128, params: &ProtocolParams) -> Result<u64, ProgramError> {
+        if self.is_recovery_mode(sol_price, params)? {
+            msg!("Recovery mode, total collateral ratio is {}", self.get_total_collateral_ratio(sol_price)?);
+            return Ok(params.critical_collateral_ratio);
+        }
+
+        Ok(params.liquidation_ratio)
+    }
+
+    /// Reject a trove update lowering the total collateral ratio while in recovery mode,
+    /// `trove` holds the updated amounts which are not in the list yet and `token_lamports` is
+    /// its weighted token collateral
+    pub fn check_recovery_mode(&self, trove: &Trove, token_lamports: u64, sol_price: u128, params: &ProtocolParams) -> ProgramResult {
+        if !self.is_recovery_mode(sol_price, params)? {
+            return Ok(());
+        }
+
+        let (collateral, debt) = self.get(&trove.authority).map(|t| (t.collateral, t.debt)).unwrap_or((0, 0));
+        let new_collateral = trove.lamports_amount.checked_add(token_lamports).ok_or(NexfinError::MathOverflow)?;
+        let new_total_collateral = self.total_collateral
+            .checked_sub(collateral).ok_or(NexfinError::MathOverflow)?
+            .checked_add(new_collateral).ok_or(NexfinError::MathOverflow)?;
+        let new_total_debt = self.total_debt
+            .checked_sub(debt).ok_or(NexfinError::MathOverflow)?
+            .checked_add(trove.amount_to_close).ok_or(NexfinError::MathOverflow)?;
+
+        // new_collateral / new_debt < collateral / debt without rounding the ratios
+        let lowered = (new_total_collateral as u128).checked_mul(self.total_debt as u128).ok_or(NexfinError::MathOverflow)?
+            < (self.total_collateral as u128).checked_mul(new_total_debt as u128).ok_or(NexfinError::MathOverflow)?;
+
+        if lowered {
+            msg!("Recovery mode, total collateral ratio would fall to {}", get_collateral_ratio(new_total_collateral, new_total_debt, sol_price)?);
+            return Err(NexfinError::RecoveryMode.into());
+        }
+
+        Ok(())
+    }
+
+    /// Decay the base rate by the minutes elapsed since the last fee operation
+    pub fn decay_base_rate(&mut self, now: i64) -> Result<u64, ProgramError> {
+        self.base_rate = get_decayed_base_rate(self.base_rate, self.last_fee_operation_time, now)?;
+        if now - self.last_fee_operation_time >= SECONDS_IN_ONE_MINUTE {
+            self.last_fee_operation_time = now;
+        }
+
+        Ok(self.base_rate)
+    }
+
+    /// Raise the base rate by the fraction of the supply minted or redeemed
+    pub fn increase_base_rate(&mut self, amount: u64, total_supply: u64) -> ProgramResult {
+        self.base_rate = get_increased_base_rate(self.base_rate, amount, total_supply)?;
+        Ok(())
+    }
+
+    /// Move the trove to its new position, troves without debt leave the list.
+    /// `token_lamports` is the weighted token collateral of the trove
+    pub fn update_trove(&mut self, trove: &Trove, token_lamports: u64) -> ProgramResult {
+        if trove.is_liquidated || trove.amount_to_close == 0 {
+            return self.remove(&trove.authority);
+        }
+
+        let collateral = trove.lamports_amount.checked_add(token_lamports).ok_or(NexfinError::MathOverflow)?;
+        self.insert(SortedTrove {
+            authority: trove.authority,
+            nicr: get_nominal_collateral_ratio(collateral, trove.amount_to_close),
+            collateral,
+            debt: trove.amount_to_close,
+        })
+    }
+}
+
+/// Reward sums of the stability pool for one (epoch, scale) of the pool product
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug)]
+pub struct PoolSum {
+    pub epoch: u64,
+    pub scale: u64,
+    pub coin_sum: u128,
+    pub token_sum: u128,
+    pub governance_sum: u128,
+    pub collateral_sums: [u128; MAX_COLLATERAL_TYPES],
+    /// Deposits holding coins whose snapshot was taken at this epoch and scale
+    pub deposits: u64,
+}
+
+impl PoolSum {
+    pub const LEN: usize = layout_len(&[
+        U64_LEN,                         // epoch
+        U64_LEN,                         // scale
+        U128_LEN,                        // coin_sum
+        U128_LEN,                        // token_sum
+        U128_LEN,                        // governance_sum
+        MAX_COLLATERAL_TYPES * U128_LEN, // collateral_sums
+        U64_LEN,                         // deposits
+    ]);
+}
+
+/// Pool state taken by a deposit when it was last settled
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug)]
+pub struct DepositSnapshot {
+    pub p: u128,
+    pub coin_sum: u128,
+    pub token_sum: u128,
+    pub governance_sum: u128,
+    pub collateral_sums: [u128; MAX_COLLATERAL_TYPES],
+    pub scale: u64,
+    pub epoch: u64,
+}
+
+impl DepositSnapshot {
+    pub const LEN: usize = layout_len(&[
+        U128_LEN,                        // p
+        U128_LEN,                        // coin_sum
+        U128_LEN,                        // token_sum
+        U128_LEN,                        // governance_sum
+        MAX_COLLATERAL_TYPES * U128_LEN, // collateral_sums
+        U64_LEN,                         // scale
+        U64_LEN,                         // epoch
+    ]);
+}
+
+/// Stability pool tracking every deposit through the running product `p` of the liquidation
+/// losses and the running sums of the rewards per deposited coin
+#[account]
+#[derive(Default, Debug)]
+pub struct StabilityPool {
+    pub bump: u8,
+    pub deposit_vault_bump: u8,
+    pub total_deposits: u64,
+    pub p: u128,
+    pub current_scale: u64,
+    pub current_epoch: u64,
+    pub last_debt_error: u128,
+    pub last_coin_error: u128,
+    pub last_token_error: u128,
+    pub last_governance_error: u128,
+    pub governance_mint: Pubkey,
+    pub governance_supply_cap: u64,
+    pub governance_issuance_factor: u128,
+    pub emission_start_time: i64,
+    pub total_governance_issued: u64,
+    pub last_collateral_errors: [u128; MAX_COLLATERAL_TYPES],
+    pub sums: Vec<PoolSum>,
+}
+
+impl StabilityPool {
+    pub const LEN: usize = layout_len(&[
+        U8_LEN,                                          // bump
+        U8_LEN,                                          // deposit_vault_bump
+        U64_LEN,                                         // total_deposits
+        U128_LEN,                                        // p
+        U64_LEN,                                         // current_scale
+        U64_LEN,                                         // current_epoch
+        U128_LEN,                                        // last_debt_error
+        U128_LEN,                                        // last_coin_error
+        U128_LEN,                                        // last_token_error
+        U128_LEN,                                        // last_governance_error
+        PUBKEY_LEN,                                      // governance_mint
+        U64_LEN,                                         // governance_supply_cap
+        U128_LEN,                                        // governance_issuance_factor
+        I64_LEN,                                         // emission_start_time
+        U64_LEN,                                         // total_governance_issued
+        MAX_COLLATERAL_TYPES * U128_LEN,                 // last_collateral_errors
+        VEC_PREFIX_LEN + MAX_POOL_SCALES * PoolSum::LEN, // sums
+    ]);
+
+    /// Sums of the `epoch` and `scale`, zero when nothing was distributed there
+    pub fn get_sum(&self, epoch: u64, scale: u64) -> PoolSum {
+        self.sums
+            .iter()
+            .find(|s| s.epoch == epoch && s.scale == scale)
+            .copied()
+            .unwrap_or(PoolSum { epoch, scale, ..PoolSum::default() })
+    }
+
+    fn current_sum_mut(&mut self) -> Result<&mut PoolSum, ProgramError> {
+        let (epoch, scale) = (self.current_epoch, self.current_scale);
+        let index = match self.sums.iter().position(|s| s.epoch == epoch && s.scale == scale) {
+            Some(index) => index,
+            None => {
+                if self.sums.len() >= MAX_POOL_SCALES {
+                    self.prune_sums();
+                }
+                if self.sums.len() >= MAX_POOL_SCALES {
+                    return Err(NexfinError::PoolScaleListFull.into());
+                }
+                self.sums.push(PoolSum { epoch, scale, ..PoolSum::default() });
+                self.sums.len() - 1
+            }
+        };
+
+        Ok(&mut self.sums[index])
+    }
+
+    /// Drop the sums no deposit snapshot reads anymore, a snapshot reads the sums of its own
+    /// epoch and scale and of the next scale. The sums of the current epoch and scale are kept.
+    fn prune_sums(&mut self) {
+        let snapshots: Vec<(u64, u64)> = self.sums.iter().filter(|s| s.deposits > 0).map(|s| (s.epoch, s.scale)).collect();
+        let (current_epoch, current_scale) = (self.current_epoch, self.current_scale);
+
+        self.sums.retain(|s| {
+            (s.epoch == current_epoch && s.scale == current_scale)
+                || snapshots.iter().any(|&(epoch, scale)| s.epoch == epoch && (s.scale == scale || s.scale == scale + 1))
+        });
+    }
+
+    /// Count a deposit holding coins against the sums of the current epoch and scale, its snapshot
+    fn retain_current_sum(&mut self) -> ProgramResult {
+        let sum = self.current_sum_mut()?;
+        sum.deposits = sum.deposits.checked_add(1).ok_or(NexfinError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Stop counting a deposit against the sums of its `snapshot`
+    fn release_sum(&mut self, snapshot: &DepositSnapshot) {
+        if let Some(sum) = self.sums.iter_mut().find(|s| s.epoch == snapshot.epoch && s.scale == snapshot.scale) {
+            sum.deposits = sum.deposits.saturating_sub(1);
+        }
+    }
+
+    /// Offset `debt` against the deposits and distribute the seized `lamports` in O(1)
+    pub fn offset(&mut self, debt: u64, lamports: u64) -> ProgramResult {
+        if debt > self.total_deposits {
+            return Err(NexfinError::InsufficientLiquidity.into());
+        }
+
+        if debt == 0 {
+            return Ok(());
+        }
+
+        self.distribute(lamports, 0, 0)?;
+
+        let (loss_per_unit, debt_error) = get_loss_per_unit_staked(debt, self.total_deposits, self.last_debt_error)?;
+        self.last_debt_error = debt_error;
+
+        let product_factor = DECIMAL_PRECISION.checked_sub(loss_per_unit).ok_or(NexfinError::MathOverflow)?;
+        if product_factor == 0 {
+            // the pool was emptied, the deposits of the past epochs are worth nothing
+            self.current_epoch = self.current_epoch.checked_add(1).ok_or(NexfinError::MathOverflow)?;
+            self.current_scale = 0;
+            self.p = DECIMAL_PRECISION;
+        } else {
+            let p = self.p.checked_mul(product_factor).ok_or(NexfinError::MathOverflow)?;
+            if p / DECIMAL_PRECISION < SCALE_FACTOR {
+                self.current_scale = self.current_scale.checked_add(1).ok_or(NexfinError::MathOverflow)?;
+                self.p = p.checked_mul(SCALE_FACTOR).ok_or(NexfinError::MathOverflow)? / DECIMAL_PRECISION;
+            } else {
+                self.p = p / DECIMAL_PRECISION;
+            }
+        }
+
+        self.total_deposits -= debt;
+        Ok(())
+    }
+
+    /// Distribute SOL, stable coin and governance rewards over the deposits in O(1)
+    pub fn distribute(&mut self, coin: u64, token: u64, governance: u64) -> ProgramResult {
+        if self.total_deposits == 0 {
+            return Err(NexfinError::InsufficientLiquidity.into());
+        }
+
+        let (coin_per_unit, coin_error) = get_gain_per_unit_staked(coin, self.total_deposits, self.last_coin_error)?;
+        let (token_per_unit, token_error) = get_gain_per_unit_staked(token, self.total_deposits, self.last_token_error)?;
+        let (governance_per_unit, governance_error) = get_gain_per_unit_staked(governance, self.total_deposits, self.last_governance_error)?;
+        self.last_coin_error = coin_error;
+        self.last_token_error = token_error;
+        self.last_governance_error = governance_error;
+
+        let p = self.p;
+        let sum = self.current_sum_mut()?;
+        sum.coin_sum = sum.coin_sum.checked_add(coin_per_unit.checked_mul(p).ok_or(NexfinError::MathOverflow)? / DECIMAL_PRECISION).ok_or(NexfinError::MathOverflow)?;
+        sum.token_sum = sum.token_sum.checked_add(token_per_unit.checked_mul(p).ok_or(NexfinError::MathOverflow)? / DECIMAL_PRECISION).ok_or(NexfinError::MathOverflow)?;
+        sum.governance_sum = sum.governance_sum.checked_add(governance_per_unit.checked_mul(p).ok_or(NexfinError::MathOverflow)? / DECIMAL_PRECISION).ok_or(NexfinError::MathOverflow)?;
+
+        Ok(())
+    }
+
+    /// Distribute `amount` of the collateral token registered at `index` over the deposits in O(1)
+    pub fn distribute_collateral(&mut self, index: usize, amount: u64) -> ProgramResult {
+        if self.total_deposits == 0 {
+            return Err(NexfinError::InsufficientLiquidity.into());
+        }
+
+        let (collateral_per_unit, collateral_error) = get_gain_per_unit_staked(amount, self.total_deposits, self.last_collateral_errors[index])?;
+        self.last_collateral_errors[index] = collateral_error;
+
+        let p = self.p;
+        let sum = self.current_sum_mut()?;
+        sum.collateral_sums[index] = sum.collateral_sums[index].checked_add(collateral_per_unit.checked_mul(p).ok_or(NexfinError::MathOverflow)? / DECIMAL_PRECISION).ok_or(NexfinError::MathOverflow)?;
+
+        Ok(())
+    }
+
+    /// Issue the governance tokens emitted since the last call over the deposits, the emission
+    /// of the periods without any deposit is never issued
+    pub fn issue_governance(&mut self, now: i64) -> ProgramResult {
+        if self.governance_supply_cap == 0 || now <= self.emission_start_time {
+            return Ok(());
+        }
+
+        let minutes = (now - self.emission_start_time) / SECONDS_IN_ONE_MINUTE;
+        let cumulative_issuance = get_cumulative_issuance(self.governance_supply_cap, self.governance_issuance_factor, minutes as u64)?;
+        let issuance = cumulative_issuance.saturating_sub(self.total_governance_issued);
+        if issuance == 0 {
+            return Ok(());
+        }
+
+        self.total_governance_issued = cumulative_issuance;
+        if self.total_deposits > 0 {
+            self.distribute(0, 0, issuance)?;
+        }
+
+        Ok(())
+    }
+
+    /// Snapshot of the current product and sums for a settled deposit
+    pub fn snapshot(&self) -> DepositSnapshot {
+        let sum = self.get_sum(self.current_epoch, self.current_scale);
+        DepositSnapshot {
+            p: self.p,
+            coin_sum: sum.coin_sum,
+            token_sum: sum.token_sum,
+            governance_sum: sum.governance_sum,
+            collateral_sums: sum.collateral_sums,
+            scale: self.current_scale,
+            epoch: self.current_epoch,
+        }
+    }
+
+    /// Add a deposit made before the pool existed, its coins join the pool deposits and it earns
+    /// from the current pool state on. Returns the coins added to the pool.
+    pub fn add_legacy_deposit(&mut self, deposit: &mut Deposit) -> Result<u64, ProgramError> {
+        if deposit.snapshot.p > 0 {
+            return Ok(0);
+        }
+
+        self.total_deposits = self.total_deposits.checked_add(deposit.token_amount).ok_or(NexfinError::MathOverflow)?;
+        deposit.snapshot = self.snapshot();
+        if deposit.token_amount > 0 {
+            self.retain_current_sum()?;
+        }
+        Ok(deposit.token_amount)
+    }
+
+    /// Add `amount` coins to a deposit settled at the current pool state
+    pub fn add_to_deposit(&mut self, deposit: &mut Deposit, amount: u64) -> ProgramResult {
+        if deposit.token_amount == 0 && amount > 0 {
+            self.retain_current_sum()?;
+        }
+
+        deposit.token_amount = deposit.token_amount.checked_add(amount).ok_or(NexfinError::MathOverflow)?;
+        self.total_deposits = self.total_deposits.checked_add(amount).ok_or(NexfinError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Take `amount` coins out of a deposit settled at the current pool state
+    pub fn withdraw_from_deposit(&mut self, deposit: &mut Deposit, amount: u64) -> ProgramResult {
+        if amount > deposit.token_amount {
+            return Err(NexfinError::AttemptToWithdrawTooMuch.into());
+        }
+
+        deposit.token_amount -= amount;
+        self.total_deposits = self.total_deposits.checked_sub(amount).ok_or(NexfinError::MathOverflow)?;
+        if amount > 0 && deposit.token_amount == 0 {
+            self.release_sum(&deposit.snapshot);
+        }
+        Ok(())
+    }
+
+    /// Credit the rewards accrued since the deposit snapshot, shrink the deposit by its share of
+    /// the liquidated debt and move the snapshot to the current pool state
+    pub fn settle(&mut self, deposit: &mut Deposit) -> ProgramResult {
+        let snapshot = deposit.snapshot;
+        if deposit.token_amount > 0 && snapshot.p > 0 {
+            self.release_sum(&snapshot);
+
+            let first = self.get_sum(snapshot.epoch, snapshot.scale);
+            let second = self.get_sum(snapshot.epoch, snapshot.scale + 1);
+
+            let coin = get_depositor_gain(deposit.token_amount, snapshot.p, snapshot.coin_sum, first.coin_sum, second.coin_sum)?;
+            let token = get_depositor_gain(deposit.token_amount, snapshot.p, snapshot.token_sum, first.token_sum, second.token_sum)?;
+            let governance = get_depositor_gain(deposit.token_amount, snapshot.p, snapshot.governance_sum, first.governance_sum, second.governance_sum)?;
+
+            deposit.reward_coin_amount = deposit.reward_coin_amount.checked_add(coin).ok_or(NexfinError::MathOverflow)?;
+            deposit.reward_token_amount = deposit.reward_token_amount.checked_add(token).ok_or(NexfinError::MathOverflow)?;
+            deposit.reward_governance_token_amount = deposit.reward_governance_token_amount.checked_add(governance).ok_or(NexfinError::MathOverflow)?;
+
+            for index in 0..MAX_COLLATERAL_TYPES {
+                let collateral = get_depositor_gain(deposit.token_amount, snapshot.p, snapshot.collateral_sums[index], first.collateral_sums[index], second.collateral_sums[index])?;
+                deposit.reward_collateral_amounts[index] = deposit.reward_collateral_amounts[index].checked_add(collateral).ok_or(NexfinError::MathOverflow)?;
+            }
+
+            deposit.token_amount = if snapshot.epoch < self.current_epoch {
+                0
+            } else {
+                get_compounded_deposit(deposit.token_amount, snapshot.p, self.p, self.current_scale - snapshot.scale)?
+            };
+        }
+
+        deposit.snapshot = self.snapshot();
+        if deposit.token_amount > 0 {
+            self.retain_current_sum()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+use crate::error::NexfinError;
+use crate::params::{ProtocolParams, ACCOUNT_VERSION, MAX_COLLATERAL_TYPES};
+use crate::{CollateralType, Config, Deposit, DepositSnapshot, Fee, Trove, TroveManager};
+use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
+
+/// Account which can be read from any of its past layouts
+///
+/// The version byte is the first field after the discriminator. Accounts created before it
+/// existed are shorter than the current layout, which tells them apart from the current ones.
+pub trait Versioned: AccountSerialize + AccountDeserialize + Discriminator + Sized {
+    /// Space allocated for the current layout, discriminator included
+    const SPACE: usize;
+
+    /// Read the layout written before the version byte, without the discriminator
+    fn from_v0(data: &[u8]) -> Result<Self, ProgramError>;
+}
+
+/// Read an account in any of its layouts, returns the account upgraded to the current layout and
+/// whether its data still has to be rewritten
+pub fn load_versioned<T: Versioned>(data: &[u8]) -> Result<(T, bool), ProgramError> {
+    if data.len() <= 8 || data[..8] != T::discriminator() {
+        return Err(NexfinError::InvalidAccountInput.into());
+    }
+
+    if data.len() >= T::SPACE && data[8] == ACCOUNT_VERSION {
+        return Ok((T::try_deserialize(&mut &data[..])?, false));
+    }
+
+    Ok((T::from_v0(&data[8..])?, true))
+}
+
+fn deserialize_v0<T: AnchorDeserialize>(data: &[u8]) -> Result<T, ProgramError> {
+    T::deserialize(&mut &data[..]).map_err(|_| NexfinError::InvalidAccountInput.into())
+}
+
+/// Trove layout of the first deployments, before the version byte
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug)]
+pub struct TroveV0 {
+    pub bump: u8,
+    pub sol_bump: u8,
+    pub is_initialized: bool,
+    pub is_received: bool,
+    pub is_liquidated: bool,
+    pub borrow_amount: u64,
+    pub lamports_amount: u64,
+    pub team_fee: u64,
+    pub depositor_fee: u64,
+    pub amount_to_close: u64,
+    pub authority: Pubkey,
+}
+
+impl Versioned for Trove {
+    const SPACE: usize = Trove::LEN + 8;
+
+    fn from_v0(data: &[u8]) -> Result<Self, ProgramError> {
+        let trove: TroveV0 = deserialize_v0(data)?;
+        Ok(Trove {
+            version: ACCOUNT_VERSION,
+            bump: trove.bump,
+            sol_bump: trove.sol_bump,
+            is_initialized: trove.is_initialized,
+            is_received: trove.is_received,
+            is_liquidated: trove.is_liquidated,
+            borrow_amount: trove.borrow_amount,
+            lamports_amount: trove.lamports_amount,
+            team_fee: trove.team_fee,
+            depositor_fee: trove.depositor_fee,
+            amount_to_close: trove.amount_to_close,
+            authority: trove.authority,
+            // the debt starts accruing interest at the next instruction on the trove
+            interest_index: 0,
+            // a trove already queued has its grace period elapsed, as the v0 queue had none
+            received_at: 0,
+            received_price: 0,
+            collaterals: [0; MAX_COLLATERAL_TYPES],
+            collateral_surplus: 0,
+            reserved: Default::default(),
+        })
+    }
+}
+
+/// Deposit layout of the first deployments, before the version byte
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug)]
+pub struct DepositV0 {
+    pub bump: u8,
+    pub is_initialized: bool,
+    pub token_amount: u64,
+    pub reward_token_amount: u64,
+    pub reward_governance_token_amount: u64,
+    pub reward_coin_amount: u64,
+    pub bank: Pubkey,
+    pub governance_bank: Pubkey,
+    pub authority: Pubkey,
+}
+
+impl Versioned for Deposit {
+    const SPACE: usize = Deposit::LEN + 8;
+
+    fn from_v0(data: &[u8]) -> Result<Self, ProgramError> {
+        let deposit: DepositV0 = deserialize_v0(data)?;
+        Ok(Deposit {
+            version: ACCOUNT_VERSION,
+            bump: deposit.bump,
+            is_initialized: deposit.is_initialized,
+            token_amount: deposit.token_amount,
+            reward_token_amount: deposit.reward_token_amount,
+            reward_governance_token_amount: deposit.reward_governance_token_amount,
+            reward_coin_amount: deposit.reward_coin_amount,
+            bank: deposit.bank,
+            governance_bank: deposit.governance_bank,
+            authority: deposit.authority,
+            // an empty snapshot marks a legacy deposit, `migrate_deposit` brings it into the pool
+            snapshot: DepositSnapshot::default(),
+            reward_collateral_amounts: [0; MAX_COLLATERAL_TYPES],
+            reserved: Default::default(),
+        })
+    }
+}
+
+/// Config layout of the first deployments, kept per authority at `[b"config", authority]`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug)]
+pub struct ConfigV0 {
+    pub bump: u8,
+    pub is_initialized: bool,
+    pub mint_account_authority: Pubkey,
+    pub admin_account_authority: Pubkey,
+}
+
+impl Versioned for Config {
+    const SPACE: usize = Config::LEN + 8;
+
+    fn from_v0(data: &[u8]) -> Result<Self, ProgramError> {
+        let config: ConfigV0 = deserialize_v0(data)?;
+        Ok(Config {
+            version: ACCOUNT_VERSION,
+            bump: config.bump,
+            is_initialized: config.is_initialized,
+            mint_account_authority: config.mint_account_authority,
+            admin_account_authority: config.admin_account_authority,
+            // the oracle settings, the keeper and the collateral types are set by the admin
+            pyth_sol_account: Pubkey::default(),
+            secondary_price_source: Pubkey::default(),
+            max_price_staleness: 0,
+            max_price_confidence: 0,
+            max_price_deviation: 0,
+            params: ProtocolParams::default(),
+            // started by the first accrual
+            cumulative_interest_index: 0,
+            last_interest_update: 0,
+            liquidation_keeper: Pubkey::default(),
+            collateral_types: [CollateralType::default(); MAX_COLLATERAL_TYPES],
+            guardian: Pubkey::default(),
+            paused: 0,
+            pending_admin: Pubkey::default(),
+            reserved: Default::default(),
+        })
+    }
+}
+
+/// Fee layout of the first deployments, before the version byte
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug)]
+pub struct FeeV0 {
+    pub bump: u8,
+    pub is_initialized: bool,
+    pub sol_amount: u64,
+}
+
+impl Versioned for Fee {
+    const SPACE: usize = Fee::LEN + 8;
+
+    fn from_v0(data: &[u8]) -> Result<Self, ProgramError> {
+        let fee: FeeV0 = deserialize_v0(data)?;
+        Ok(Fee {
+            version: ACCOUNT_VERSION,
+            bump: fee.bump,
+            is_initialized: fee.is_initialized,
+            sol_amount: fee.sol_amount,
+            // the stable coin fees came with the interest
+            token_amount: 0,
+            reserved: Default::default(),
+        })
+    }
+}
+
+/// Sorted trove entry before the trove manager tracked the total collateral and debt
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug)]
+pub struct SortedTroveV0 {
+    pub authority: Pubkey,
+    pub nicr: u128,
+}
+
+/// Trove manager layout before the total collateral and debt, its list held 200 troves
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug)]
+pub struct TroveManagerV0 {
+    pub bump: u8,
+    pub base_rate: u64,
+    pub last_fee_operation_time: i64,
+    pub troves: Vec<SortedTroveV0>,
+}
+
+/// Read a trove manager in any of its layouts, returns it with an empty list and no totals along
+/// with the authorities of its listed troves, the list is rebuilt from the troves
+pub fn load_trove_manager(data: &[u8]) -> Result<(TroveManager, Vec<Pubkey>), ProgramError> {
+    if data.len() <= 8 || data[..8] != TroveManager::discriminator() {
+        return Err(NexfinError::InvalidAccountInput.into());
+    }
+
+    // the list entries grew with the totals, the current accounts are the only ones of this size
+    let (trove_manager, authorities) = if data.len() == TroveManager::LEN + 8 {
+        let trove_manager = TroveManager::try_deserialize(&mut &data[..])?;
+        let authorities = trove_manager.troves.iter().map(|t| t.authority).collect();
+        (trove_manager, authorities)
+    } else {
+        let trove_manager: TroveManagerV0 = deserialize_v0(&data[8..])?;
+        let authorities = trove_manager.troves.iter().map(|t| t.authority).collect();
+        let trove_manager = TroveManager {
+            bump: trove_manager.bump,
+            base_rate: trove_manager.base_rate,
+            last_fee_operation_time: trove_manager.last_fee_operation_time,
+            ..TroveManager::default()
+        };
+        (trove_manager, authorities)
+    };
+
+    Ok((
+        TroveManager {
+            total_collateral: 0,
+            total_debt: 0,
+            troves: Vec::new(),
+            ..trove_manager
+        },
+        authorities,
+    ))
+}
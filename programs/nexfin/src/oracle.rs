@@ -0,0 +1,190 @@
+use crate::error::NexfinError;
+use crate::params::SOL_PRICE_EXPO;
+use crate::{state, CollateralType, Config};
+use anchor_lang::prelude::*;
+use std::mem::size_of;
+
+/// Aggregate price of a pyth price account
+pub struct PythPrice {
+    pub price: i64,
+    pub conf: u64,
+    pub pub_slot: u64,
+    pub expo: i32,
+}
+
+/// SOL/USD price chosen between the primary and the secondary feed
+pub struct SolPrice {
+    pub price: u128,
+    pub is_primary: bool,
+    pub is_borrowing_frozen: bool,
+}
+
+impl SolPrice {
+    /// Price for the instructions increasing the debt, rejected while both feeds disagree
+    pub fn for_borrow(&self) -> Result<u128, ProgramError> {
+        if self.is_borrowing_frozen {
+            return Err(NexfinError::OracleDeviation.into());
+        }
+
+        Ok(self.price)
+    }
+}
+
+/// Read the SOL/USD price through the primary pyth feed, falling back to the secondary
+/// `state::Price` feed updated by the crank when the primary is stale or halted.
+///
+/// When both feeds are healthy but deviate by more than `max_price_deviation` basis points
+/// the primary price is returned with borrowing frozen.
+pub fn get_sol_price(
+    pyth_price_info: &AccountInfo,
+    secondary_price: &state::Price,
+    config: &Config,
+    clock: &Clock,
+) -> Result<SolPrice, ProgramError> {
+    let primary = load_pyth_price(pyth_price_info, &config.pyth_sol_account).and_then(|pyth_price| {
+        check_sol_price_expo(pyth_price.expo)?;
+        check_price(pyth_price.price, pyth_price.conf, pyth_price.pub_slot, config, clock)
+    });
+    let secondary = check_price(secondary_price.price, secondary_price.conf, secondary_price.pub_slot, config, clock);
+
+    match (primary, secondary) {
+        (Ok(primary_price), Ok(secondary_price)) => {
+            let deviation = get_price_deviation(primary_price, secondary_price)?;
+            if deviation > config.max_price_deviation as u128 {
+                msg!("Oracle prices deviate by {} basis points, borrowing is frozen", deviation);
+            }
+
+            Ok(SolPrice {
+                price: primary_price,
+                is_primary: true,
+                is_borrowing_frozen: deviation > config.max_price_deviation as u128,
+            })
+        }
+        (Ok(primary_price), Err(_)) => Ok(SolPrice {
+            price: primary_price,
+            is_primary: true,
+            is_borrowing_frozen: false,
+        }),
+        (Err(_), Ok(secondary_price)) => {
+            msg!("Primary oracle is unavailable, using the secondary price");
+            Ok(SolPrice {
+                price: secondary_price,
+                is_primary: false,
+                is_borrowing_frozen: false,
+            })
+        }
+        (Err(e), Err(_)) => Err(e),
+    }
+}
+
+/// Load the aggregate price of a trading pyth price account at `expected_key`
+pub fn load_pyth_price(pyth_price_info: &AccountInfo, expected_key: &Pubkey) -> Result<PythPrice, ProgramError> {
+    if pyth_price_info.key != expected_key {
+        msg!("Pyth price account {} does not match the config", pyth_price_info.key);
+        return Err(NexfinError::InvalidOracleConfig.into());
+    }
+
+    #[cfg(feature = "test-oracle")]
+    if *pyth_price_info.owner == crate::ID {
+        return load_mock_price(pyth_price_info);
+    }
+
+    let pyth_price_data = &pyth_price_info.try_borrow_data()?;
+    if pyth_price_data.len() < size_of::<pyth_client::Price>() {
+        return Err(NexfinError::InvalidOracleConfig.into());
+    }
+
+    let pyth_price = pyth_client::cast::<pyth_client::Price>(pyth_price_data);
+    if pyth_price.magic != pyth_client::MAGIC {
+        return Err(NexfinError::InvalidOracleConfig.into());
+    }
+
+    if !matches!(pyth_price.agg.status, pyth_client::PriceStatus::Trading) {
+        msg!("Pyth price is not trading");
+        return Err(NexfinError::InvalidOracleConfig.into());
+    }
+
+    Ok(PythPrice {
+        price: pyth_price.agg.price,
+        conf: pyth_price.agg.conf,
+        pub_slot: pyth_price.agg.pub_slot,
+        expo: pyth_price.expo,
+    })
+}
+
+/// Reject a SOL/USD feed which does not publish with the exponent of `SOL_PRICE_EXPO`, any
+/// other exponent would misprice the collateral by powers of ten
+pub fn check_sol_price_expo(expo: i32) -> ProgramResult {
+    if expo != SOL_PRICE_EXPO {
+        msg!("SOL price exponent {} is not {}", expo, SOL_PRICE_EXPO);
+        return Err(NexfinError::InvalidOracleConfig.into());
+    }
+
+    Ok(())
+}
+
+/// Load a mock price account moved by `set_price`, the discriminator tells it apart from the
+/// other program accounts
+#[cfg(feature = "test-oracle")]
+fn load_mock_price(mock_price_info: &AccountInfo) -> Result<PythPrice, ProgramError> {
+    let mock_price = state::MockPrice::try_deserialize(&mut &mock_price_info.try_borrow_data()?[..])?;
+    if !mock_price.is_trading {
+        msg!("Mock price is not trading");
+        return Err(NexfinError::InvalidOracleConfig.into());
+    }
+
+    Ok(PythPrice {
+        price: mock_price.price,
+        conf: mock_price.conf,
+        pub_slot: mock_price.pub_slot,
+        expo: mock_price.expo,
+    })
+}
+
+/// Read the USD price of a registered collateral token through its pyth feed, returns the
+/// price with its exponent
+pub fn get_collateral_price(
+    pyth_price_info: &AccountInfo,
+    collateral_type: &CollateralType,
+    config: &Config,
+    clock: &Clock,
+) -> Result<(u128, i32), ProgramError> {
+    let pyth_price = load_pyth_price(pyth_price_info, &collateral_type.oracle)?;
+    let price = check_price(pyth_price.price, pyth_price.conf, pyth_price.pub_slot, config, clock)?;
+
+    Ok((price, pyth_price.expo))
+}
+
+/// Reject prices which are not positive, were published more than `max_price_staleness` slots
+/// ago or have a confidence interval wider than `max_price_confidence` basis points of the price.
+fn check_price(price: i64, conf: u64, pub_slot: u64, config: &Config, clock: &Clock) -> Result<u128, ProgramError> {
+    if price <= 0 {
+        return Err(NexfinError::InvalidOracleConfig.into());
+    }
+
+    let staleness = clock.slot.saturating_sub(pub_slot);
+    if staleness > config.max_price_staleness {
+        msg!("Price is stale by {} slots", staleness);
+        return Err(NexfinError::ReserveStale.into());
+    }
+
+    let price = price as u128;
+    let confidence = (conf as u128)
+        .checked_mul(10_000).ok_or(NexfinError::MathOverflow)?
+        .checked_div(price).ok_or(NexfinError::MathOverflow)?;
+    if confidence > config.max_price_confidence as u128 {
+        msg!("Price confidence is {} basis points", confidence);
+        return Err(NexfinError::InvalidOracleConfig.into());
+    }
+
+    Ok(price)
+}
+
+/// Difference between two prices in basis points of the lowest one
+fn get_price_deviation(a: u128, b: u128) -> Result<u128, ProgramError> {
+    let (low, high) = if a < b { (a, b) } else { (b, a) };
+
+    Ok((high - low)
+        .checked_mul(10_000).ok_or(NexfinError::MathOverflow)?
+        .checked_div(low).ok_or(NexfinError::MathOverflow)?)
+}
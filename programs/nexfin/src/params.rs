@@ -1,4 +1,11 @@
-pub const MIN_COLLATERAL: f64 = 0.4;
+use crate::error::NexfinError;
+use crate::state::{layout_len, U64_LEN};
+use anchor_lang::prelude::*;
+
+// Defaults of the protocol parameters stored in the config account
+
+/// 40% minimum collateral
+pub const MIN_COLLATERAL: u64 = 40;
 /// 2 SOL as gase fee
 pub const GAS_FEE: u64 = 1;
 
@@ -9,4 +16,200 @@ pub const TEAM_FEE: u64 = 47;
 
 pub const MIN_DEPOSIT_FEE: u64 = 4000;
 pub const MIN_TEAM_FEE: u64 = 1000;
-pub const MIN_TOTAL_FEE: u64 = 5000;
\ No newline at end of file
+pub const MIN_TOTAL_FEE: u64 = 5000;
+/// Exponent of the SOL/USD prices, the price math of the program is written for it
+pub const SOL_PRICE_EXPO: i32 = -8;
+/// Converts a fee in 1/10000 stable coin to lamports at a pyth price with an exponent of -8,
+/// 1e9 lamports per SOL times 1e8 price units over 1e4 fee units
+pub const FEE_TO_LAMPORTS: u128 = 10_000_000_000_000;
+
+pub const COLLATERAL_RATIO: u64 = 110;
+pub const LIQUIDATION_RATIO: u64 = 110;
+/// The system enters recovery mode when its total collateral ratio falls under 150%
+pub const CRITICAL_COLLATERAL_RATIO: u64 = 150;
+/// Smallest debt a trove can hold, every trove takes a slot of the sorted trove list so filling
+/// the list has to lock `MAX_SORTED_TROVES` times this debt
+pub const MIN_TROVE_DEBT: u64 = 2_000;
+pub const MIN_BORROW_AMOUNT: u64 = MIN_TROVE_DEBT;
+pub const DEBT_CEILING: u64 = 100_000_000;
+/// 5% cap on the borrowing fee, in basis points
+pub const MAX_BORROWING_FEE: u64 = 500;
+/// Yearly interest on the trove debt in basis points, disabled by default
+pub const INTEREST_RATE: u64 = 0;
+pub const SECONDS_PER_YEAR: i64 = 31_536_000;
+/// Seconds a trove stays in the liquidation queue before it can be liquidated
+pub const LIQUIDATION_GRACE_PERIOD: u64 = 600;
+/// Fee on the flash minted stable coins in basis points
+pub const FLASH_MINT_FEE: u64 = 9;
+
+/// 0.5% of the seized collateral is paid to the liquidator as gas compensation
+pub const LIQUIDATOR_REWARD: u64 = 5;
+pub const LIQUIDATOR_REWARD_DENOMINATOR: u64 = 1000;
+
+/// Protocol parameters governed by the admin through `update_params`
+///
+/// Fees are in basis points of the borrowed amount, minimum fees in 1/10000 stable coin,
+/// ratios in percent and amounts in whole stable coins.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct ProtocolParams {
+    pub min_collateral: u64,
+    pub gas_fee: u64,
+    pub deposit_fee: u64,
+    pub team_fee: u64,
+    pub min_deposit_fee: u64,
+    pub min_team_fee: u64,
+    pub max_borrowing_fee: u64,
+    pub collateral_ratio: u64,
+    pub liquidation_ratio: u64,
+    pub liquidator_reward: u64,
+    pub min_borrow_amount: u64,
+    pub debt_ceiling: u64,
+    pub interest_rate: u64,
+    pub liquidation_grace_period: u64,
+    pub critical_collateral_ratio: u64,
+    pub flash_mint_fee: u64,
+}
+
+impl Default for ProtocolParams {
+    fn default() -> Self {
+        ProtocolParams {
+            min_collateral: MIN_COLLATERAL,
+            gas_fee: GAS_FEE,
+            deposit_fee: DEPOSIT_FEE,
+            team_fee: TEAM_FEE,
+            min_deposit_fee: MIN_DEPOSIT_FEE,
+            min_team_fee: MIN_TEAM_FEE,
+            max_borrowing_fee: MAX_BORROWING_FEE,
+            collateral_ratio: COLLATERAL_RATIO,
+            liquidation_ratio: LIQUIDATION_RATIO,
+            liquidator_reward: LIQUIDATOR_REWARD,
+            min_borrow_amount: MIN_BORROW_AMOUNT,
+            debt_ceiling: DEBT_CEILING,
+            interest_rate: INTEREST_RATE,
+            liquidation_grace_period: LIQUIDATION_GRACE_PERIOD,
+            critical_collateral_ratio: CRITICAL_COLLATERAL_RATIO,
+            flash_mint_fee: FLASH_MINT_FEE,
+        }
+    }
+}
+
+impl ProtocolParams {
+    pub const LEN: usize = layout_len(&[
+        U64_LEN, // min_collateral
+        U64_LEN, // gas_fee
+        U64_LEN, // deposit_fee
+        U64_LEN, // team_fee
+        U64_LEN, // min_deposit_fee
+        U64_LEN, // min_team_fee
+        U64_LEN, // max_borrowing_fee
+        U64_LEN, // collateral_ratio
+        U64_LEN, // liquidation_ratio
+        U64_LEN, // liquidator_reward
+        U64_LEN, // min_borrow_amount
+        U64_LEN, // debt_ceiling
+        U64_LEN, // interest_rate
+        U64_LEN, // liquidation_grace_period
+        U64_LEN, // critical_collateral_ratio
+        U64_LEN, // flash_mint_fee
+    ]);
+
+    /// Reject parameters outside of the allowed ranges
+    pub fn validate(&self) -> ProgramResult {
+        let fee_floor = self.deposit_fee.checked_add(self.team_fee).ok_or(NexfinError::MathOverflow)?;
+        let min_fee = self.min_deposit_fee.checked_add(self.min_team_fee).ok_or(NexfinError::MathOverflow)?;
+
+        // at most 10% borrowing fee and 10 stable coins minimum fee
+        if fee_floor > 1_000 || min_fee > 100_000 {
+            return Err(NexfinError::InvalidConfig.into());
+        }
+
+        // the borrowing fee floor is the depositor and team fee
+        if self.max_borrowing_fee < fee_floor || self.max_borrowing_fee > 1_000 {
+            return Err(NexfinError::InvalidConfig.into());
+        }
+
+        if self.collateral_ratio < 100 || self.collateral_ratio > 1_000 {
+            return Err(NexfinError::InvalidConfig.into());
+        }
+
+        if self.liquidation_ratio < 100 || self.liquidation_ratio > self.collateral_ratio {
+            return Err(NexfinError::InvalidConfig.into());
+        }
+
+        // troves between the collateral ratio and the critical ratio are liquidatable in recovery mode
+        if self.critical_collateral_ratio < self.collateral_ratio || self.critical_collateral_ratio > 1_000 {
+            return Err(NexfinError::InvalidConfig.into());
+        }
+
+        // at most 10% of the seized collateral for the liquidator
+        if self.liquidator_reward > LIQUIDATOR_REWARD_DENOMINATOR / 10 {
+            return Err(NexfinError::InvalidConfig.into());
+        }
+
+        if self.min_collateral > 100 || self.gas_fee > self.min_borrow_amount {
+            return Err(NexfinError::InvalidConfig.into());
+        }
+
+        // the minimum borrow is the debt floor of the troves
+        if self.min_borrow_amount < MIN_TROVE_DEBT || self.debt_ceiling < self.min_borrow_amount {
+            return Err(NexfinError::InvalidConfig.into());
+        }
+
+        // at most 20% yearly interest
+        if self.interest_rate > 2_000 {
+            return Err(NexfinError::InvalidConfig.into());
+        }
+
+        // at most one day in the liquidation queue
+        if self.liquidation_grace_period > 86_400 {
+            return Err(NexfinError::InvalidConfig.into());
+        }
+
+        // at most 1% flash mint fee
+        if self.flash_mint_fee > 100 {
+            return Err(NexfinError::InvalidConfig.into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Layout version written after the discriminator of the versioned accounts
+pub const ACCOUNT_VERSION: u8 = 1;
+
+/// Maximum number of collateral token types registered in the config besides SOL
+pub const MAX_COLLATERAL_TYPES: usize = 4;
+
+/// Maximum number of troves tracked by the trove manager sorted list, keeps the account under
+/// the 10KB limit of the accounts created by the program. Lowered from 200 when the entries
+/// started to carry the trove collateral and debt
+pub const MAX_SORTED_TROVES: usize = 150;
+
+/// Precision of the redemption base rate, 1e18 = 100%
+pub const DECIMAL_PRECISION: u128 = 1_000_000_000_000_000_000;
+/// Precision of the nominal collateral ratio used to sort the troves
+pub const NICR_PRECISION: u128 = 100_000_000_000_000_000_000;
+
+/// 0.5% floor on the redemption fee
+pub const REDEMPTION_FEE_FLOOR: u64 = 5_000_000_000_000_000;
+/// Divisor applied to the redeemed fraction of the supply when raising the base rate
+pub const BETA: u64 = 2;
+/// Base rate decay per minute for a 12 hour half life
+pub const MINUTE_DECAY_FACTOR: u128 = 999_037_758_833_783_000;
+pub const SECONDS_IN_ONE_MINUTE: i64 = 60;
+
+/// The stability pool product moves to the next scale once it falls under 1e9
+pub const SCALE_FACTOR: u128 = 1_000_000_000;
+/// Number of (epoch, scale) reward sums kept by the stability pool, a deposit snapshot reads the
+/// sums of its own scale and the next one so only the sums no deposit snapshot reads are dropped
+pub const MAX_POOL_SCALES: usize = 32;
+/// Governance issuance decay per minute, half of the remaining supply is issued each year
+pub const GOVERNANCE_ISSUANCE_FACTOR: u128 = 999_998_681_227_695_000;
+
+/// Flags of the config pause bitmask, each one stops a group of instructions
+pub const PAUSE_BORROWING: u8 = 1 << 0;
+pub const PAUSE_DEPOSITS: u8 = 1 << 1;
+pub const PAUSE_WITHDRAWALS: u8 = 1 << 2;
+pub const PAUSE_LIQUIDATIONS: u8 = 1 << 3;
+pub const PAUSE_REDEMPTIONS: u8 = 1 << 4;
+pub const PAUSE_ALL: u8 = PAUSE_BORROWING | PAUSE_DEPOSITS | PAUSE_WITHDRAWALS | PAUSE_LIQUIDATIONS | PAUSE_REDEMPTIONS;
@@ -1,4 +1,5 @@
 use crate::*;
+use crate::error::NexfinError;
 use anchor_lang::prelude::AccountInfo;
 use bytemuck::{cast_slice_mut, from_bytes_mut, try_cast_slice_mut, Pod, Zeroable};
 use std::cell::RefMut;
@@ -103,10 +104,14 @@ impl Price {
         let account_data: RefMut<'a, [u8]>;
         let state: RefMut<'a, Self>;
 
-        account_data = RefMut::map(price_feed.try_borrow_mut_data().unwrap(), |data| *data);
+        if price_feed.data_len() < std::mem::size_of::<Price>() {
+            return Err(NexfinError::InvalidOracleConfig.into());
+        }
+
+        account_data = RefMut::map(price_feed.try_borrow_mut_data()?, |data| *data);
 
         state = RefMut::map(account_data, |data| {
-            from_bytes_mut(cast_slice_mut::<u8, u8>(try_cast_slice_mut(data).unwrap()))
+            from_bytes_mut(cast_slice_mut::<u8, u8>(try_cast_slice_mut(&mut data[..std::mem::size_of::<Price>()]).unwrap()))
         });
         Ok(state)
     }
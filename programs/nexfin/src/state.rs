@@ -1,10 +1,71 @@
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program::{program_error::ProgramError, pubkey::Pubkey};
 
+/// Borsh length of the field types of the accounts
+pub const BOOL_LEN: usize = 1;
+pub const U8_LEN: usize = 1;
+pub const I32_LEN: usize = 4;
+pub const U64_LEN: usize = 8;
+pub const I64_LEN: usize = 8;
+pub const U128_LEN: usize = 16;
+pub const PUBKEY_LEN: usize = 32;
+/// Length prefix of a borsh `Vec`
+pub const VEC_PREFIX_LEN: usize = 4;
+
+/// Borsh length of an account layout from the lengths of its fields, without the discriminator
+pub const fn layout_len(fields: &[usize]) -> usize {
+    let mut len = 0;
+    let mut index = 0;
+    while index < fields.len() {
+        len += fields[index];
+        index += 1;
+    }
+    len
+}
+
+/// Secondary SOL/USD price updated by the crank, with the pyth exponent of -8
 #[account]
 #[derive(Default, Debug)]
 pub struct Price {
     pub price: i64,
+    pub conf: u64,
+    pub pub_slot: u64,
+    pub bump: u8,
+}
+
+impl Price {
+    pub const LEN: usize = layout_len(&[
+        I64_LEN, // price
+        U64_LEN, // conf
+        U64_LEN, // pub_slot
+        U8_LEN,  // bump
+    ]);
+}
+
+/// Price feed moved by the admin through `set_price`, read in place of a pyth price account
+/// owned by the program. Only compiled with the `test-oracle` feature.
+#[cfg(feature = "test-oracle")]
+#[account]
+#[derive(Default, Debug)]
+pub struct MockPrice {
+    pub bump: u8,
+    pub price: i64,
+    pub conf: u64,
+    pub expo: i32,
+    pub pub_slot: u64,
+    pub is_trading: bool,
+}
+
+#[cfg(feature = "test-oracle")]
+impl MockPrice {
+    pub const LEN: usize = layout_len(&[
+        U8_LEN,   // bump
+        I64_LEN,  // price
+        U64_LEN,  // conf
+        I32_LEN,  // expo
+        U64_LEN,  // pub_slot
+        BOOL_LEN, // is_trading
+    ]);
 }
 
 #[account]
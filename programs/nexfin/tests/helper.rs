@@ -1,22 +1,29 @@
 #![cfg(feature = "test-bpf")]
+#![allow(dead_code)]
 
 use anchor_lang::prelude::Pubkey;
-use anchor_lang::solana_program::{instruction::Instruction, program_pack::Pack};
-use anchor_lang::{InstructionData, ToAccountMetas};
-use anchor_spl::token::transfer;
+use anchor_lang::solana_program::{instruction::Instruction, program_option::COption, program_pack::Pack, system_program, sysvar};
+use anchor_lang::{AccountDeserialize, AccountSerialize, InstructionData, ToAccountMetas};
 use assert_matches::assert_matches;
-use nexfin_program::helpers::{get_depositors_fee, get_team_fee, get_trove_debt_amount};
-use nexfin_program::params;
-use nexfin_program::state::Trove;
-use solana_program_test::BanksClient;
-use solana_program_test::{processor, tokio, ProgramTest};
+use nexfin_program::error::NexfinError;
+use nexfin_program::params::{ProtocolParams, ACCOUNT_VERSION, DECIMAL_PRECISION};
+use nexfin_program::{state, Admin, Config, Fee, StabilityPool, TroveManager};
+use solana_program_test::{processor, BanksClient, ProgramTest, ProgramTestContext};
+use solana_sdk::account::{Account, AccountSharedData};
+use solana_sdk::instruction::InstructionError;
+use solana_sdk::native_token::LAMPORTS_PER_SOL;
+use solana_sdk::rent::Rent;
 use solana_sdk::signature::{Keypair, Signer};
-use solana_sdk::transaction::Transaction;
-use solana_sdk::transport::Result;
-use solana_sdk::{commitment_config::CommitmentLevel, system_instruction, system_program};
-use spl_associated_token_account;
-use std::mem::size_of;
-use std::str::FromStr;
+use solana_sdk::transaction::{Transaction, TransactionError};
+use solana_sdk::transport::TransportError;
+
+/// 100 USD per SOL with the -8 exponent of the SOL/USD feeds
+pub const SOL_PRICE: i64 = 10_000_000_000;
+pub const STABLE_DECIMALS: u8 = 6;
+/// Coins held by the stability pool at the start of every test
+pub const POOL_DEPOSITS: u64 = 10_000;
+/// Lamports of the admin, the guardian and the borrower at the start of every test
+pub const USER_LAMPORTS: u64 = 1_000 * LAMPORTS_PER_SOL;
 
 pub async fn process_and_assert_ok(
     instructions: &[Instruction],
@@ -39,198 +46,337 @@ pub async fn process_and_assert_ok(
     assert_matches!(banks_client.process_transaction(tx).await, Ok(()));
 }
 
-pub struct InitResult {
-    pub program_id: Pubkey,
-    payer: [u8; 64],
-    authority: [u8; 64],
-    trove: [u8; 64],
-    token_mint: [u8; 64],
-    pub banks_client: BanksClient,
+/// Assert that the transaction failed on `error` of the program
+pub fn assert_nexfin_error(result: Result<(), TransportError>, error: NexfinError) {
+    assert_matches!(
+        result,
+        Err(TransportError::TransactionError(TransactionError::InstructionError(_, InstructionError::Custom(code)))) if code == error as u32
+    );
 }
 
-impl InitResult {
-    pub fn authority(&self) -> Keypair {
-        Keypair::from_bytes(self.authority.as_ref()).unwrap()
+/// Address and bump of a PDA of the program
+pub fn pda(seeds: &[&[u8]]) -> (Pubkey, u8) {
+    Pubkey::find_program_address(seeds, &nexfin_program::id())
+}
+
+pub fn trove_address(authority: &Pubkey) -> (Pubkey, u8) {
+    pda(&[b"borrowertrove", authority.as_ref()])
+}
+
+pub fn sol_trove_address(authority: &Pubkey) -> (Pubkey, u8) {
+    pda(&[b"solTrove", authority.as_ref()])
+}
+
+/// Rent exempt account of the program holding `account`, padded to its `len`
+pub fn program_account<T: AccountSerialize>(account: &T, len: usize) -> Account {
+    let mut data = Vec::new();
+    account.try_serialize(&mut data).unwrap();
+    data.resize(len + 8, 0);
+
+    Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner: nexfin_program::id(),
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+pub fn mint_account(mint_authority: &Pubkey, supply: u64) -> Account {
+    let mut data = vec![0; spl_token::state::Mint::LEN];
+    spl_token::state::Mint {
+        mint_authority: COption::Some(*mint_authority),
+        supply,
+        decimals: STABLE_DECIMALS,
+        is_initialized: true,
+        freeze_authority: COption::None,
     }
-    pub fn payer(&self) -> Keypair {
-        Keypair::from_bytes(self.payer.as_ref()).unwrap()
+    .pack_into_slice(&mut data);
+
+    Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner: spl_token::id(),
+        executable: false,
+        rent_epoch: 0,
     }
+}
 
-    pub fn trove(&self) -> Keypair {
-        Keypair::from_bytes(self.trove.as_ref()).unwrap()
+pub fn token_account(mint: &Pubkey, owner: &Pubkey, amount: u64) -> Account {
+    let mut data = vec![0; spl_token::state::Account::LEN];
+    spl_token::state::Account {
+        mint: *mint,
+        owner: *owner,
+        amount,
+        delegate: COption::None,
+        state: spl_token::state::AccountState::Initialized,
+        is_native: COption::None,
+        delegated_amount: 0,
+        close_authority: COption::None,
     }
+    .pack_into_slice(&mut data);
 
-    pub fn token_mint(&self) -> Keypair {
-        Keypair::from_bytes(self.token_mint.as_ref()).unwrap()
+    Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner: spl_token::id(),
+        executable: false,
+        rent_epoch: 0,
     }
 }
 
-pub async fn setup() -> InitResult {
-    let program_id = Pubkey::from_str("5kLDDxNQzz82UtPA5hJmyKR3nUKBtRTfu4nXaGZmLanS").unwrap();
-    let program_test = ProgramTest::new(
+/// Base units of `coins` stable coins
+pub fn coins(coins: u64) -> u64 {
+    coins * 10u64.pow(STABLE_DECIMALS as u32)
+}
+
+/// Protocol running in a `ProgramTest` bank with an initialized config, trove manager,
+/// stability pool holding `POOL_DEPOSITS` coins and fee accounts.
+///
+/// The primary SOL feed of the config holds no price account, every instruction reads the
+/// secondary price which the tests move with `set_sol_price`.
+pub struct Protocol {
+    pub context: ProgramTestContext,
+    admin: [u8; 64],
+    authority: [u8; 64],
+    guardian: [u8; 64],
+    pub stable_coin: Pubkey,
+    pub user_token: Pubkey,
+    pub pyth_sol_account: Pubkey,
+}
+
+pub async fn setup() -> Protocol {
+    setup_with(|_| {}).await
+}
+
+/// Set up the protocol, `add_programs` registers the other programs of the test
+pub async fn setup_with<F: FnOnce(&mut ProgramTest)>(add_programs: F) -> Protocol {
+    let mut program_test = ProgramTest::new(
         "nexfin_program",
-        program_id,
+        nexfin_program::id(),
         processor!(nexfin_program::entry),
     );
+    add_programs(&mut program_test);
 
-    let (mut banks_client, payer, _) = program_test.start().await;
-
+    let admin = Keypair::new();
     let authority = Keypair::new();
-    let trove = Keypair::new();
-
-    let rent = banks_client.get_rent().await.unwrap();
-    let space = size_of::<Trove>() as u64 + 8;
-    let create_ix = system_instruction::create_account(
-        &payer.pubkey(),
-        &trove.pubkey(),
-        rent.minimum_balance(space as usize),
-        space,
-        &program_id,
-    );
-    process_and_assert_ok(&[create_ix], &payer, &[&payer, &trove], &mut banks_client).await;
-
-    let token_mint = Keypair::new();
-
-    init_user_token(&mut banks_client, &authority, &token_mint, &payer).await;
+    let guardian = Keypair::new();
+    let stable_coin = Pubkey::new_unique();
+    let user_token = Pubkey::new_unique();
+    let pyth_sol_account = Pubkey::new_unique();
 
-    InitResult {
-        program_id,
-        authority: authority.to_bytes(),
-        banks_client,
-        trove: trove.to_bytes(),
-        payer: payer.to_bytes(),
-        token_mint: token_mint.to_bytes(),
+    for user in [&admin, &authority, &guardian].iter() {
+        program_test.add_account(user.pubkey(), Account::new(USER_LAMPORTS, 0, &system_program::id()));
     }
-}
 
-pub async fn initialize_mint(
-    banks_client: &mut BanksClient,
-    payer: &Keypair,
-    token_mint: &Keypair,
-    authority: &Pubkey,
-    decimals: u8,
-) {
-    // Fund authority
-
-    let rent = banks_client.get_rent().await.unwrap();
-    let token_mint_account_rent = rent.minimum_balance(spl_token::state::Mint::LEN);
-    let recent_blockhash = banks_client.get_recent_blockhash().await.unwrap();
-    let transaction = Transaction::new_signed_with_payer(
-        &[
-            system_instruction::create_account(
-                &payer.pubkey(),
-                &token_mint.pubkey(),
-                token_mint_account_rent,
-                spl_token::state::Mint::LEN as u64,
-                &spl_token::id(),
-            ),
-            spl_token::instruction::initialize_mint(
-                &spl_token::id(),
-                &token_mint.pubkey(),
-                authority,
-                None,
-                decimals,
-            )
-            .unwrap(),
-        ],
-        Some(&payer.pubkey()),
-        &[payer, token_mint],
-        recent_blockhash,
+    let (admin_account, admin_bump) = pda(&[b"admin", admin.pubkey().as_ref()]);
+    program_test.add_account(
+        admin_account,
+        program_account(&Admin { bump: admin_bump, authority: admin.pubkey() }, Admin::LEN),
     );
 
-    assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
-}
+    let (config, config_bump) = pda(&[b"config"]);
+    let (token_authority, _) = pda(&[b"mint-authority"]);
+    program_test.add_account(
+        config,
+        program_account(
+            &Config {
+                version: ACCOUNT_VERSION,
+                bump: config_bump,
+                is_initialized: true,
+                mint_account_authority: token_authority,
+                admin_account_authority: admin_account,
+                pyth_sol_account,
+                secondary_price_source: Pubkey::new_unique(),
+                max_price_staleness: u64::MAX,
+                max_price_confidence: 10_000,
+                max_price_deviation: 10_000,
+                params: ProtocolParams::default(),
+                guardian: guardian.pubkey(),
+                ..Config::default()
+            },
+            Config::LEN,
+        ),
+    );
 
-pub async fn mint_to(
-    owner: &Keypair,
-    token_mint: &Pubkey,
-    account_pubkey: &Pubkey,
-    amount: u64,
-    banks_client: &mut BanksClient,
-) {
-    let recent_blockhash = banks_client.get_recent_blockhash().await.unwrap();
-    let transaction = Transaction::new_signed_with_payer(
-        &[spl_token::instruction::mint_to(
-            &spl_token::id(),
-            token_mint,
-            account_pubkey,
-            &owner.pubkey(),
-            &[],
-            amount,
-        )
-        .unwrap()],
-        Some(&owner.pubkey()),
-        &[owner],
-        recent_blockhash,
+    let (price, price_bump) = pda(&[b"price"]);
+    program_test.add_account(
+        price,
+        program_account(
+            &state::Price { price: SOL_PRICE, conf: 0, pub_slot: 0, bump: price_bump },
+            state::Price::LEN,
+        ),
     );
 
-    assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
-}
+    let (trove_manager, trove_manager_bump) = pda(&[b"trovemanager"]);
+    program_test.add_account(
+        trove_manager,
+        program_account(&TroveManager { bump: trove_manager_bump, ..TroveManager::default() }, TroveManager::LEN),
+    );
 
-async fn init_user_token(
-    banks_client: &mut BanksClient,
-    user_keypair: &Keypair,
-    token_keypair: &Keypair,
-    payer_keypair: &Keypair,
-) -> Pubkey {
-    initialize_mint(
-        banks_client,
-        &payer_keypair,
-        &token_keypair,
-        &payer_keypair.pubkey(),
-        6,
-    )
-    .await;
-
-    process_ins(
-        banks_client,
-        &[
-            spl_associated_token_account::create_associated_token_account(
-                &payer_keypair.pubkey(),
-                &user_keypair.pubkey(),
-                &token_keypair.pubkey(),
+    let fee_seeds: [&[u8]; 2] = [b"fee", b"teamfee"];
+    for seed in fee_seeds.iter() {
+        let (fee, fee_bump) = pda(&[*seed]);
+        program_test.add_account(
+            fee,
+            program_account(
+                &Fee { version: ACCOUNT_VERSION, bump: fee_bump, is_initialized: true, ..Fee::default() },
+                Fee::LEN,
             ),
-        ],
-        &payer_keypair,
-        &[],
-    )
-    .await
-    .ok()
-    .unwrap_or_else(|| panic!("Can not create ATA account"));
-
-    let user_ata = spl_associated_token_account::get_associated_token_address(
-        &user_keypair.pubkey(),
-        &token_keypair.pubkey(),
+        );
+    }
+
+    let (stability_pool, stability_pool_bump) = pda(&[b"stabilitypool"]);
+    let (deposit_vault, deposit_vault_bump) = pda(&[b"depositvault"]);
+    program_test.add_account(
+        stability_pool,
+        program_account(
+            &StabilityPool {
+                bump: stability_pool_bump,
+                deposit_vault_bump,
+                total_deposits: POOL_DEPOSITS,
+                p: DECIMAL_PRECISION,
+                ..StabilityPool::default()
+            },
+            StabilityPool::LEN,
+        ),
     );
+    program_test.add_account(deposit_vault, token_account(&stable_coin, &stability_pool, coins(POOL_DEPOSITS)));
+
+    let (reward_vault, _) = pda(&[b"rewardVault"]);
+    program_test.add_account(reward_vault, Account::new(Rent::default().minimum_balance(0), 0, &system_program::id()));
+
+    program_test.add_account(stable_coin, mint_account(&token_authority, coins(POOL_DEPOSITS)));
+    program_test.add_account(user_token, token_account(&stable_coin, &authority.pubkey(), 0));
 
-    mint_to(
-        payer_keypair,
-        &token_keypair.pubkey(),
-        &user_ata,
-        1_000_000 * 1_000_000_000,
-        banks_client,
-    )
-    .await;
+    let context = program_test.start_with_context().await;
 
-    user_ata
+    Protocol {
+        context,
+        admin: admin.to_bytes(),
+        authority: authority.to_bytes(),
+        guardian: guardian.to_bytes(),
+        stable_coin,
+        user_token,
+        pyth_sol_account,
+    }
 }
-pub async fn process_ins(
-    banks_client: &mut BanksClient,
-    instructions: &[Instruction],
-    payer: &Keypair,
-    signers: &[&Keypair],
-) -> Result<()> {
-    let recent_blockhash = banks_client.get_recent_blockhash().await.unwrap();
 
-    let mut all_signers = vec![payer];
-    all_signers.extend_from_slice(signers);
+impl Protocol {
+    /// Signer of the admin account of the config
+    pub fn admin(&self) -> Keypair {
+        Keypair::from_bytes(self.admin.as_ref()).unwrap()
+    }
 
-    let mut tx = Transaction::new_with_payer(instructions, Some(&payer.pubkey()));
-    if let Err(e) = tx.try_sign(&all_signers, recent_blockhash) {
-        panic!(">>> Transaction::sign failed with error {:?}", e)
+    /// Borrower owning the user token account
+    pub fn authority(&self) -> Keypair {
+        Keypair::from_bytes(self.authority.as_ref()).unwrap()
     }
 
-    banks_client
-        .process_transaction_with_commitment(tx, CommitmentLevel::Finalized)
-        .await
+    pub fn guardian(&self) -> Keypair {
+        Keypair::from_bytes(self.guardian.as_ref()).unwrap()
+    }
+
+    /// Process `instructions` paid by the payer of the bank
+    pub async fn process(&mut self, instructions: &[Instruction], signers: &[&Keypair]) -> Result<(), TransportError> {
+        // a new blockhash for every transaction, a rejected instruction is often sent again
+        let recent_blockhash = self.context.banks_client.get_new_latest_blockhash(&self.context.last_blockhash).await.unwrap();
+        self.context.last_blockhash = recent_blockhash;
+
+        let mut all_signers = vec![&self.context.payer];
+        all_signers.extend_from_slice(signers);
+
+        let tx = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&self.context.payer.pubkey()),
+            &all_signers,
+            recent_blockhash,
+        );
+
+        self.context.banks_client.process_transaction(tx).await.map_err(Into::into)
+    }
+
+    pub async fn get_account<T: AccountDeserialize>(&mut self, address: Pubkey) -> T {
+        let account = self.context.banks_client.get_account(address).await.unwrap().unwrap();
+        T::try_deserialize(&mut account.data.as_ref()).unwrap()
+    }
+
+    pub async fn set_account<T: AccountSerialize>(&mut self, address: Pubkey, account: &T, len: usize) {
+        self.context.set_account(&address, &AccountSharedData::from(program_account(account, len)));
+    }
+
+    pub async fn lamports(&mut self, address: Pubkey) -> u64 {
+        self.context.banks_client.get_balance(address).await.unwrap()
+    }
+
+    pub async fn token_amount(&mut self, address: Pubkey) -> u64 {
+        let account = self.context.banks_client.get_account(address).await.unwrap().unwrap();
+        spl_token::state::Account::unpack(&account.data).unwrap().amount
+    }
+
+    pub async fn config(&mut self) -> Config {
+        self.get_account(pda(&[b"config"]).0).await
+    }
+
+    /// Change the config in place, as the admin instructions would
+    pub async fn update_config<F: FnOnce(&mut Config)>(&mut self, update: F) {
+        let mut config = self.config().await;
+        update(&mut config);
+        self.set_account(pda(&[b"config"]).0, &config, Config::LEN).await;
+    }
+
+    /// Move the SOL/USD price read by the instructions
+    pub async fn set_sol_price(&mut self, price: i64) {
+        let (address, bump) = pda(&[b"price"]);
+        self.set_account(address, &state::Price { price, conf: 0, pub_slot: 0, bump }, state::Price::LEN).await;
+    }
+
+    pub fn borrow_instruction(&self, borrow_amount: u64, lamports: u64) -> Instruction {
+        let authority = self.authority().pubkey();
+        let (trove_account, trove_account_bump) = trove_address(&authority);
+        let (sol_trove, sol_account_bump) = sol_trove_address(&authority);
+        let (fee_account, fee_account_bump) = pda(&[b"fee"]);
+        let (team_fee_account, team_fee_account_bump) = pda(&[b"teamfee"]);
+        let (token_authority, mint_account_bump) = pda(&[b"mint-authority"]);
+
+        Instruction {
+            program_id: nexfin_program::id(),
+            accounts: nexfin_program::accounts::Borrow {
+                authority,
+                trove_account,
+                trove_manager: pda(&[b"trovemanager"]).0,
+                sol_trove,
+                fee_account,
+                team_fee_account,
+                token_authority,
+                stable_coin: self.stable_coin,
+                user_token: self.user_token,
+                pyth_sol_account: self.pyth_sol_account,
+                secondary_price: pda(&[b"price"]).0,
+                config: pda(&[b"config"]).0,
+                clock: sysvar::clock::ID,
+                system_program: system_program::ID,
+                token_program: spl_token::ID,
+                rent: sysvar::rent::ID,
+            }
+            .to_account_metas(None),
+            data: nexfin_program::instruction::Borrow {
+                borrow_amount,
+                lamports,
+                trove_account_bump,
+                sol_account_bump,
+                mint_account_bump,
+                fee_account_bump,
+                team_fee_account_bump,
+            }
+            .data(),
+        }
+    }
+
+    /// Open the trove of the borrower, the borrowed coins are minted to the user token account
+    pub async fn open_trove(&mut self, borrow_amount: u64, lamports: u64) {
+        let authority = self.authority();
+        let borrow = self.borrow_instruction(borrow_amount, lamports);
+        assert_matches!(self.process(&[borrow], &[&authority]).await, Ok(()));
+    }
 }
@@ -0,0 +1,171 @@
+#![cfg(feature = "test-bpf")]
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::{AccountSerialize, AnchorSerialize};
+use nexfin_program::migration::{ConfigV0, DepositV0, FeeV0, TroveV0, Versioned};
+use nexfin_program::params::{ProtocolParams, MAX_COLLATERAL_TYPES, MAX_POOL_SCALES, MAX_SORTED_TROVES};
+use nexfin_program::{state, Admin, CollateralType, Config, Deposit, DepositSnapshot, Fee, PoolSum, SortedTrove, StabilityPool, Trove, TroveManager};
+use std::mem::size_of;
+
+/// Bytes written by anchor for the account, discriminator included
+fn serialized_len<T: AccountSerialize>(account: &T) -> usize {
+    let mut data = Vec::new();
+    account.try_serialize(&mut data).unwrap();
+    data.len()
+}
+
+#[test]
+fn test_fixed_account_space() {
+    assert_eq!(serialized_len(&Config::default()), Config::LEN + 8);
+    assert_eq!(serialized_len(&Deposit::default()), Deposit::LEN + 8);
+    assert_eq!(serialized_len(&Trove::default()), Trove::LEN + 8);
+    assert_eq!(serialized_len(&Fee::default()), Fee::LEN + 8);
+    assert_eq!(serialized_len(&Admin::default()), Admin::LEN + 8);
+    assert_eq!(serialized_len(&state::Price::default()), state::Price::LEN + 8);
+}
+
+fn collateral_type() -> CollateralType {
+    CollateralType {
+        mint: Pubkey::new_unique(),
+        oracle: Pubkey::new_unique(),
+        decimals: 6,
+        vault_bump: 255,
+        max_ltv: 7_500,
+        liquidation_threshold: 8_000,
+    }
+}
+
+fn deposit_snapshot() -> DepositSnapshot {
+    DepositSnapshot {
+        p: u128::MAX,
+        coin_sum: u128::MAX,
+        token_sum: u128::MAX,
+        governance_sum: u128::MAX,
+        collateral_sums: [u128::MAX; MAX_COLLATERAL_TYPES],
+        scale: u64::MAX,
+        epoch: u64::MAX,
+    }
+}
+
+fn pool_sum() -> PoolSum {
+    PoolSum {
+        epoch: u64::MAX,
+        scale: u64::MAX,
+        coin_sum: u128::MAX,
+        token_sum: u128::MAX,
+        governance_sum: u128::MAX,
+        collateral_sums: [u128::MAX; MAX_COLLATERAL_TYPES],
+        deposits: u64::MAX,
+    }
+}
+
+fn sorted_trove() -> SortedTrove {
+    SortedTrove {
+        authority: Pubkey::new_unique(),
+        nicr: u128::MAX,
+        collateral: u64::MAX,
+        debt: u64::MAX,
+    }
+}
+
+#[test]
+fn test_populated_field_space() {
+    assert_eq!(ProtocolParams::default().try_to_vec().unwrap().len(), ProtocolParams::LEN);
+    assert_eq!(collateral_type().try_to_vec().unwrap().len(), CollateralType::LEN);
+    assert_eq!(deposit_snapshot().try_to_vec().unwrap().len(), DepositSnapshot::LEN);
+    assert_eq!(pool_sum().try_to_vec().unwrap().len(), PoolSum::LEN);
+    assert_eq!(sorted_trove().try_to_vec().unwrap().len(), SortedTrove::LEN);
+}
+
+#[test]
+fn test_populated_account_space() {
+    let config = Config {
+        version: 1,
+        bump: 255,
+        is_initialized: true,
+        admin_account_authority: Pubkey::new_unique(),
+        params: ProtocolParams::default(),
+        cumulative_interest_index: u128::MAX,
+        collateral_types: [collateral_type(); MAX_COLLATERAL_TYPES],
+        guardian: Pubkey::new_unique(),
+        paused: u8::MAX,
+        pending_admin: Pubkey::new_unique(),
+        reserved: [u64::MAX; 4],
+        ..Config::default()
+    };
+    assert_eq!(serialized_len(&config), Config::LEN + 8);
+
+    let deposit = Deposit {
+        token_amount: u64::MAX,
+        authority: Pubkey::new_unique(),
+        snapshot: deposit_snapshot(),
+        reward_collateral_amounts: [u64::MAX; MAX_COLLATERAL_TYPES],
+        ..Deposit::default()
+    };
+    assert_eq!(serialized_len(&deposit), Deposit::LEN + 8);
+
+    let trove = Trove {
+        amount_to_close: u64::MAX,
+        authority: Pubkey::new_unique(),
+        interest_index: u128::MAX,
+        received_at: i64::MIN,
+        collaterals: [u64::MAX; MAX_COLLATERAL_TYPES],
+        ..Trove::default()
+    };
+    assert_eq!(serialized_len(&trove), Trove::LEN + 8);
+
+    let admin = Admin {
+        bump: 255,
+        authority: Pubkey::new_unique(),
+    };
+    assert_eq!(serialized_len(&admin), Admin::LEN + 8);
+
+    let price = state::Price {
+        price: i64::MIN,
+        conf: u64::MAX,
+        pub_slot: u64::MAX,
+        bump: 255,
+    };
+    assert_eq!(serialized_len(&price), state::Price::LEN + 8);
+}
+
+#[cfg(feature = "test-oracle")]
+#[test]
+fn test_mock_price_space() {
+    let mock_price = state::MockPrice {
+        bump: 255,
+        price: i64::MIN,
+        conf: u64::MAX,
+        expo: -8,
+        pub_slot: u64::MAX,
+        is_trading: true,
+    };
+    assert_eq!(serialized_len(&mock_price), state::MockPrice::LEN + 8);
+}
+
+#[test]
+fn test_full_list_account_space() {
+    let trove_manager = TroveManager {
+        troves: vec![sorted_trove(); MAX_SORTED_TROVES],
+        ..TroveManager::default()
+    };
+    assert_eq!(serialized_len(&trove_manager), TroveManager::LEN + 8);
+
+    let stability_pool = StabilityPool {
+        sums: vec![pool_sum(); MAX_POOL_SCALES],
+        ..StabilityPool::default()
+    };
+    assert_eq!(serialized_len(&stability_pool), StabilityPool::LEN + 8);
+
+    // the program can only create accounts up to 10KB
+    assert!(TroveManager::LEN + 8 <= 10_240);
+    assert!(StabilityPool::LEN + 8 <= 10_240);
+}
+
+#[test]
+fn test_v0_accounts_are_shorter_than_the_current_layout() {
+    // the v0 accounts were allocated with `size_of` plus the discriminator twice
+    assert!(size_of::<TroveV0>() + 16 < Trove::SPACE);
+    assert!(size_of::<DepositV0>() + 16 < Deposit::SPACE);
+    assert!(size_of::<ConfigV0>() + 16 < Config::SPACE);
+    assert!(size_of::<FeeV0>() + 16 < Fee::SPACE);
+}
@@ -0,0 +1,89 @@
+#![cfg(feature = "test-bpf")]
+use anchor_lang::prelude::{ProgramError, Pubkey};
+use anchor_lang::{solana_program::system_program, ToAccountMetas};
+use nexfin_program::error::NexfinError;
+use nexfin_program::Config;
+
+fn config_with_admin(admin_account_authority: Pubkey) -> Config {
+    Config {
+        is_initialized: true,
+        admin_account_authority,
+        ..Config::default()
+    }
+}
+
+#[test]
+fn test_pending_admin_accepts() {
+    let previous_admin_account = Pubkey::new_unique();
+    let pending_admin = Pubkey::new_unique();
+    let admin_account = Pubkey::new_unique();
+    let mut config = config_with_admin(previous_admin_account);
+
+    config.propose_admin(pending_admin);
+    assert_eq!(config.pending_admin, pending_admin);
+
+    assert_eq!(config.accept_admin(&pending_admin, admin_account), Ok(previous_admin_account));
+    assert_eq!(config.admin_account_authority, admin_account);
+    assert_eq!(config.pending_admin, Pubkey::default());
+}
+
+#[test]
+fn test_default_key_cancels_the_proposal() {
+    let previous_admin_account = Pubkey::new_unique();
+    let pending_admin = Pubkey::new_unique();
+    let mut config = config_with_admin(previous_admin_account);
+
+    config.propose_admin(pending_admin);
+    config.propose_admin(Pubkey::default());
+
+    assert_eq!(
+        config.accept_admin(&pending_admin, Pubkey::new_unique()),
+        Err(ProgramError::from(NexfinError::OnlyForPendingAdmin))
+    );
+    // nobody can accept a cancelled proposal, not even with the default key
+    assert_eq!(
+        config.accept_admin(&Pubkey::default(), Pubkey::new_unique()),
+        Err(ProgramError::from(NexfinError::OnlyForPendingAdmin))
+    );
+    assert_eq!(config.admin_account_authority, previous_admin_account);
+}
+
+#[test]
+fn test_only_the_pending_admin_accepts() {
+    let previous_admin_account = Pubkey::new_unique();
+    let pending_admin = Pubkey::new_unique();
+    let mut config = config_with_admin(previous_admin_account);
+
+    config.propose_admin(pending_admin);
+
+    assert_eq!(
+        config.accept_admin(&Pubkey::new_unique(), Pubkey::new_unique()),
+        Err(ProgramError::from(NexfinError::OnlyForPendingAdmin))
+    );
+    assert_eq!(config.admin_account_authority, previous_admin_account);
+    // the proposal stays open for the pending admin
+    assert_eq!(config.pending_admin, pending_admin);
+}
+
+#[test]
+fn test_admin_account_is_paid_by_a_separate_payer() {
+    let authority = Pubkey::new_unique();
+    let payer = Pubkey::new_unique();
+    let metas = nexfin_program::accounts::AcceptAdmin {
+        authority,
+        payer,
+        admin_account: Pubkey::new_unique(),
+        config: Pubkey::new_unique(),
+        system_program: system_program::ID,
+    }
+    .to_account_metas(None);
+
+    let authority_meta = metas.iter().find(|meta| meta.pubkey == authority).unwrap();
+    assert!(authority_meta.is_signer);
+    // the pending admin does not need to hold SOL for the admin account rent
+    assert!(!authority_meta.is_writable);
+
+    let payer_meta = metas.iter().find(|meta| meta.pubkey == payer).unwrap();
+    assert!(payer_meta.is_signer);
+    assert!(payer_meta.is_writable);
+}
@@ -0,0 +1,39 @@
+#![cfg(feature = "test-bpf")]
+use nexfin_program::amount::{StableAmount, REWARD_DECIMALS};
+
+#[test]
+fn test_amount_two_decimals() {
+    let amount = StableAmount::from_coins(150, 2).unwrap();
+    assert_eq!(amount.base_units(), 15_000);
+    assert_eq!(amount.coins().unwrap(), 150);
+
+    // 1234 hundredths of a coin
+    let reward = StableAmount::from_scaled(1_234, REWARD_DECIMALS, 2).unwrap();
+    assert_eq!(reward.base_units(), 1_234);
+}
+
+#[test]
+fn test_amount_nine_decimals() {
+    let amount = StableAmount::from_coins(150, 9).unwrap();
+    assert_eq!(amount.base_units(), 150_000_000_000);
+    assert_eq!(amount.coins().unwrap(), 150);
+
+    let reward = StableAmount::from_scaled(1_234, REWARD_DECIMALS, 9).unwrap();
+    assert_eq!(reward.base_units(), 12_340_000_000);
+}
+
+#[test]
+fn test_amount_scaled_down_truncates() {
+    let reward = StableAmount::from_scaled(1_234, REWARD_DECIMALS, 0).unwrap();
+    assert_eq!(reward.base_units(), 12);
+
+    let amount = StableAmount::from_base_units(15_099, 2);
+    assert_eq!(amount.coins().unwrap(), 150);
+}
+
+#[test]
+fn test_amount_overflow() {
+    assert!(StableAmount::from_coins(u64::MAX, 2).is_err());
+    assert!(StableAmount::from_coins(20_000_000_000, 9).is_err());
+    assert!(StableAmount::from_coins(1, 20).is_err());
+}
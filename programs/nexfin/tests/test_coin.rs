@@ -1,114 +1,142 @@
 #![cfg(feature = "test-bpf")]
 #![allow(unused)]
 mod helper;
-use anchor_lang::solana_program::{instruction::Instruction, pubkey::Pubkey, sysvar};
+use anchor_lang::solana_program::{instruction::{AccountMeta, Instruction}, native_token::LAMPORTS_PER_SOL, pubkey::Pubkey, system_program, sysvar};
 use anchor_lang::AccountDeserialize;
 use anchor_lang::{InstructionData, ToAccountMetas};
-use anchor_spl::token;
-use helper::process_and_assert_ok;
-use solana_program_test::{processor, tokio, ProgramTest};
+use helper::{assert_nexfin_error, coins, pda, sol_trove_address, trove_address, Protocol, SOL_PRICE};
+use solana_program_test::tokio;
 use solana_sdk::signature::{Keypair, Signer};
-use solana_sdk::system_instruction;
 
-use nexfin_program::helpers::{get_depositors_fee, get_team_fee, get_trove_debt_amount};
-use nexfin_program::{params, state::Trove};
+use nexfin_program::error::NexfinError;
+use nexfin_program::{Fee, Trove, TroveManager};
 
-#[tokio::test]
-async fn test_withdraw() {
-    let init = helper::setup().await;
-
-    let program_id = init.program_id;
-    let authority = init.authority();
-    let trove = init.trove();
-    let payer = init.payer();
-    let mut banks_client = init.banks_client;
-
-    let borrow_amount = 100;
-    let lamports = 100;
-    let inx = Instruction {
-        program_id,
-        accounts: nexfin_program::accounts::Borrow {
-            authority: authority.pubkey(),
-            trove: trove.pubkey(),
-            rent: sysvar::rent::ID,
-        }
-        .to_account_metas(None),
-        data: nexfin_program::instruction::Borrow {
-            borrow_amount,
-            lamports,
-        }
-        .data(),
-    };
-
-    process_and_assert_ok(&[inx], &payer, &[&payer, &authority], &mut banks_client).await;
+fn withdraw_coin_instruction(protocol: &Protocol, amount: u64) -> Instruction {
+    let authority = protocol.authority().pubkey();
+    let (trove, trove_bump) = trove_address(&authority);
 
-    let withdraw_inx = Instruction {
-        program_id,
+    Instruction {
+        program_id: nexfin_program::id(),
         accounts: nexfin_program::accounts::WithdrawCoin {
-            authority: authority.pubkey(),
-            trove: trove.pubkey(),
+            authority,
+            trove,
+            trove_manager: pda(&[b"trovemanager"]).0,
+            fee_account: pda(&[b"fee"]).0,
+            team_fee_account: pda(&[b"teamfee"]).0,
+            sol_trove: sol_trove_address(&authority).0,
+            pyth_sol_account: protocol.pyth_sol_account,
+            secondary_price: pda(&[b"price"]).0,
+            config: pda(&[b"config"]).0,
+            clock: sysvar::clock::ID,
+            system_program: system_program::ID,
         }
         .to_account_metas(None),
         data: nexfin_program::instruction::WithdrawCoin {
-            amount: borrow_amount,
+            amount,
+            _trove_bump: trove_bump,
         }
         .data(),
-    };
-    process_and_assert_ok(
-        &[withdraw_inx],
-        &payer,
-        &[&payer, &authority],
-        &mut banks_client,
-    )
-    .await;
+    }
+}
+
+/// Redeem `amount` coins of the borrower against its own trove
+fn redeem_coin_instruction(protocol: &Protocol, amount: u64) -> Instruction {
+    let authority = protocol.authority().pubkey();
+
+    let mut accounts = nexfin_program::accounts::RedeemCoin {
+        authority,
+        trove_manager: pda(&[b"trovemanager"]).0,
+        fee_account: pda(&[b"fee"]).0,
+        team_fee_account: pda(&[b"teamfee"]).0,
+        stable_coin: protocol.stable_coin,
+        user_token: protocol.user_token,
+        token_program: spl_token::ID,
+        pyth_sol_account: protocol.pyth_sol_account,
+        secondary_price: pda(&[b"price"]).0,
+        config: pda(&[b"config"]).0,
+        clock: sysvar::clock::ID,
+    }
+    .to_account_metas(None);
+    accounts.push(AccountMeta::new(trove_address(&authority).0, false));
+    accounts.push(AccountMeta::new(sol_trove_address(&authority).0, false));
+
+    Instruction {
+        program_id: nexfin_program::id(),
+        accounts,
+        data: nexfin_program::instruction::RedeemCoin { amount }.data(),
+    }
+}
+
+#[tokio::test]
+async fn test_withdraw() {
+    let mut protocol = helper::setup().await;
+    let authority = protocol.authority();
+
+    protocol.open_trove(2_000, 40 * LAMPORTS_PER_SOL).await;
+
+    let withdraw_coin = withdraw_coin_instruction(&protocol, 5 * LAMPORTS_PER_SOL);
+    protocol.process(&[withdraw_coin], &[&authority]).await.unwrap();
+
+    let trove_state: Trove = protocol.get_account(trove_address(&authority.pubkey()).0).await;
+    assert_eq!(trove_state.lamports_amount, 35 * LAMPORTS_PER_SOL);
+
+    // 50% collateral ratio after the withdrawal
+    let withdraw_coin = withdraw_coin_instruction(&protocol, 25 * LAMPORTS_PER_SOL);
+    assert_nexfin_error(protocol.process(&[withdraw_coin], &[&authority]).await, NexfinError::InvalidCollateral);
 }
 
 #[tokio::test]
 async fn test_redeem_coin() {
-    let init = helper::setup().await;
-
-    let program_id = init.program_id;
-    let authority = init.authority();
-    let trove = init.trove();
-    let payer = init.payer();
-    let mut banks_client = init.banks_client;
-
-    let borrow_amount = 100;
-    let lamports = 100;
-    let inx = Instruction {
-        program_id,
-        accounts: nexfin_program::accounts::Borrow {
-            authority: authority.pubkey(),
-            trove: trove.pubkey(),
-            rent: sysvar::rent::ID,
-        }
-        .to_account_metas(None),
-        data: nexfin_program::instruction::Borrow {
-            borrow_amount,
-            lamports,
-        }
-        .data(),
-    };
+    let mut protocol = helper::setup().await;
+    let authority = protocol.authority();
 
-    process_and_assert_ok(&[inx], &payer, &[&payer, &authority], &mut banks_client).await;
+    protocol.open_trove(2_500, 40 * LAMPORTS_PER_SOL).await;
 
-    let withdraw_inx = Instruction {
-        program_id,
-        accounts: nexfin_program::accounts::RedeemCoin {
-            authority: authority.pubkey(),
-            trove: trove.pubkey(),
-        }
-        .to_account_metas(None),
-        data: nexfin_program::instruction::RedeemCoin {
-            amount: borrow_amount,
-        }
-        .data(),
-    };
-    process_and_assert_ok(
-        &[withdraw_inx],
-        &payer,
-        &[&payer, &authority],
-        &mut banks_client,
-    )
-    .await;
+    let sol_trove_lamports = protocol.lamports(sol_trove_address(&authority.pubkey()).0).await;
+    let authority_lamports = protocol.lamports(authority.pubkey()).await;
+    let fee: Fee = protocol.get_account(pda(&[b"fee"]).0).await;
+
+    // 500 coins are worth 5 SOL at the harness price
+    let redeem_coin = redeem_coin_instruction(&protocol, 500);
+    protocol.process(&[redeem_coin], &[&authority]).await.unwrap();
+
+    let trove_state: Trove = protocol.get_account(trove_address(&authority.pubkey()).0).await;
+    assert_eq!(trove_state.amount_to_close, 2_000);
+    assert_eq!(trove_state.lamports_amount, 35 * LAMPORTS_PER_SOL);
+    assert_eq!(protocol.lamports(sol_trove_address(&authority.pubkey()).0).await, sol_trove_lamports - 5 * LAMPORTS_PER_SOL);
+    assert_eq!(protocol.token_amount(protocol.user_token).await, coins(2_000));
+
+    // the redeemer is paid the SOL minus the redemption fee of the depositors
+    let redemption_fee = protocol.get_account::<Fee>(pda(&[b"fee"]).0).await.sol_amount - fee.sol_amount;
+    assert!(redemption_fee > 0);
+    assert_eq!(protocol.lamports(authority.pubkey()).await, authority_lamports + 5 * LAMPORTS_PER_SOL - redemption_fee);
+
+    let trove_manager: TroveManager = protocol.get_account(pda(&[b"trovemanager"]).0).await;
+    assert_eq!(trove_manager.get(&authority.pubkey()).unwrap().debt, 2_000);
+}
+
+#[tokio::test]
+async fn test_redeem_coin_rejects_what_no_trove_pays() {
+    let mut protocol = helper::setup().await;
+    let authority = protocol.authority();
+
+    protocol.open_trove(2_500, 40 * LAMPORTS_PER_SOL).await;
+
+    let redeem_coin = redeem_coin_instruction(&protocol, 0);
+    assert_nexfin_error(protocol.process(&[redeem_coin], &[&authority]).await, NexfinError::InvalidAmount);
+
+    // the trove is left with the minimum debt, nothing can be redeemed from it
+    let redeem_coin = redeem_coin_instruction(&protocol, 500);
+    protocol.process(&[redeem_coin], &[&authority]).await.unwrap();
+    let redeem_coin = redeem_coin_instruction(&protocol, 500);
+    assert_nexfin_error(protocol.process(&[redeem_coin], &[&authority]).await, NexfinError::InsufficientLiquidity);
+
+    // a trove under the liquidation ratio is skipped
+    protocol.set_sol_price(SOL_PRICE / 4).await;
+    let redeem_coin = redeem_coin_instruction(&protocol, 2_000);
+    assert_nexfin_error(protocol.process(&[redeem_coin], &[&authority]).await, NexfinError::InsufficientLiquidity);
+
+    let trove_state: Trove = protocol.get_account(trove_address(&authority.pubkey()).0).await;
+    assert_eq!(trove_state.amount_to_close, 2_000);
+    assert_eq!(protocol.token_amount(protocol.user_token).await, coins(2_000));
 }
@@ -0,0 +1,60 @@
+#![cfg(feature = "test-bpf")]
+use nexfin_program::helpers::{get_collateral_lamports, get_weighted_collateral_lamports};
+use nexfin_program::params::DECIMAL_PRECISION;
+use nexfin_program::{CollateralType, Deposit, StabilityPool};
+
+// $100 with the pyth exponent of -8
+const SOL_PRICE: u128 = 10_000_000_000;
+
+#[test]
+fn test_collateral_lamports() {
+    // one token of 6 decimals at $1
+    assert_eq!(get_collateral_lamports(1_000_000, 6, 100_000_000, -8, SOL_PRICE).unwrap(), 10_000_000);
+    // one token of 9 decimals at $250 with the exponent of -5
+    assert_eq!(get_collateral_lamports(1_000_000_000, 9, 25_000_000, -5, SOL_PRICE).unwrap(), 2_500_000_000);
+
+    assert!(get_collateral_lamports(u64::MAX, 0, u128::MAX, -8, SOL_PRICE).is_err());
+}
+
+#[test]
+fn test_weighted_collateral_lamports() {
+    // $1 at 80% max LTV backs $0.8 of debt, which needs $0.88 of SOL at 110%
+    assert_eq!(get_weighted_collateral_lamports(10_000_000, 8_000, 110).unwrap(), 8_800_000);
+}
+
+#[test]
+fn test_collateral_type_validation() {
+    let collateral_type = |max_ltv, liquidation_threshold| CollateralType {
+        max_ltv,
+        liquidation_threshold,
+        ..CollateralType::default()
+    };
+
+    assert!(collateral_type(7_500, 8_000).validate().is_ok());
+    assert!(collateral_type(0, 8_000).validate().is_err());
+    assert!(collateral_type(8_500, 8_000).validate().is_err());
+    assert!(collateral_type(8_000, 10_001).validate().is_err());
+}
+
+#[test]
+fn test_seized_collateral_is_shared_pro_rata() {
+    let mut pool = StabilityPool {
+        p: DECIMAL_PRECISION,
+        ..StabilityPool::default()
+    };
+    let mut alice = Deposit::default();
+    let mut bob = Deposit::default();
+
+    pool.settle(&mut alice).unwrap();
+    pool.add_to_deposit(&mut alice, 1_000).unwrap();
+    pool.settle(&mut bob).unwrap();
+    pool.add_to_deposit(&mut bob, 3_000).unwrap();
+
+    pool.distribute_collateral(1, 4_000).unwrap();
+    pool.offset(400, 0).unwrap();
+
+    pool.settle(&mut alice).unwrap();
+    pool.settle(&mut bob).unwrap();
+    assert_eq!(alice.reward_collateral_amounts, [0, 1_000, 0, 0]);
+    assert_eq!(bob.reward_collateral_amounts, [0, 3_000, 0, 0]);
+}
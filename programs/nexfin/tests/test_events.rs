@@ -0,0 +1,46 @@
+#![cfg(feature = "test-bpf")]
+use anchor_lang::{AnchorDeserialize, Event};
+use anchor_lang::prelude::Pubkey;
+use nexfin_program::events::{ConfigChange, ConfigChanged, TroveAdjusted};
+use nexfin_program::Trove;
+
+#[test]
+fn test_trove_adjusted_carries_the_trove_state() {
+    let trove = Trove {
+        authority: Pubkey::new_unique(),
+        lamports_amount: 2_000_000_000,
+        collaterals: [0, 5_000, 0, 0],
+        borrow_amount: 100,
+        amount_to_close: 106,
+        ..Trove::default()
+    };
+
+    let event = TroveAdjusted::new(&trove);
+    let data = event.data();
+    let logged = TroveAdjusted::try_from_slice(&data[8..]).unwrap();
+
+    assert_eq!(logged.authority, trove.authority);
+    assert_eq!(logged.lamports_amount, trove.lamports_amount);
+    assert_eq!(logged.collaterals, trove.collaterals);
+    assert_eq!(logged.borrow_amount, trove.borrow_amount);
+    assert_eq!(logged.amount_to_close, trove.amount_to_close);
+}
+
+#[test]
+fn test_config_changed_carries_the_changed_fields() {
+    let guardian = Pubkey::new_unique();
+    let event = ConfigChanged {
+        authority: Pubkey::new_unique(),
+        change: ConfigChange::Guardian { guardian },
+    };
+    let data = event.data();
+    let logged = ConfigChanged::try_from_slice(&data[8..]).unwrap();
+
+    assert_eq!(logged.authority, event.authority);
+    match logged.change {
+        ConfigChange::Guardian { guardian: logged_guardian } => assert_eq!(logged_guardian, guardian),
+        change => panic!("unexpected change {:?}", change),
+    }
+    // the discriminator, the authority, the variant and the guardian
+    assert_eq!(data.len(), 8 + 32 + 1 + 32);
+}
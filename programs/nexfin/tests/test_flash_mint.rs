@@ -0,0 +1,160 @@
+#![cfg(feature = "test-bpf")]
+mod helper;
+use anchor_lang::prelude::{AccountInfo, ProgramResult, Pubkey};
+use anchor_lang::solana_program::{instruction::Instruction, native_token::LAMPORTS_PER_SOL, program_pack::Pack};
+use anchor_lang::{InstructionData, ToAccountMetas};
+use helper::{assert_nexfin_error, coins, pda, Protocol, POOL_DEPOSITS};
+use nexfin_program::error::NexfinError;
+use nexfin_program::helpers::{get_borrowing_fees, get_fee_in_lamports, get_flash_mint_fee, get_total_fee};
+use nexfin_program::params::ProtocolParams;
+use nexfin_program::Fee;
+use solana_program_test::{processor, tokio};
+use solana_sdk::signature::Signer;
+
+/// Flash mint receiver which keeps the minted coins where they are, the borrower repays the
+/// fee out of the coins it already holds
+fn process_flash_mint_receiver(_program_id: &Pubkey, _accounts: &[AccountInfo], _data: &[u8]) -> ProgramResult {
+    Ok(())
+}
+
+async fn setup_receiver() -> (Protocol, Pubkey) {
+    let receiver_program = Pubkey::new_unique();
+    let protocol = helper::setup_with(|program_test| {
+        // the receiver has no BPF build, it always runs natively
+        program_test.prefer_bpf(false);
+        program_test.add_program("flash_mint_receiver", receiver_program, processor!(process_flash_mint_receiver));
+    })
+    .await;
+
+    (protocol, receiver_program)
+}
+
+fn flash_mint_instruction(protocol: &Protocol, receiver_program: &Pubkey, amount: u64) -> Instruction {
+    let (token_authority, mint_account_bump) = pda(&[b"mint-authority"]);
+
+    Instruction {
+        program_id: nexfin_program::id(),
+        accounts: nexfin_program::accounts::FlashMint {
+            authority: protocol.authority().pubkey(),
+            user_token: protocol.user_token,
+            token_authority,
+            stable_coin: protocol.stable_coin,
+            fee_account: pda(&[b"fee"]).0,
+            config: pda(&[b"config"]).0,
+            receiver_program: *receiver_program,
+            token_program: spl_token::ID,
+        }
+        .to_account_metas(None),
+        data: nexfin_program::instruction::FlashMint {
+            amount,
+            data: vec![],
+            mint_account_bump,
+        }
+        .data(),
+    }
+}
+
+#[tokio::test]
+async fn test_flash_mint_is_repaid() {
+    let (mut protocol, receiver_program) = setup_receiver().await;
+    let authority = protocol.authority();
+
+    // the borrowed coins pay the flash mint fee
+    protocol.open_trove(2_000, 40 * LAMPORTS_PER_SOL).await;
+
+    let flash_mint = flash_mint_instruction(&protocol, &receiver_program, 1_000);
+    protocol.process(&[flash_mint], &[&authority]).await.unwrap();
+
+    let fee = get_flash_mint_fee(1_000, &ProtocolParams::default()).unwrap();
+    assert_eq!(protocol.token_amount(protocol.user_token).await, coins(2_000 - fee));
+
+    let fee_account: Fee = protocol.get_account(pda(&[b"fee"]).0).await;
+    assert_eq!(fee_account.token_amount, fee);
+
+    let stable_coin = protocol.context.banks_client.get_account(protocol.stable_coin).await.unwrap().unwrap();
+    let supply = spl_token::state::Mint::unpack(&stable_coin.data).unwrap().supply;
+    assert_eq!(supply, coins(POOL_DEPOSITS + 2_000 - fee));
+}
+
+#[tokio::test]
+async fn test_flash_mint_not_repaid_fails() {
+    let (mut protocol, receiver_program) = setup_receiver().await;
+    let authority = protocol.authority();
+
+    // the minted coins are left but nothing pays the fee
+    let flash_mint = flash_mint_instruction(&protocol, &receiver_program, 1_000);
+    assert_nexfin_error(protocol.process(&[flash_mint], &[&authority]).await, NexfinError::NotEnoughLiquidityAfterFlashLoan);
+
+    assert_eq!(protocol.token_amount(protocol.user_token).await, 0);
+    let fee_account: Fee = protocol.get_account(pda(&[b"fee"]).0).await;
+    assert_eq!(fee_account.token_amount, 0);
+}
+
+#[tokio::test]
+async fn test_flash_mint_receiver_is_checked() {
+    let (mut protocol, _) = setup_receiver().await;
+    let authority = protocol.authority();
+
+    for receiver_program in [nexfin_program::id(), spl_token::ID].iter() {
+        let flash_mint = flash_mint_instruction(&protocol, receiver_program, 1_000);
+        assert_nexfin_error(protocol.process(&[flash_mint], &[&authority]).await, NexfinError::InvalidFlashLoanReceiverProgram);
+    }
+}
+
+#[test]
+fn test_flash_mint_fee() {
+    let params = ProtocolParams::default();
+    assert_eq!(get_flash_mint_fee(1_000_000, &params).unwrap(), 900);
+
+    // the fee is rounded up to a whole coin
+    assert_eq!(get_flash_mint_fee(1, &params).unwrap(), 1);
+    assert_eq!(get_flash_mint_fee(10_001, &params).unwrap(), 10);
+}
+
+#[test]
+fn test_flash_mint_fee_disabled() {
+    let params = ProtocolParams {
+        flash_mint_fee: 0,
+        ..ProtocolParams::default()
+    };
+    assert_eq!(get_flash_mint_fee(1_000_000, &params).unwrap(), 0);
+}
+
+#[test]
+fn test_flash_mint_fee_is_capped() {
+    let params = ProtocolParams {
+        flash_mint_fee: 101,
+        ..ProtocolParams::default()
+    };
+    assert!(params.validate().is_err());
+}
+
+#[test]
+fn test_fee_sums_overflow() {
+    let params = ProtocolParams {
+        deposit_fee: u64::MAX,
+        team_fee: 1,
+        ..ProtocolParams::default()
+    };
+    // the wrapped sum would pass the 10% cap
+    assert!(params.validate().is_err());
+    assert!(get_total_fee(1, &params).is_err());
+    assert!(get_borrowing_fees(1, 0, &params).is_err());
+
+    let params = ProtocolParams {
+        min_deposit_fee: u64::MAX,
+        min_team_fee: 1,
+        ..ProtocolParams::default()
+    };
+    assert!(params.validate().is_err());
+    assert!(get_total_fee(1, &params).is_err());
+}
+
+#[test]
+fn test_fee_in_lamports() {
+    // 1 stable coin at 100 USD per SOL
+    assert_eq!(get_fee_in_lamports(10_000, 10_000_000_000).unwrap(), 10_000_000);
+    assert!(get_fee_in_lamports(10_000, 0).is_err());
+    // a price under 1e-8 USD overflows the lamports instead of truncating them
+    assert!(get_fee_in_lamports(u64::MAX, 1).is_err());
+}
@@ -0,0 +1,205 @@
+#![cfg(feature = "test-bpf")]
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::{AccountSerialize, AnchorSerialize, Discriminator};
+use nexfin_program::migration::{load_trove_manager, load_versioned, ConfigV0, DepositV0, FeeV0, SortedTroveV0, TroveManagerV0, TroveV0, Versioned};
+use nexfin_program::params::{ProtocolParams, ACCOUNT_VERSION, MAX_COLLATERAL_TYPES};
+use nexfin_program::{Config, Deposit, Fee, SortedTrove, Trove, TroveManager};
+use std::mem::size_of;
+
+/// v0 account data, the v0 accounts were allocated with `size_of` plus the discriminator twice
+fn v0_fixture<T: AnchorSerialize>(discriminator: [u8; 8], account: &T) -> Vec<u8> {
+    let mut data = discriminator.to_vec();
+    account.serialize(&mut data).unwrap();
+    data.resize(size_of::<T>() + 16, 0);
+    data
+}
+
+#[test]
+fn test_trove_v0_is_upgraded() {
+    let authority = Pubkey::new_unique();
+    let data = v0_fixture(
+        Trove::discriminator(),
+        &TroveV0 {
+            bump: 254,
+            sol_bump: 1,
+            is_initialized: true,
+            borrow_amount: 100,
+            lamports_amount: 2_000_000_000,
+            is_received: true,
+            amount_to_close: 105,
+            authority,
+            ..TroveV0::default()
+        },
+    );
+
+    let (trove, is_outdated) = load_versioned::<Trove>(&data).unwrap();
+    assert!(is_outdated);
+    assert_eq!(trove.version, ACCOUNT_VERSION);
+    assert_eq!(trove.bump, 254);
+    // a sol bump of 1 is not mistaken for the version byte
+    assert_eq!(trove.sol_bump, 1);
+    assert!(trove.is_initialized);
+    assert_eq!(trove.borrow_amount, 100);
+    assert_eq!(trove.lamports_amount, 2_000_000_000);
+    assert_eq!(trove.amount_to_close, 105);
+    assert_eq!(trove.authority, authority);
+    assert_eq!(trove.interest_index, 0);
+    // the queued trove is liquidatable at once, as it was before the grace period
+    assert!(trove.is_received);
+    assert_eq!(trove.received_at, 0);
+    assert_eq!(trove.collaterals, [0; MAX_COLLATERAL_TYPES]);
+    assert_eq!(trove.collateral_surplus, 0);
+    assert_eq!(trove.reserved, [0; 7]);
+}
+
+#[test]
+fn test_current_trove_is_not_migrated() {
+    let trove = Trove {
+        version: ACCOUNT_VERSION,
+        bump: 1,
+        amount_to_close: 42,
+        ..Trove::default()
+    };
+    let mut data = Vec::new();
+    trove.try_serialize(&mut data).unwrap();
+    data.resize(Trove::SPACE, 0);
+
+    let (trove, is_outdated) = load_versioned::<Trove>(&data).unwrap();
+    assert!(!is_outdated);
+    assert_eq!(trove.bump, 1);
+    assert_eq!(trove.amount_to_close, 42);
+}
+
+#[test]
+fn test_deposit_v0_is_upgraded() {
+    let authority = Pubkey::new_unique();
+    let data = v0_fixture(
+        Deposit::discriminator(),
+        &DepositV0 {
+            bump: 253,
+            is_initialized: true,
+            token_amount: 1_000,
+            reward_coin_amount: 5,
+            authority,
+            ..DepositV0::default()
+        },
+    );
+
+    let (deposit, is_outdated) = load_versioned::<Deposit>(&data).unwrap();
+    assert!(is_outdated);
+    assert_eq!(deposit.version, ACCOUNT_VERSION);
+    assert_eq!(deposit.bump, 253);
+    assert_eq!(deposit.token_amount, 1_000);
+    assert_eq!(deposit.reward_coin_amount, 5);
+    assert_eq!(deposit.authority, authority);
+    // not in the pool until migrate_deposit
+    assert_eq!(deposit.snapshot.p, 0);
+    assert_eq!(deposit.reward_collateral_amounts, [0; MAX_COLLATERAL_TYPES]);
+}
+
+#[test]
+fn test_fee_v0_is_upgraded() {
+    let data = v0_fixture(
+        Fee::discriminator(),
+        &FeeV0 {
+            bump: 255,
+            is_initialized: true,
+            sol_amount: 10,
+        },
+    );
+
+    let (fee, is_outdated) = load_versioned::<Fee>(&data).unwrap();
+    assert!(is_outdated);
+    assert_eq!(fee.version, ACCOUNT_VERSION);
+    assert_eq!(fee.sol_amount, 10);
+    assert_eq!(fee.token_amount, 0);
+}
+
+#[test]
+fn test_legacy_config_is_upgraded() {
+    let mint_account_authority = Pubkey::new_unique();
+    let admin_account_authority = Pubkey::new_unique();
+    let data = v0_fixture(
+        Config::discriminator(),
+        &ConfigV0 {
+            bump: 1,
+            is_initialized: true,
+            mint_account_authority,
+            admin_account_authority,
+        },
+    );
+
+    let (config, is_outdated) = load_versioned::<Config>(&data).unwrap();
+    assert!(is_outdated);
+    assert_eq!(config.version, ACCOUNT_VERSION);
+    assert_eq!(config.bump, 1);
+    assert!(config.is_initialized);
+    assert_eq!(config.mint_account_authority, mint_account_authority);
+    assert_eq!(config.admin_account_authority, admin_account_authority);
+    assert_eq!(config.params.min_borrow_amount, ProtocolParams::default().min_borrow_amount);
+    assert_eq!(config.cumulative_interest_index, 0);
+    assert_eq!(config.paused, 0);
+    assert!(config.collateral_types.iter().all(|c| !c.is_registered()));
+}
+
+#[test]
+fn test_trove_manager_v0_is_read() {
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+    let mut data = TroveManager::discriminator().to_vec();
+    TroveManagerV0 {
+        bump: 251,
+        base_rate: 5,
+        last_fee_operation_time: 100,
+        troves: vec![
+            SortedTroveV0 { authority: alice, nicr: 1 },
+            SortedTroveV0 { authority: bob, nicr: 2 },
+        ],
+    }
+    .serialize(&mut data)
+    .unwrap();
+    // the v0 list held 200 troves of 48 bytes
+    data.resize(8 + 1 + 8 + 8 + 4 + 200 * 48, 0);
+
+    let (trove_manager, authorities) = load_trove_manager(&data).unwrap();
+    assert_eq!(trove_manager.bump, 251);
+    assert_eq!(trove_manager.base_rate, 5);
+    assert_eq!(trove_manager.last_fee_operation_time, 100);
+    assert!(trove_manager.troves.is_empty());
+    assert_eq!(trove_manager.total_collateral, 0);
+    assert_eq!(authorities, vec![alice, bob]);
+}
+
+#[test]
+fn test_trove_manager_totals_are_rebuilt() {
+    let alice = Pubkey::new_unique();
+    let mut data = Vec::new();
+    TroveManager {
+        bump: 251,
+        total_collateral: 1,
+        total_debt: 1,
+        troves: vec![SortedTrove {
+            authority: alice,
+            collateral: 1_000_000_000,
+            debt: 60,
+            ..SortedTrove::default()
+        }],
+        ..TroveManager::default()
+    }
+    .try_serialize(&mut data)
+    .unwrap();
+    data.resize(TroveManager::LEN + 8, 0);
+
+    let (trove_manager, authorities) = load_trove_manager(&data).unwrap();
+    assert_eq!(trove_manager.bump, 251);
+    assert_eq!(trove_manager.total_collateral, 0);
+    assert_eq!(trove_manager.total_debt, 0);
+    assert!(trove_manager.troves.is_empty());
+    assert_eq!(authorities, vec![alice]);
+}
+
+#[test]
+fn test_other_account_is_rejected() {
+    let data = v0_fixture(Deposit::discriminator(), &TroveV0::default());
+    assert!(load_versioned::<Trove>(&data).is_err());
+}
@@ -0,0 +1,15 @@
+#![cfg(feature = "test-bpf")]
+use anchor_lang::prelude::ProgramError;
+use nexfin_program::error::NexfinError;
+use nexfin_program::oracle::check_sol_price_expo;
+use nexfin_program::params::SOL_PRICE_EXPO;
+
+#[test]
+fn test_sol_price_expo_is_checked() {
+    assert!(check_sol_price_expo(SOL_PRICE_EXPO).is_ok());
+
+    // a feed with more or fewer decimals would misprice the collateral by powers of ten
+    for expo in [-9, -6, 0, 8] {
+        assert_eq!(check_sol_price_expo(expo), Err(ProgramError::from(NexfinError::InvalidOracleConfig)));
+    }
+}
@@ -0,0 +1,115 @@
+#![cfg(feature = "test-bpf")]
+mod helper;
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::solana_program::{instruction::Instruction, native_token::LAMPORTS_PER_SOL};
+use anchor_lang::{InstructionData, ToAccountMetas};
+use helper::{assert_nexfin_error, pda, trove_address};
+use nexfin_program::error::NexfinError;
+use nexfin_program::params::{PAUSE_ALL, PAUSE_BORROWING, PAUSE_DEPOSITS, PAUSE_LIQUIDATIONS, PAUSE_REDEMPTIONS, PAUSE_WITHDRAWALS};
+use nexfin_program::{Config, Trove};
+use solana_program_test::tokio;
+use solana_sdk::signature::Signer;
+
+fn pause_instruction(authority: &Pubkey, flags: u8) -> Instruction {
+    Instruction {
+        program_id: nexfin_program::id(),
+        accounts: nexfin_program::accounts::Pause {
+            authority: *authority,
+            config: pda(&[b"config"]).0,
+        }
+        .to_account_metas(None),
+        data: nexfin_program::instruction::Pause { flags }.data(),
+    }
+}
+
+fn set_pause_instruction(authority: &Pubkey, paused: u8) -> Instruction {
+    Instruction {
+        program_id: nexfin_program::id(),
+        accounts: nexfin_program::accounts::ChangeConfig {
+            authority: *authority,
+            admin_account_authority: pda(&[b"admin", authority.as_ref()]).0,
+            config_account: pda(&[b"config"]).0,
+        }
+        .to_account_metas(None),
+        data: nexfin_program::instruction::SetPause { paused }.data(),
+    }
+}
+
+#[test]
+fn test_paused_flags_are_checked_separately() {
+    let config = Config {
+        is_initialized: true,
+        paused: PAUSE_BORROWING | PAUSE_LIQUIDATIONS,
+        ..Config::default()
+    };
+
+    assert!(config.check_not_paused(PAUSE_BORROWING).is_err());
+    assert!(config.check_not_paused(PAUSE_LIQUIDATIONS).is_err());
+    assert!(config.check_not_paused(PAUSE_DEPOSITS).is_ok());
+    assert!(config.check_not_paused(PAUSE_WITHDRAWALS).is_ok());
+    assert!(config.check_not_paused(PAUSE_REDEMPTIONS).is_ok());
+}
+
+#[test]
+fn test_redemptions_are_paused_on_their_own() {
+    let config = Config {
+        is_initialized: true,
+        paused: PAUSE_REDEMPTIONS,
+        ..Config::default()
+    };
+
+    assert!(config.check_not_paused(PAUSE_REDEMPTIONS).is_err());
+    assert!(config.check_not_paused(PAUSE_WITHDRAWALS).is_ok());
+    assert_eq!(PAUSE_ALL & PAUSE_REDEMPTIONS, PAUSE_REDEMPTIONS);
+}
+
+#[test]
+fn test_uninitialized_config_is_paused() {
+    let config = Config::default();
+
+    assert!(config.check_not_paused(PAUSE_DEPOSITS).is_err());
+}
+
+#[tokio::test]
+async fn test_paused_borrowing_is_gated() {
+    let mut protocol = helper::setup().await;
+    let admin = protocol.admin();
+    let authority = protocol.authority();
+    let guardian = protocol.guardian();
+
+    let pause = pause_instruction(&guardian.pubkey(), PAUSE_BORROWING);
+    protocol.process(&[pause], &[&guardian]).await.unwrap();
+    assert_eq!(protocol.config().await.paused, PAUSE_BORROWING);
+
+    let borrow = protocol.borrow_instruction(2_000, 40 * LAMPORTS_PER_SOL);
+    assert_nexfin_error(protocol.process(&[borrow], &[&authority]).await, NexfinError::ProtocolPaused);
+
+    let set_pause = set_pause_instruction(&admin.pubkey(), 0);
+    protocol.process(&[set_pause], &[&admin]).await.unwrap();
+    assert_eq!(protocol.config().await.paused, 0);
+
+    protocol.open_trove(2_000, 40 * LAMPORTS_PER_SOL).await;
+    let trove: Trove = protocol.get_account(trove_address(&authority.pubkey()).0).await;
+    assert_eq!(trove.is_initialized, true);
+}
+
+#[tokio::test]
+async fn test_only_the_admin_unpauses() {
+    let mut protocol = helper::setup().await;
+    let authority = protocol.authority();
+    let guardian = protocol.guardian();
+
+    let pause = pause_instruction(&authority.pubkey(), PAUSE_ALL);
+    assert_nexfin_error(protocol.process(&[pause], &[&authority]).await, NexfinError::OnlyForGuardian);
+
+    let pause = pause_instruction(&guardian.pubkey(), 0);
+    assert_nexfin_error(protocol.process(&[pause], &[&guardian]).await, NexfinError::InvalidConfig);
+
+    let pause = pause_instruction(&guardian.pubkey(), PAUSE_ALL);
+    protocol.process(&[pause], &[&guardian]).await.unwrap();
+
+    // the guardian has no admin account
+    let set_pause = set_pause_instruction(&guardian.pubkey(), 0);
+    assert!(protocol.process(&[set_pause], &[&guardian]).await.is_err());
+    assert_eq!(protocol.config().await.paused, PAUSE_ALL);
+}
@@ -0,0 +1,183 @@
+#![cfg(feature = "test-bpf")]
+use nexfin_program::params::{DECIMAL_PRECISION, GOVERNANCE_ISSUANCE_FACTOR, MAX_POOL_SCALES};
+use nexfin_program::{Deposit, StabilityPool};
+
+fn setup_pool() -> StabilityPool {
+    StabilityPool {
+        p: DECIMAL_PRECISION,
+        ..StabilityPool::default()
+    }
+}
+
+fn add_deposit(pool: &mut StabilityPool, deposit: &mut Deposit, amount: u64) {
+    pool.settle(deposit).unwrap();
+    pool.add_to_deposit(deposit, amount).unwrap();
+}
+
+#[test]
+fn test_liquidation_gain_is_shared_pro_rata() {
+    let mut pool = setup_pool();
+    let mut alice = Deposit::default();
+    let mut bob = Deposit::default();
+    add_deposit(&mut pool, &mut alice, 1_000);
+    add_deposit(&mut pool, &mut bob, 3_000);
+
+    pool.offset(400, 8_000_000).unwrap();
+    assert_eq!(pool.total_deposits, 3_600);
+
+    pool.settle(&mut alice).unwrap();
+    pool.settle(&mut bob).unwrap();
+    assert_eq!(alice.token_amount, 899);
+    assert_eq!(bob.token_amount, 2_699);
+    assert_eq!(alice.reward_coin_amount, 2_000_000);
+    assert_eq!(bob.reward_coin_amount, 6_000_000);
+
+    // settling again does not pay twice
+    pool.settle(&mut alice).unwrap();
+    assert_eq!(alice.token_amount, 899);
+    assert_eq!(alice.reward_coin_amount, 2_000_000);
+}
+
+#[test]
+fn test_late_deposit_misses_earlier_gains() {
+    let mut pool = setup_pool();
+    let mut alice = Deposit::default();
+    let mut bob = Deposit::default();
+    add_deposit(&mut pool, &mut alice, 1_000);
+
+    pool.offset(500, 1_000_000).unwrap();
+    add_deposit(&mut pool, &mut bob, 500);
+    pool.distribute(0, 10_000, 0).unwrap();
+
+    pool.settle(&mut alice).unwrap();
+    pool.settle(&mut bob).unwrap();
+    assert_eq!(alice.reward_coin_amount, 1_000_000);
+    assert_eq!(bob.reward_coin_amount, 0);
+    assert_eq!(alice.reward_token_amount, 4_999);
+    assert_eq!(bob.reward_token_amount, 5_000);
+}
+
+#[test]
+fn test_emptied_pool_starts_a_new_epoch() {
+    let mut pool = setup_pool();
+    let mut alice = Deposit::default();
+    add_deposit(&mut pool, &mut alice, 1_000);
+
+    pool.offset(1_000, 5_000_000).unwrap();
+    assert_eq!(pool.current_epoch, 1);
+    assert_eq!(pool.total_deposits, 0);
+
+    pool.settle(&mut alice).unwrap();
+    assert_eq!(alice.token_amount, 0);
+    assert_eq!(alice.reward_coin_amount, 5_000_000);
+}
+
+#[test]
+fn test_offset_more_than_the_pool_fails() {
+    let mut pool = setup_pool();
+    let mut alice = Deposit::default();
+    add_deposit(&mut pool, &mut alice, 1_000);
+
+    assert!(pool.offset(1_001, 0).is_err());
+}
+
+#[test]
+fn test_governance_emission_halves_every_year() {
+    let mut pool = setup_pool();
+    pool.governance_supply_cap = 1_000_000;
+    pool.governance_issuance_factor = GOVERNANCE_ISSUANCE_FACTOR;
+    pool.emission_start_time = 1_000;
+
+    let mut alice = Deposit::default();
+    add_deposit(&mut pool, &mut alice, 1_000);
+
+    let one_year = 365 * 24 * 60 * 60;
+    pool.issue_governance(1_000 + one_year).unwrap();
+    pool.settle(&mut alice).unwrap();
+    assert!((alice.reward_governance_token_amount as i64 - 500_000).abs() < 10);
+
+    // nothing more is issued within the same minute
+    pool.issue_governance(1_000 + one_year + 30).unwrap();
+    pool.settle(&mut alice).unwrap();
+    assert!((alice.reward_governance_token_amount as i64 - 500_000).abs() < 10);
+    assert_eq!(pool.total_governance_issued, alice.reward_governance_token_amount);
+}
+
+#[test]
+fn test_legacy_deposit_joins_the_pool() {
+    let mut pool = setup_pool();
+    let mut alice = Deposit::default();
+    add_deposit(&mut pool, &mut alice, 1_000);
+    pool.offset(500, 1_000).unwrap();
+
+    // a deposit from before the pool has no snapshot yet
+    let mut bob = Deposit {
+        token_amount: 2_000,
+        ..Deposit::default()
+    };
+    assert_eq!(pool.add_legacy_deposit(&mut bob).unwrap(), 2_000);
+    assert_eq!(pool.total_deposits, 2_500);
+    assert_eq!(bob.snapshot.p, pool.p);
+
+    // it misses the earlier liquidation and is not added twice
+    pool.settle(&mut bob).unwrap();
+    assert_eq!(bob.token_amount, 2_000);
+    assert_eq!(bob.reward_coin_amount, 0);
+    assert_eq!(pool.add_legacy_deposit(&mut bob).unwrap(), 0);
+    assert_eq!(pool.total_deposits, 2_500);
+}
+
+#[test]
+fn test_pool_scales_read_by_a_snapshot_are_kept() {
+    let mut pool = setup_pool();
+    let mut alice = Deposit::default();
+    add_deposit(&mut pool, &mut alice, 1_000);
+
+    for scale in 0..MAX_POOL_SCALES as u64 {
+        pool.current_scale = scale;
+        pool.distribute(10, 0, 0).unwrap();
+    }
+
+    // the scales no snapshot reads make room for the new one
+    pool.current_scale = MAX_POOL_SCALES as u64;
+    pool.distribute(10, 0, 0).unwrap();
+    assert!(pool.sums.len() <= MAX_POOL_SCALES);
+    assert!(pool.get_sum(0, 0).coin_sum > 0);
+    assert_eq!(pool.get_sum(0, 0).coin_sum, pool.get_sum(0, 1).coin_sum);
+}
+
+#[test]
+fn test_more_pool_wipes_than_pool_scales() {
+    let mut pool = setup_pool();
+    let mut alice = Deposit::default();
+    let wipes = 2 * MAX_POOL_SCALES as u64;
+
+    for _ in 0..wipes {
+        add_deposit(&mut pool, &mut alice, 1_000);
+        pool.offset(1_000, 1_000_000).unwrap();
+    }
+    assert_eq!(pool.current_epoch, wipes);
+    assert!(pool.sums.len() <= MAX_POOL_SCALES);
+
+    pool.settle(&mut alice).unwrap();
+    assert_eq!(alice.token_amount, 0);
+    assert_eq!(alice.reward_coin_amount, wipes * 1_000_000);
+}
+
+#[test]
+fn test_pending_gain_of_an_old_epoch_is_kept() {
+    let mut pool = setup_pool();
+    let mut alice = Deposit::default();
+    let mut bob = Deposit::default();
+    add_deposit(&mut pool, &mut alice, 1_000);
+    pool.offset(1_000, 5_000_000).unwrap();
+
+    // alice never comes back while bob goes through more wipes than the pool keeps scales
+    for _ in 0..MAX_POOL_SCALES {
+        add_deposit(&mut pool, &mut bob, 1_000);
+        pool.offset(1_000, 1_000_000).unwrap();
+    }
+
+    pool.settle(&mut alice).unwrap();
+    assert_eq!(alice.reward_coin_amount, 5_000_000);
+}
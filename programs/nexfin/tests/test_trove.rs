@@ -1,180 +1,414 @@
 #![cfg(feature = "test-bpf")]
 #![allow(unused)]
 mod helper;
-use anchor_lang::solana_program::{instruction::Instruction, pubkey::Pubkey, sysvar};
+use anchor_lang::solana_program::{instruction::Instruction, native_token::LAMPORTS_PER_SOL, pubkey::Pubkey, system_program, sysvar};
 use anchor_lang::AccountDeserialize;
 use anchor_lang::{InstructionData, ToAccountMetas};
-use anchor_spl::token;
-use helper::process_and_assert_ok;
-use solana_program_test::{processor, tokio, ProgramTest};
+use helper::{assert_nexfin_error, coins, pda, sol_trove_address, trove_address, Protocol, POOL_DEPOSITS, SOL_PRICE};
+use solana_program_test::tokio;
 use solana_sdk::signature::{Keypair, Signer};
-use solana_sdk::system_instruction;
 
-use nexfin_program::helpers::{get_depositors_fee, get_team_fee, get_trove_debt_amount};
-use nexfin_program::{params, state::Trove};
+use nexfin_program::error::NexfinError;
+use nexfin_program::helpers::get_borrowing_fees;
+use nexfin_program::{params, StabilityPool, Trove, TroveManager};
 
-#[tokio::test]
-async fn test_borrow() {
-    let init = helper::setup().await;
-
-    let program_id = init.program_id;
-    let authority = init.authority();
-    let trove = init.trove();
-    let payer = init.payer();
-    let mut banks_client = init.banks_client;
-
-    let borrow_amount = 100;
-    let lamports = 100;
-    let inx = Instruction {
-        program_id,
-        accounts: nexfin_program::accounts::Borrow {
-            authority: authority.pubkey(),
-            trove: trove.pubkey(),
-            rent: sysvar::rent::ID,
+const BORROW_AMOUNT: u64 = 2_000;
+/// 200% collateral ratio at the harness price
+const LAMPORTS: u64 = 40 * LAMPORTS_PER_SOL;
+
+fn close_trove_instruction(protocol: &Protocol) -> Instruction {
+    let authority = protocol.authority().pubkey();
+    let (sol_trove, sol_account_bump) = sol_trove_address(&authority);
+
+    Instruction {
+        program_id: nexfin_program::id(),
+        accounts: nexfin_program::accounts::CloseTrove {
+            authority,
+            trove: trove_address(&authority).0,
+            trove_manager: pda(&[b"trovemanager"]).0,
+            fee_account: pda(&[b"fee"]).0,
+            team_fee_account: pda(&[b"teamfee"]).0,
+            config: pda(&[b"config"]).0,
+            clock: sysvar::clock::ID,
+            sol_trove,
+            token_program: spl_token::ID,
+            user_token: protocol.user_token,
+            token_mint: protocol.stable_coin,
         }
         .to_account_metas(None),
-        data: nexfin_program::instruction::Borrow {
-            borrow_amount,
-            lamports,
+        data: nexfin_program::instruction::CloseTrove {
+            _sol_account_bump: sol_account_bump,
         }
         .data(),
-    };
+    }
+}
 
-    process_and_assert_ok(&[inx], &payer, &[&payer, &authority], &mut banks_client).await;
-    let trove_account = banks_client
-        .get_account(trove.pubkey())
-        .await
-        .unwrap()
-        .unwrap();
+fn reconcile_trove_instruction(protocol: &Protocol) -> Instruction {
+    let authority = protocol.authority().pubkey();
 
-    let trove_state = Trove::try_deserialize(&mut trove_account.data.as_ref()).unwrap();
-    assert_eq!(trove_state.is_initialized, true);
-    assert_eq!(trove_state.is_liquidated, false);
-    assert_eq!(trove_state.is_received, false);
-    assert_eq!(trove_state.borrow_amount, borrow_amount);
-    assert_eq!(trove_state.lamports_amount, lamports);
-    assert_eq!(trove_state.depositor_fee, get_depositors_fee(borrow_amount));
-    assert_eq!(trove_state.team_fee, get_team_fee(borrow_amount));
-    assert_eq!(
-        trove_state.amount_to_close,
-        get_trove_debt_amount(borrow_amount)
-    );
-    assert_eq!(trove_state.owner, authority.pubkey());
+    Instruction {
+        program_id: nexfin_program::id(),
+        accounts: nexfin_program::accounts::ReconcileTrove {
+            trove: trove_address(&authority).0,
+            sol_trove: sol_trove_address(&authority).0,
+        }
+        .to_account_metas(None),
+        data: nexfin_program::instruction::ReconcileTrove {}.data(),
+    }
 }
 
-#[tokio::test]
-async fn test_close_trove() {
-    let init = helper::setup().await;
-
-    let program_id = init.program_id;
-    let authority = init.authority();
-    let trove = init.trove();
-    let payer = init.payer();
-    let token_mint = init.token_mint();
-    let mut banks_client = init.banks_client;
-
-    let borrow_amount = 100;
-    let lamports = 100;
-    let init_trove = Instruction {
-        program_id,
-        accounts: nexfin_program::accounts::Borrow {
-            authority: authority.pubkey(),
-            trove: trove.pubkey(),
-            rent: sysvar::rent::ID,
+fn adjust_trove_instruction(protocol: &Protocol, collateral_delta: i64, debt_delta: i64) -> Instruction {
+    let authority = protocol.authority().pubkey();
+    let (token_authority, mint_account_bump) = pda(&[b"mint-authority"]);
+
+    Instruction {
+        program_id: nexfin_program::id(),
+        accounts: nexfin_program::accounts::AdjustTrove {
+            authority,
+            trove: trove_address(&authority).0,
+            trove_manager: pda(&[b"trovemanager"]).0,
+            fee_account: pda(&[b"fee"]).0,
+            team_fee_account: pda(&[b"teamfee"]).0,
+            sol_trove: sol_trove_address(&authority).0,
+            token_authority,
+            stable_coin: protocol.stable_coin,
+            user_token: protocol.user_token,
+            pyth_sol_account: protocol.pyth_sol_account,
+            secondary_price: pda(&[b"price"]).0,
+            config: pda(&[b"config"]).0,
+            clock: sysvar::clock::ID,
+            system_program: system_program::ID,
+            token_program: spl_token::ID,
         }
         .to_account_metas(None),
-        data: nexfin_program::instruction::Borrow {
-            borrow_amount,
-            lamports,
+        data: nexfin_program::instruction::AdjustTrove {
+            collateral_delta,
+            debt_delta,
+            mint_account_bump,
         }
         .data(),
-    };
-    process_and_assert_ok(
-        &[init_trove],
-        &payer,
-        &[&payer, &authority],
-        &mut banks_client,
-    )
-    .await;
-
-    let user_ata = spl_associated_token_account::get_associated_token_address(
-        &authority.pubkey(),
-        &token_mint.pubkey(),
-    );
-    let inx = Instruction {
-        program_id,
-        accounts: nexfin_program::accounts::CloseTrove {
-            authority: authority.pubkey(),
-            trove: trove.pubkey(),
+    }
+}
+
+fn receive_trove_instruction(protocol: &Protocol, keeper: &Pubkey) -> Instruction {
+    let authority = protocol.authority().pubkey();
+
+    Instruction {
+        program_id: nexfin_program::id(),
+        accounts: nexfin_program::accounts::ReceiveTrove {
+            authority: *keeper,
+            config: pda(&[b"config"]).0,
+            trove: trove_address(&authority).0,
+            trove_manager: pda(&[b"trovemanager"]).0,
+            fee_account: pda(&[b"fee"]).0,
+            team_fee_account: pda(&[b"teamfee"]).0,
+            pyth_sol_account: protocol.pyth_sol_account,
+            secondary_price: pda(&[b"price"]).0,
+            clock: sysvar::clock::ID,
+        }
+        .to_account_metas(None),
+        data: nexfin_program::instruction::ReceiveTrove {
+            _trove_account: authority,
+        }
+        .data(),
+    }
+}
+
+fn liquidate_trove_instruction(protocol: &Protocol, liquidator: &Pubkey) -> Instruction {
+    let authority = protocol.authority().pubkey();
+    let (trove, trove_bump) = trove_address(&authority);
+    let (reward_coin_vault, reward_vault_bump) = pda(&[b"rewardVault"]);
+
+    Instruction {
+        program_id: nexfin_program::id(),
+        accounts: nexfin_program::accounts::LiquidateTrove {
+            liquidator: *liquidator,
+            trove,
+            trove_manager: pda(&[b"trovemanager"]).0,
+            fee_account: pda(&[b"fee"]).0,
+            team_fee_account: pda(&[b"teamfee"]).0,
+            sol_trove: sol_trove_address(&authority).0,
+            reward_coin_vault,
+            stability_pool: pda(&[b"stabilitypool"]).0,
+            deposit_vault: pda(&[b"depositvault"]).0,
+            stable_coin: protocol.stable_coin,
+            pyth_sol_account: protocol.pyth_sol_account,
+            secondary_price: pda(&[b"price"]).0,
+            config: pda(&[b"config"]).0,
+            clock: sysvar::clock::ID,
+            system_program: system_program::ID,
             token_program: spl_token::ID,
-            user_token: user_ata,
-            token_mint: token_mint.pubkey(),
         }
         .to_account_metas(None),
-        data: nexfin_program::instruction::CloseTrove {}.data(),
-    };
+        data: nexfin_program::instruction::LiquidateTrove {
+            _trove_bump: trove_bump,
+            _reward_vault_bump: reward_vault_bump,
+        }
+        .data(),
+    }
+}
+
+#[tokio::test]
+async fn test_borrow() {
+    let mut protocol = helper::setup().await;
+    let authority = protocol.authority().pubkey();
+
+    protocol.open_trove(BORROW_AMOUNT, LAMPORTS).await;
+
+    let trove_state: Trove = protocol.get_account(trove_address(&authority).0).await;
+    assert_eq!(trove_state.is_initialized, true);
+    assert_eq!(trove_state.is_liquidated, false);
+    assert_eq!(trove_state.is_received, false);
+    assert_eq!(trove_state.borrow_amount, BORROW_AMOUNT);
+    assert_eq!(trove_state.lamports_amount, LAMPORTS);
+    let protocol_params = params::ProtocolParams::default();
+    assert_eq!(
+        (trove_state.depositor_fee, trove_state.team_fee),
+        get_borrowing_fees(BORROW_AMOUNT, 0, &protocol_params).unwrap()
+    );
+    assert_eq!(trove_state.amount_to_close, BORROW_AMOUNT);
+    assert_eq!(trove_state.authority, authority);
 
-    process_and_assert_ok(&[inx], &payer, &[&payer, &authority], &mut banks_client).await;
+    assert!(protocol.lamports(sol_trove_address(&authority).0).await >= LAMPORTS);
+    assert_eq!(protocol.token_amount(protocol.user_token).await, coins(BORROW_AMOUNT));
 
-    let trove_account = banks_client.get_account(trove.pubkey()).await.unwrap();
+    let trove_manager: TroveManager = protocol.get_account(pda(&[b"trovemanager"]).0).await;
+    assert_eq!(trove_manager.total_debt, BORROW_AMOUNT);
+    assert_eq!(trove_manager.get(&authority).unwrap().collateral, LAMPORTS);
+}
+
+#[tokio::test]
+async fn test_borrow_under_the_collateral_ratio_fails() {
+    let mut protocol = helper::setup().await;
+    let authority = protocol.authority();
 
+    // 100% collateral ratio
+    let borrow = protocol.borrow_instruction(BORROW_AMOUNT, LAMPORTS / 2);
+    assert_nexfin_error(protocol.process(&[borrow], &[&authority]).await, NexfinError::BorrowTooLarge);
+}
+
+#[tokio::test]
+async fn test_close_trove() {
+    let mut protocol = helper::setup().await;
+    let authority = protocol.authority();
+
+    protocol.open_trove(BORROW_AMOUNT, LAMPORTS).await;
+
+    let close_trove = close_trove_instruction(&protocol);
+    protocol.process(&[close_trove], &[&authority]).await.unwrap();
+
+    let trove_account = protocol.context.banks_client.get_account(trove_address(&authority.pubkey()).0).await.unwrap();
     assert_eq!(trove_account, None);
+    assert_eq!(protocol.token_amount(protocol.user_token).await, 0);
+
+    let trove_manager: TroveManager = protocol.get_account(pda(&[b"trovemanager"]).0).await;
+    assert_eq!(trove_manager.troves.len(), 0);
+}
+
+#[tokio::test]
+async fn test_adjust_trove() {
+    let mut protocol = helper::setup().await;
+    let authority = protocol.authority();
+
+    protocol.open_trove(BORROW_AMOUNT, LAMPORTS).await;
+
+    let adjust_trove = adjust_trove_instruction(&protocol, 10 * LAMPORTS_PER_SOL as i64, 500);
+    protocol.process(&[adjust_trove], &[&authority]).await.unwrap();
+
+    let trove_state: Trove = protocol.get_account(trove_address(&authority.pubkey()).0).await;
+    assert_eq!(trove_state.lamports_amount, LAMPORTS + 10 * LAMPORTS_PER_SOL);
+    assert_eq!(trove_state.amount_to_close, BORROW_AMOUNT + 500);
+    assert_eq!(protocol.token_amount(protocol.user_token).await, coins(BORROW_AMOUNT + 500));
+
+    // withdraw collateral and repay together
+    let adjust_trove = adjust_trove_instruction(&protocol, -(10 * LAMPORTS_PER_SOL as i64), -500);
+    protocol.process(&[adjust_trove], &[&authority]).await.unwrap();
+
+    let trove_state: Trove = protocol.get_account(trove_address(&authority.pubkey()).0).await;
+    assert_eq!(trove_state.lamports_amount, LAMPORTS);
+    assert_eq!(trove_state.amount_to_close, BORROW_AMOUNT);
+    assert_eq!(protocol.token_amount(protocol.user_token).await, coins(BORROW_AMOUNT));
+}
+
+#[tokio::test]
+async fn test_adjust_trove_rejects_unhealthy_troves() {
+    let mut protocol = helper::setup().await;
+    let authority = protocol.authority();
+
+    protocol.open_trove(BORROW_AMOUNT, LAMPORTS).await;
+
+    // 75% collateral ratio after the withdrawal
+    let adjust_trove = adjust_trove_instruction(&protocol, -(25 * LAMPORTS_PER_SOL as i64), 0);
+    assert_nexfin_error(protocol.process(&[adjust_trove], &[&authority]).await, NexfinError::InvalidCollateral);
+
+    let adjust_trove = adjust_trove_instruction(&protocol, 0, -1_000);
+    assert_nexfin_error(protocol.process(&[adjust_trove], &[&authority]).await, NexfinError::DebtBelowMinimum);
+
+    let adjust_trove = adjust_trove_instruction(&protocol, 0, 0);
+    assert_nexfin_error(protocol.process(&[adjust_trove], &[&authority]).await, NexfinError::InvalidAmount);
+
+    let trove_state: Trove = protocol.get_account(trove_address(&authority.pubkey()).0).await;
+    assert_eq!(trove_state.lamports_amount, LAMPORTS);
+    assert_eq!(trove_state.amount_to_close, BORROW_AMOUNT);
+}
+
+#[tokio::test]
+async fn test_receive_trove() {
+    let mut protocol = helper::setup().await;
+    let admin = protocol.admin();
+    let authority = protocol.authority();
+
+    protocol.open_trove(BORROW_AMOUNT, LAMPORTS).await;
+
+    let receive_trove = receive_trove_instruction(&protocol, &admin.pubkey());
+    assert_nexfin_error(protocol.process(&[receive_trove], &[&admin]).await, NexfinError::ObligationHealthy);
+
+    // 100% collateral ratio
+    protocol.set_sol_price(SOL_PRICE / 2).await;
+
+    // only the liquidation keeper or the admin queue the troves
+    let receive_trove = receive_trove_instruction(&protocol, &authority.pubkey());
+    assert_nexfin_error(protocol.process(&[receive_trove], &[&authority]).await, NexfinError::OnlyForAdmin);
+
+    let receive_trove = receive_trove_instruction(&protocol, &admin.pubkey());
+    protocol.process(&[receive_trove], &[&admin]).await.unwrap();
+
+    let trove_state: Trove = protocol.get_account(trove_address(&authority.pubkey()).0).await;
+    assert_eq!(trove_state.is_received, true);
+    assert_eq!(trove_state.received_price, (SOL_PRICE / 2) as u64);
+
+    // back above the liquidation ratio the trove leaves the queue
+    protocol.set_sol_price(SOL_PRICE).await;
+    let receive_trove = receive_trove_instruction(&protocol, &admin.pubkey());
+    protocol.process(&[receive_trove], &[&admin]).await.unwrap();
+
+    let trove_state: Trove = protocol.get_account(trove_address(&authority.pubkey()).0).await;
+    assert_eq!(trove_state.is_received, false);
+    assert_eq!(trove_state.received_at, 0);
 }
 
 #[tokio::test]
 async fn test_liquidate_trove() {
-    let init = helper::setup().await;
-
-    let program_id = init.program_id;
-    let authority = init.authority();
-    let trove = init.trove();
-    let payer = init.payer();
-    let token_mint = init.token_mint();
-    let mut banks_client = init.banks_client;
-
-    let borrow_amount = 100;
-    let lamports = 100;
-    let init_trove = Instruction {
-        program_id,
-        accounts: nexfin_program::accounts::Borrow {
-            authority: authority.pubkey(),
-            trove: trove.pubkey(),
-            rent: sysvar::rent::ID,
-        }
-        .to_account_metas(None),
-        data: nexfin_program::instruction::Borrow {
-            borrow_amount,
-            lamports,
-        }
-        .data(),
-    };
-    process_and_assert_ok(
-        &[init_trove],
-        &payer,
-        &[&payer, &authority],
-        &mut banks_client,
-    )
-    .await;
-
-    let user_ata = spl_associated_token_account::get_associated_token_address(
-        &authority.pubkey(),
-        &token_mint.pubkey(),
-    );
-    let inx = Instruction {
-        program_id,
-        accounts: nexfin_program::accounts::LiquidateTrove {
-            authority: authority.pubkey(),
-            trove: trove.pubkey(),
-            trove_owner: params::SYSTEM_ACCOUNT_ADDRESS,
-        }
-        .to_account_metas(None),
-        data: nexfin_program::instruction::LiquidateTrove {}.data(),
-    };
+    let mut protocol = helper::setup().await;
+    let admin = protocol.admin();
+    let authority = protocol.authority().pubkey();
+    let liquidator = protocol.context.payer.pubkey();
 
-    return;
-    process_and_assert_ok(&[inx], &payer, &[&payer, &authority], &mut banks_client).await;
+    protocol.open_trove(BORROW_AMOUNT, LAMPORTS).await;
+    protocol.set_sol_price(SOL_PRICE / 2).await;
 
-    let trove_account = banks_client.get_account(trove.pubkey()).await.unwrap();
+    let liquidate_trove = liquidate_trove_instruction(&protocol, &liquidator);
+    assert_nexfin_error(protocol.process(&[liquidate_trove], &[]).await, NexfinError::TroveIsNotReceived);
 
-    assert_eq!(trove_account, None);
+    let receive_trove = receive_trove_instruction(&protocol, &admin.pubkey());
+    protocol.process(&[receive_trove], &[&admin]).await.unwrap();
+
+    let liquidate_trove = liquidate_trove_instruction(&protocol, &liquidator);
+    assert_nexfin_error(protocol.process(&[liquidate_trove], &[]).await, NexfinError::LiquidationGracePeriod);
+
+    protocol.update_config(|config| config.params.liquidation_grace_period = 0).await;
+    let sol_trove_lamports = protocol.lamports(sol_trove_address(&authority).0).await;
+    let reward_vault_lamports = protocol.lamports(pda(&[b"rewardVault"]).0).await;
+
+    let liquidate_trove = liquidate_trove_instruction(&protocol, &liquidator);
+    protocol.process(&[liquidate_trove], &[]).await.unwrap();
+
+    let trove_state: Trove = protocol.get_account(trove_address(&authority).0).await;
+    assert_eq!(trove_state.is_liquidated, true);
+    assert_eq!(trove_state.amount_to_close, 0);
+    assert_eq!(trove_state.lamports_amount, 0);
+
+    // the whole collateral is seized, 0.5% of it goes to the liquidator
+    let liquidator_reward = LAMPORTS * params::LIQUIDATOR_REWARD / params::LIQUIDATOR_REWARD_DENOMINATOR;
+    assert_eq!(protocol.lamports(sol_trove_address(&authority).0).await, sol_trove_lamports - LAMPORTS);
+    assert_eq!(protocol.lamports(pda(&[b"rewardVault"]).0).await, reward_vault_lamports + LAMPORTS - liquidator_reward);
+
+    let stability_pool: StabilityPool = protocol.get_account(pda(&[b"stabilitypool"]).0).await;
+    assert_eq!(stability_pool.total_deposits, POOL_DEPOSITS - BORROW_AMOUNT);
+    assert_eq!(protocol.token_amount(pda(&[b"depositvault"]).0).await, coins(POOL_DEPOSITS - BORROW_AMOUNT));
+
+    let trove_manager: TroveManager = protocol.get_account(pda(&[b"trovemanager"]).0).await;
+    assert_eq!(trove_manager.troves.len(), 0);
+
+    let liquidate_trove = liquidate_trove_instruction(&protocol, &liquidator);
+    assert_nexfin_error(protocol.process(&[liquidate_trove], &[]).await, NexfinError::TroveAlreadyLiquidated);
+}
+
+#[tokio::test]
+async fn test_liquidate_trove_larger_than_the_pool() {
+    let mut protocol = helper::setup().await;
+    let admin = protocol.admin();
+    let authority = protocol.authority().pubkey();
+    let liquidator = protocol.context.payer.pubkey();
+
+    let debt = POOL_DEPOSITS + 5_000;
+    let lamports = 400 * LAMPORTS_PER_SOL;
+    protocol.open_trove(debt, lamports).await;
+
+    // 40% collateral ratio
+    protocol.set_sol_price(SOL_PRICE * 15 / 100).await;
+    protocol.update_config(|config| config.params.liquidation_grace_period = 0).await;
+
+    let receive_trove = receive_trove_instruction(&protocol, &admin.pubkey());
+    protocol.process(&[receive_trove], &[&admin]).await.unwrap();
+
+    let liquidate_trove = liquidate_trove_instruction(&protocol, &liquidator);
+    protocol.process(&[liquidate_trove], &[]).await.unwrap();
+
+    // the pool deposits are offset against two thirds of the debt and collateral
+    let seized_lamports = lamports * 2 / 3;
+    let trove_state: Trove = protocol.get_account(trove_address(&authority).0).await;
+    assert_eq!(trove_state.is_liquidated, false);
+    assert_eq!(trove_state.is_received, true);
+    assert_eq!(trove_state.amount_to_close, 5_000);
+    assert_eq!(trove_state.lamports_amount, lamports - seized_lamports);
+
+    let stability_pool: StabilityPool = protocol.get_account(pda(&[b"stabilitypool"]).0).await;
+    assert_eq!(stability_pool.total_deposits, 0);
+    assert_eq!(protocol.token_amount(pda(&[b"depositvault"]).0).await, 0);
+
+    let trove_manager: TroveManager = protocol.get_account(pda(&[b"trovemanager"]).0).await;
+    assert_eq!(trove_manager.total_debt, 5_000);
+    assert_eq!(trove_manager.get(&authority).unwrap().collateral, lamports - seized_lamports);
+
+    // the rest stays queued until the pool is refilled
+    let liquidate_trove = liquidate_trove_instruction(&protocol, &liquidator);
+    assert_nexfin_error(protocol.process(&[liquidate_trove], &[]).await, NexfinError::InsufficientLiquidity);
+}
+
+#[tokio::test]
+async fn test_borrow_on_an_open_trove_fails() {
+    let mut protocol = helper::setup().await;
+    let authority = protocol.authority();
+
+    protocol.open_trove(BORROW_AMOUNT, LAMPORTS).await;
+
+    let borrow = protocol.borrow_instruction(BORROW_AMOUNT, LAMPORTS);
+    assert_nexfin_error(protocol.process(&[borrow], &[&authority]).await, NexfinError::AlreadyInitialized);
+
+    // the open trove is left as it was
+    let trove_state: Trove = protocol.get_account(trove_address(&authority.pubkey()).0).await;
+    assert_eq!(trove_state.amount_to_close, BORROW_AMOUNT);
+    assert_eq!(trove_state.lamports_amount, LAMPORTS);
+    assert_eq!(protocol.token_amount(protocol.user_token).await, coins(BORROW_AMOUNT));
+}
+
+#[tokio::test]
+async fn test_reconcile_trove() {
+    let mut protocol = helper::setup().await;
+    let authority = protocol.authority().pubkey();
+
+    protocol.open_trove(BORROW_AMOUNT, LAMPORTS).await;
+
+    // a freshly opened trove holds its collateral along with the rent of the sol trove
+    let reconcile_trove = reconcile_trove_instruction(&protocol);
+    protocol.process(&[reconcile_trove], &[]).await.unwrap();
+
+    let sol_trove_lamports = protocol.lamports(sol_trove_address(&authority).0).await;
+    let mut trove_state: Trove = protocol.get_account(trove_address(&authority).0).await;
+    trove_state.lamports_amount = sol_trove_lamports + 1;
+    protocol.set_account(trove_address(&authority).0, &trove_state, Trove::LEN).await;
+
+    let reconcile_trove = reconcile_trove_instruction(&protocol);
+    assert_nexfin_error(protocol.process(&[reconcile_trove], &[]).await, NexfinError::ExpectedAmountMismatch);
 }
@@ -0,0 +1,123 @@
+#![cfg(feature = "test-bpf")]
+use anchor_lang::prelude::Pubkey;
+use nexfin_program::helpers::check_min_debt;
+use nexfin_program::params::{ProtocolParams, MIN_TROVE_DEBT};
+use nexfin_program::{SortedTrove, Trove, TroveManager};
+
+// $100 with the pyth exponent of -8
+const SOL_PRICE: u128 = 10_000_000_000;
+
+fn trove(authority: Pubkey, lamports_amount: u64, amount_to_close: u64) -> Trove {
+    Trove {
+        authority,
+        lamports_amount,
+        amount_to_close,
+        ..Trove::default()
+    }
+}
+
+#[test]
+fn test_totals_follow_the_troves() {
+    let mut trove_manager = TroveManager::default();
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+
+    trove_manager.update_trove(&trove(alice, 2_000_000_000, 100), 0).unwrap();
+    trove_manager.update_trove(&trove(bob, 1_000_000_000, 50), 0).unwrap();
+    assert_eq!(trove_manager.total_collateral, 3_000_000_000);
+    assert_eq!(trove_manager.total_debt, 150);
+    assert_eq!(trove_manager.get_total_collateral_ratio(SOL_PRICE).unwrap(), 200);
+
+    trove_manager.update_trove(&trove(alice, 1_000_000_000, 100), 0).unwrap();
+    assert_eq!(trove_manager.total_collateral, 2_000_000_000);
+
+    trove_manager.remove(&bob).unwrap();
+    assert_eq!(trove_manager.total_collateral, 1_000_000_000);
+    assert_eq!(trove_manager.total_debt, 100);
+
+    // repaid troves leave the list and the totals
+    trove_manager.update_trove(&trove(alice, 1_000_000_000, 0), 0).unwrap();
+    assert_eq!(trove_manager.total_collateral, 0);
+    assert_eq!(trove_manager.total_debt, 0);
+}
+
+#[test]
+fn test_recovery_mode() {
+    let params = ProtocolParams::default();
+    let mut trove_manager = TroveManager::default();
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+
+    trove_manager.update_trove(&trove(alice, 1_000_000_000, 60), 0).unwrap();
+    trove_manager.update_trove(&trove(bob, 1_000_000_000, 60), 0).unwrap();
+    assert!(!trove_manager.is_recovery_mode(SOL_PRICE, &params).unwrap());
+    assert_eq!(trove_manager.get_liquidation_ratio(SOL_PRICE, &params).unwrap(), params.liquidation_ratio);
+
+    // the price drop takes the system under the critical ratio
+    let sol_price = SOL_PRICE * 8 / 10;
+    assert!(trove_manager.is_recovery_mode(sol_price, &params).unwrap());
+    assert_eq!(trove_manager.get_liquidation_ratio(sol_price, &params).unwrap(), params.critical_collateral_ratio);
+
+    // borrowing lowers the total collateral ratio
+    assert!(trove_manager.check_recovery_mode(&trove(alice, 1_000_000_000, 70), 0, sol_price, &params).is_err());
+    // adding collateral and repaying raise it
+    assert!(trove_manager.check_recovery_mode(&trove(bob, 1_500_000_000, 60), 0, sol_price, &params).is_ok());
+    assert!(trove_manager.check_recovery_mode(&trove(bob, 1_000_000_000, 50), 0, sol_price, &params).is_ok());
+}
+
+#[test]
+fn test_token_collateral_is_counted() {
+    let params = ProtocolParams::default();
+    let mut trove_manager = TroveManager::default();
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+
+    // alice holds half of her collateral in tokens and ranks with bob
+    trove_manager.update_trove(&trove(alice, 500_000_000, 60), 500_000_000).unwrap();
+    trove_manager.update_trove(&trove(bob, 900_000_000, 60), 0).unwrap();
+    assert_eq!(trove_manager.total_collateral, 1_900_000_000);
+    assert_eq!(trove_manager.troves[0].authority, bob);
+    assert_eq!(trove_manager.get(&alice).unwrap().collateral, 1_000_000_000);
+
+    // withdrawing the tokens in recovery mode lowers the total collateral ratio
+    let sol_price = SOL_PRICE * 8 / 10;
+    assert!(trove_manager.is_recovery_mode(sol_price, &params).unwrap());
+    assert!(trove_manager.check_recovery_mode(&trove(alice, 500_000_000, 60), 0, sol_price, &params).is_err());
+    assert!(trove_manager.check_recovery_mode(&trove(alice, 500_000_000, 60), 600_000_000, sol_price, &params).is_ok());
+}
+
+#[test]
+fn test_drifted_totals_are_rejected() {
+    let params = ProtocolParams::default();
+    let alice = Pubkey::new_unique();
+    let mut trove_manager = TroveManager {
+        troves: vec![SortedTrove {
+            authority: alice,
+            collateral: 1_000_000_000,
+            debt: 60,
+            ..SortedTrove::default()
+        }],
+        total_collateral: 500_000_000,
+        total_debt: 100,
+        ..TroveManager::default()
+    };
+
+    // totals under the listed troves are not clamped to zero
+    assert!(trove_manager.check_recovery_mode(&trove(alice, 1_000_000_000, 60), 0, SOL_PRICE / 2, &params).is_err());
+    assert!(trove_manager.remove(&alice).is_err());
+}
+
+#[test]
+fn test_debt_floor() {
+    let params = ProtocolParams::default();
+    assert!(check_min_debt(0, &params).is_ok());
+    assert!(check_min_debt(params.min_borrow_amount, &params).is_ok());
+    assert!(check_min_debt(params.min_borrow_amount - 1, &params).is_err());
+
+    // the floor cannot be lowered back to dust troves
+    let params = ProtocolParams {
+        min_borrow_amount: MIN_TROVE_DEBT - 1,
+        ..ProtocolParams::default()
+    };
+    assert!(params.validate().is_err());
+}